@@ -35,12 +35,24 @@ use lightyear::{
 // use lightyear::{client::config::NetcodeConfig, prelude::{client::{Authentication, ClientTransport, IoConfig, NetConfig}, CompressionConfig, Key, SteamworksClient}, transport::LOCAL_SOCKET};
 // use menu::MenuPlugin;
 use clap::{Parser, Subcommand, ValueEnum};
-use networking::{server::ExampleServerPlugin, shared::SharedPlugin, NetworkingPlugin};
+use networking::{
+    host_migration::WorldSnapshot,
+    lobby_data::LobbySettings,
+    lobby_join::{split_launch_args, LaunchLobby},
+    moderation::ModerationCommand,
+    quantize::PacketCompression,
+    server::ExampleServerPlugin,
+    shared::SharedPlugin,
+    NetworkingPlugin,
+};
 use steamworks::{LobbyId, SingleClient};
 use sync_cell::SyncCell;
 use tracing::Level;
 
-use crate::{menu::MenuPlugin, networking::shared::FIXED_TIMESTEP_HZ};
+use crate::{
+    menu::MenuPlugin,
+    networking::{shared::FIXED_TIMESTEP_HZ, steam_backend::STEAM_APP_ID},
+};
 
 #[derive(Component)]
 pub struct GameCleanUp;
@@ -49,6 +61,8 @@ pub struct GameCleanUp;
 enum GameState {
     #[default]
     Menu,
+    /// Connected, waiting in the pre-match lobby
+    Lobby,
     Game,
 }
 
@@ -60,6 +74,10 @@ pub enum MultiplayerState {
     Server,
     Client,
     ClientSpawnServer,
+    /// Joining a friend's steam lobby, before connecting to its server
+    JoiningLobby,
+    /// Lost the host, waiting to reconnect to (or become) the new host
+    Migrating,
 }
 
 const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
@@ -71,11 +89,19 @@ struct ClientConfigInfo {
     port: String,
     seperate_mode: bool,
     steam_connect_to: Option<(SteamId, LobbyId)>,
+    /// true when a background server is available, so this client can take over as host
+    can_host: bool,
+    /// join servers without a ship, see `networking::spectator`
+    spectator: bool,
 }
 
 #[derive(Event)]
 pub enum ClientCommands {
     StartServer,
+    /// Start the server as the new host after a host migration
+    StartMigratedServer(WorldSnapshot),
+    /// Kick or ban players, issued by the host
+    Moderation(ModerationCommand),
     StopServer,
 }
 
@@ -105,11 +131,22 @@ fn custom_layer(_app: &mut App) -> Option<BoxedLayer> {
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Cli {
+    /// Defaults to `full`, which is also how Steam launches the game
     #[command(subcommand)]
-    pub mode: Mode,
+    pub mode: Option<Mode>,
+    /// Join servers as a spectator
+    #[arg(long)]
+    pub spectate: bool,
+    /// Game mode of a dedicated server, see `networking::game_mode`
+    #[arg(long)]
+    pub game_mode: Option<String>,
+    /// How the motion components are sent, must be the same on every peer, see
+    /// `networking::quantize`
+    #[arg(long, value_enum, default_value_t)]
+    pub compression: PacketCompression,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone, Copy)]
 pub enum Mode {
     Full,
     Client,
@@ -137,7 +174,7 @@ fn main() {
     server_app.insert_state(server_multiplayer_state);
 
     //  let steam_client: Arc<parking_lot::lock_api::RwLock<parking_lot::RawRwLock, SteamworksClient>> = Arc::new(RwLock::new(SteamworksClient::));
-    let (steam_result) = lightyear::prelude::steamworks::Client::init_app(480);
+    let (steam_result) = lightyear::prelude::steamworks::Client::init_app(STEAM_APP_ID);
 
     // let (steam, single_client) = lightyear::prelude::steamworks::SingleClient::init_app(480);
 
@@ -165,15 +202,19 @@ fn main() {
         tick_duration: Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ),
     });
 
+    // Steam adds `+connect_lobby <id>` when a friend joins us while the game isn't running
+    let (args, launch_lobby) = split_launch_args(std::env::args());
+    let cli = Cli::parse_from(args);
+    let mode = cli.mode.unwrap_or(Mode::Full);
+
     server_app.add_plugins(SharedPlugin {
         show_confirmed: false,
+        compression: cli.compression,
     });
 
     server_app.add_systems(OnEnter(GameState::Menu), despawn_screen::<GameCleanUp>);
 
-    let cli = Cli::parse();
-
-    match cli.mode {
+    match mode {
         Mode::Full => {
             //Client here does spawn server in background
 
@@ -199,8 +240,14 @@ fn main() {
                 steam: steam.clone(),
                 wrapped_single_client: wrapped_single_client.clone(),
             });
+            if let Some(game_mode) = cli.game_mode {
+                server_app.insert_resource(LobbySettings {
+                    mode: game_mode,
+                    ..default()
+                });
+            }
             info!("Started Server as main task (server is auto started)");
-            let game_state = GameState::Game;
+            let game_state = GameState::Lobby;
             server_app.insert_state(game_state);
             let server_multiplayer_state = MultiplayerState::Server;
             server_app.insert_state(server_multiplayer_state);
@@ -214,6 +261,8 @@ fn main() {
         port: "5000".to_string(),
         seperate_mode: false,
         steam_connect_to: None,
+        can_host: matches!(mode, Mode::Full),
+        spectator: cli.spectate,
     };
 
     let mut client_app = App::new();
@@ -248,6 +297,7 @@ fn main() {
         server_receive_commands: Some(server_commands_receive.clone()),
         steam: steam.clone(),
         wrapped_single_client: wrapped_single_client.clone(),
+        compression: cli.compression,
     });
 
     // if let Some((steamclient, steam_single)) = steam {
@@ -265,6 +315,7 @@ fn main() {
     // }
     client_app
         .insert_resource(client_config)
+        .insert_resource(LaunchLobby(launch_lobby))
         //Menu Setup
         .init_state::<GameState>()
         .init_state::<MultiplayerState>()
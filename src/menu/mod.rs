@@ -5,12 +5,20 @@ use bevy_simple_text_input::{
     TextInput, TextInputSubmitEvent, TextInputSystem, TextInputTextColor, TextInputTextFont,
     TextInputValue,
 };
-use lightyear::prelude::{steamworks::FriendFlags, SteamId, SteamworksClient};
+use lightyear::prelude::SteamId;
 use steamworks::LobbyId;
 
 // use crate::{networking::SteamworksResource, GameCleanUp, MultiplayerState};
 
-use crate::{networking::client::ClientStartupResources, MultiplayerState};
+use crate::{
+    networking::{
+        client::{ClientStartupResources, LastDisconnectReason},
+        lobby_data::LobbyInfo,
+        network_settings::NetworkSettings,
+        steam_backend::SteamPlatform,
+    },
+    MultiplayerState,
+};
 
 use super::{despawn_screen, GameState, TEXT_COLOR};
 
@@ -35,18 +43,44 @@ impl Plugin for MenuPlugin {
             // Systems to handle the main menu screen
             .add_systems(OnEnter(MenuState::Main), main_menu_setup)
             .add_systems(OnEnter(MenuState::JoinServer), join_server_menu_setup)
+            .add_systems(OnEnter(MenuState::Settings), settings_menu_setup)
             .add_systems(OnExit(MenuState::Main), despawn_screen::<OnMainMenuScreen>)
             // Systems to handle the settings menu screen
             .add_systems(
                 OnExit(MenuState::JoinServer),
                 despawn_screen::<OnJoinServerMenuScreen>,
             )
+            .add_systems(OnExit(MenuState::Settings), despawn_screen::<OnSettingsMenuScreen>)
             // Common systems to all screens that handles buttons behavior
             .add_systems(
                 Update,
-                (menu_action, button_system).run_if(in_state(GameState::Menu)),
+                (
+                    menu_action.run_if(in_state(GameState::Menu)),
+                    // the lobby screen reuses the menu buttons
+                    button_system
+                        .run_if(in_state(GameState::Menu).or(in_state(GameState::Lobby))),
+                ),
             )
-            .add_systems(Update, listener.after(TextInputSystem));
+            .add_systems(
+                Update,
+                update_spectate_toggle.run_if(in_state(MenuState::JoinServer)),
+            )
+            .add_systems(
+                Update,
+                update_network_settings_text.run_if(in_state(MenuState::Settings)),
+            )
+            .add_systems(
+                Update,
+                update_friend_lobbies.run_if(
+                    in_state(MenuState::JoinServer).and(resource_exists::<SteamPlatform>),
+                ),
+            )
+            .add_systems(
+                Update,
+                listener
+                    .after(TextInputSystem)
+                    .run_if(in_state(GameState::Menu)),
+            );
         
         app.add_systems(Update, client_accepts_join_game.run_if(
             in_state(MultiplayerState::None).and(in_state(GameState::Menu)),
@@ -59,6 +93,7 @@ impl Plugin for MenuPlugin {
 enum MenuState {
     Main,
     JoinServer,
+    Settings,
     #[default]
     Disabled,
 }
@@ -71,7 +106,19 @@ struct OnMainMenuScreen;
 #[derive(Component)]
 struct OnJoinServerMenuScreen;
 
-const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+// Tag component used to tag entities added on the network settings screen
+#[derive(Component)]
+struct OnSettingsMenuScreen;
+
+// Label of a network settings button, showing the current value
+#[derive(Component, Clone, Copy)]
+enum NetworkSettingText {
+    InputDelay,
+    MaxRollback,
+    InterpolationDelay,
+}
+
+pub(crate) const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::srgb(0.25, 0.25, 0.25);
 const HOVERED_PRESSED_BUTTON: Color = Color::srgb(0.25, 0.65, 0.25);
 const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
@@ -79,6 +126,24 @@ const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
 const BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
 const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
 
+// Lobby of the friend a join button connects to
+#[derive(Component)]
+struct FriendLobby(LobbyId);
+
+// Text next to a friend's join button describing their lobby
+#[derive(Component)]
+struct FriendLobbyInfoText(LobbyId);
+
+// Added to join buttons that can't be used: full server, other game version...
+#[derive(Component)]
+struct JoinBlocked;
+
+const BLOCKED_BUTTON: Color = Color::srgb(0.3, 0.1, 0.1);
+
+// Button joining servers as a spectator, selected while spectating is on
+#[derive(Component)]
+struct SpectateToggle;
+
 // Tag component used to mark which setting is currently selected
 #[derive(Component)]
 struct SelectedOption;
@@ -91,17 +156,31 @@ enum MenuButtonAction {
     MainMenu,
     JoinSteamFriend((SteamId, LobbyId)),
     JoinServer,
+    ToggleSpectate,
+    SettingsScreen,
+    CycleInputDelay,
+    CycleMaxRollback,
+    CycleInterpolationDelay,
     Quit,
 }
 
 // This system handles changing all buttons color based on mouse interaction
 fn button_system(
     mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor, Option<&SelectedOption>),
+        (
+            &Interaction,
+            &mut BackgroundColor,
+            Option<&SelectedOption>,
+            Has<JoinBlocked>,
+        ),
         (Changed<Interaction>, With<Button>),
     >,
 ) {
-    for (interaction, mut background_color, selected) in &mut interaction_query {
+    for (interaction, mut background_color, selected, blocked) in &mut interaction_query {
+        if blocked {
+            *background_color = BLOCKED_BUTTON.into();
+            continue;
+        }
         *background_color = match (*interaction, selected) {
             (Interaction::Pressed, _) | (Interaction::None, Some(_)) => PRESSED_BUTTON.into(),
             (Interaction::Hovered, Some(_)) => HOVERED_PRESSED_BUTTON.into(),
@@ -119,7 +198,7 @@ fn menu_setup(
     multiplayer_state.set(MultiplayerState::None);
 }
 
-fn main_menu_setup(mut commands: Commands) {
+fn main_menu_setup(mut commands: Commands, last_disconnect_reason: Res<LastDisconnectReason>) {
     // Common style for all buttons on the screen
     let button_node = Node {
         width: Val::Px(300.0),
@@ -178,6 +257,18 @@ fn main_menu_setup(mut commands: Commands) {
                         },
                     ));
 
+                    // Display why we got back to the menu, if the server told us
+                    if let Some(reason) = &last_disconnect_reason.0 {
+                        parent.spawn((
+                            Text::new(format!("Disconnected: {reason}")),
+                            TextFont {
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(1.0, 0.75, 0.3)),
+                        ));
+                    }
+
                     parent
                         .spawn((
                             Button,
@@ -210,6 +301,21 @@ fn main_menu_setup(mut commands: Commands) {
                         });
                         
 
+                    parent
+                        .spawn((
+                            Button,
+                            button_node.clone(),
+                            BackgroundColor(NORMAL_BUTTON),
+                            MenuButtonAction::SettingsScreen,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn((
+                                Text::new("Network Settings"),
+                                button_text_font.clone(),
+                                TextColor(TEXT_COLOR),
+                            ));
+                        });
+
                     parent
                         .spawn((
                             Button,
@@ -231,13 +337,14 @@ fn main_menu_setup(mut commands: Commands) {
 fn menu_action(
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
-        (Changed<Interaction>, With<Button>),
+        (Changed<Interaction>, With<Button>, Without<JoinBlocked>),
     >,
     mut app_exit_events: EventWriter<AppExit>,
     mut menu_state: ResMut<NextState<MenuState>>,
     mut game_state: ResMut<NextState<GameState>>,
     mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
     mut client_setup_info: ResMut<crate::ClientConfigInfo>,
+    mut network_settings: ResMut<NetworkSettings>,
 ) {
     for (interaction, menu_button_action) in &interaction_query {
         if *interaction == Interaction::Pressed {
@@ -251,28 +358,41 @@ fn menu_action(
                 MenuButtonAction::MainMenu => {
                     menu_state.set(MenuState::Main);
                 }
+                MenuButtonAction::ToggleSpectate => {
+                    client_setup_info.spectator = !client_setup_info.spectator;
+                }
+                MenuButtonAction::SettingsScreen => {
+                    menu_state.set(MenuState::Settings);
+                }
+                MenuButtonAction::CycleInputDelay => network_settings.cycle_input_delay(),
+                MenuButtonAction::CycleMaxRollback => network_settings.cycle_max_rollback(),
+                MenuButtonAction::CycleInterpolationDelay => {
+                    network_settings.cycle_interpolation_delay()
+                }
                 MenuButtonAction::JoinSteamFriend((id, lobby_id)) => {
                     client_setup_info.seperate_mode = false;
                     client_setup_info.steam_connect_to = Some((*id, *lobby_id));
 
-                    game_state.set(GameState::Game);
+                    game_state.set(GameState::Lobby);
                     menu_state.set(MenuState::Disabled);
-                    multiplayer_state.set(MultiplayerState::Client)
+                    multiplayer_state.set(MultiplayerState::JoiningLobby)
                 }
                 MenuButtonAction::JoinServer => {
                     if Ipv4Addr::from_str(&client_setup_info.address).is_ok() {
                         // client_setup_info.address = text_input_value.single().0.clone();
                         client_setup_info.seperate_mode = false;
                         client_setup_info.steam_connect_to = None;
-                        game_state.set(GameState::Game);
+                        game_state.set(GameState::Lobby);
                         menu_state.set(MenuState::Disabled);
                         multiplayer_state.set(MultiplayerState::Client)
                     }
                 }
                 MenuButtonAction::SeperateAndJoin => {
+                    // the host always plays
                     client_setup_info.seperate_mode = true;
+                    client_setup_info.spectator = false;
                     client_setup_info.steam_connect_to = None;
-                    game_state.set(GameState::Game);
+                    game_state.set(GameState::Lobby);
                     menu_state.set(MenuState::Disabled);
                     multiplayer_state.set(MultiplayerState::ClientSpawnServer);
                     // multiplayer_state.set(MultiplayerState::Client);
@@ -283,6 +403,51 @@ fn menu_action(
 }
 
 
+// Keep the spectate button selected while spectating is on
+fn update_spectate_toggle(
+    mut commands: Commands,
+    client_setup_info: Res<crate::ClientConfigInfo>,
+    toggle: Query<(Entity, Has<SelectedOption>), With<SpectateToggle>>,
+) {
+    for (entity, selected) in &toggle {
+        if client_setup_info.spectator && !selected {
+            commands.entity(entity).insert(SelectedOption);
+        } else if !client_setup_info.spectator && selected {
+            commands.entity(entity).remove::<SelectedOption>();
+        }
+    }
+}
+
+// Show the current value of each network setting on its button
+fn update_network_settings_text(
+    network_settings: Res<NetworkSettings>,
+    mut texts: Query<(&NetworkSettingText, &mut Text)>,
+) {
+    if !network_settings.is_changed() {
+        return;
+    }
+    for (setting, mut text) in &mut texts {
+        text.0 = setting.describe(&network_settings);
+    }
+}
+
+impl NetworkSettingText {
+    fn describe(&self, network_settings: &NetworkSettings) -> String {
+        match self {
+            NetworkSettingText::InputDelay => {
+                format!("Input delay: {}", network_settings.input_delay.describe())
+            }
+            NetworkSettingText::MaxRollback => {
+                format!("Max rollback: {} ticks", network_settings.max_rollback_ticks)
+            }
+            NetworkSettingText::InterpolationDelay => format!(
+                "Interpolation: {}ms",
+                network_settings.interpolation_delay.as_millis()
+            ),
+        }
+    }
+}
+
 //Non-menu actions that only happen in the menu
 
 fn client_accepts_join_game(
@@ -293,45 +458,83 @@ fn client_accepts_join_game(
     mut client_setup_info: ResMut<crate::ClientConfigInfo>,) {
 
     if let Some(temp) = client_startup.steam_accept_join_game_request.clone() {
-        if let Some(guard) = temp.try_lock() {
-            if let Some(steam_id) = *guard {
+        if let Some(mut guard) = temp.try_lock() {
+            if let Some((lobby_id, steam_id)) = guard.take() {
 
                 client_setup_info.seperate_mode = false;
-                client_setup_info.steam_connect_to = Some((steam_id, LobbyId::from_raw(0)));
+                client_setup_info.steam_connect_to = Some((steam_id, lobby_id));
 
-                game_state.set(GameState::Game);
+                game_state.set(GameState::Lobby);
                 menu_state.set(MenuState::Disabled);
-                multiplayer_state.set(MultiplayerState::Client);
-                client_startup.steam_accept_join_game_request = None;
+                multiplayer_state.set(MultiplayerState::JoiningLobby);
             }
         }
     }
 
 }
 
-fn join_server_menu_setup(mut commands: Commands, mut steamworks: Option<ResMut<SteamworksClient>>) {//mut steamworks: ResMut<SteamworksResource>
-    let mut steam_friends = Vec::new();
-
-    if let Some(steamworks) = steamworks.as_mut() {
-         for friend in steamworks
-        .0.friends().get_friends(FriendFlags::all()).iter()
+/// Show the lobby data published by each friend's server, and block joining when we can't
+fn update_friend_lobbies(
+    steam: Res<SteamPlatform>,
+    buttons: Query<(Entity, &FriendLobby, Has<JoinBlocked>)>,
+    mut info_texts: Query<(&FriendLobbyInfoText, &mut Text, &mut TextColor)>,
+    mut commands: Commands,
+) {
+    for (info_text, mut text, mut color) in info_texts.iter_mut() {
+        let Some(info) = LobbyInfo::read(&*steam.0, info_text.0) else {
+            continue;
+        };
+        let blocker = info.join_blocker();
+        let description = match &blocker {
+            Some(blocker) => format!("{} - {blocker}", info.describe()),
+            None => info.describe(),
+        };
+        if text.0 != description {
+            text.0 = description;
+            color.0 = if blocker.is_some() {
+                Color::srgb(1.0, 0.75, 0.3)
+            } else {
+                TEXT_COLOR
+            };
+        }
+        for (button, _, blocked) in buttons
+            .iter()
+            .filter(|(_, lobby, _)| lobby.0 == info_text.0)
         {
-            if let Some(game_info) = friend.game_played() {
-                if game_info.game.app_id().0 == 480 && game_info.lobby.raw() != 0 {
-                    steam_friends.push((friend.name(), friend.id(), game_info.lobby));
-                    println!(
-                        "{:?} {:?} {:?} {:?} {:?} {:?}",
-                        friend.name(),
-                        friend.id(),
-                        game_info.game_address,
-                        game_info.game_port,
-                        game_info.query_port,
-                        game_info.lobby
-                    )
+            match (blocker.is_some(), blocked) {
+                (true, false) => {
+                    commands
+                        .entity(button)
+                        .insert((JoinBlocked, BackgroundColor(BLOCKED_BUTTON)));
                 }
+                (false, true) => {
+                    commands
+                        .entity(button)
+                        .remove::<JoinBlocked>()
+                        .insert(BackgroundColor(NORMAL_BUTTON));
+                }
+                _ => {}
             }
         }
-    } 
+    }
+}
+
+fn join_server_menu_setup(
+    mut commands: Commands,
+    steam: Option<Res<SteamPlatform>>,
+    client_setup_info: Res<crate::ClientConfigInfo>,
+) {
+    let steam_friends = steam
+        .as_ref()
+        .map(|steam| steam.friends_in_game())
+        .unwrap_or_default();
+    for friend in steam_friends.iter() {
+        info!("{:?} {:?} {:?}", friend.name, friend.id, friend.lobby);
+        // shown by update_friend_lobbies once it arrives
+        if let Some(steam) = steam.as_ref() {
+            steam.request_lobby_data(friend.lobby);
+        }
+    }
   
 
     // Common style for all buttons on the screen
@@ -379,19 +582,43 @@ fn join_server_menu_setup(mut commands: Commands, mut steamworks: Option<ResMut<
                     })),
                 ))
                 .with_children(|parent| {
-                    for (friend, id, lobby) in steam_friends {
+                    for friend in steam_friends {
                         parent
-                            .spawn((
-                                Button,
-                                button_node.clone(),
-                                BackgroundColor(NORMAL_BUTTON),
-                                MenuButtonAction::JoinSteamFriend((id, lobby)),
-                            ))
+                            .spawn(Node {
+                                align_items: AlignItems::Center,
+                                ..default()
+                            })
                             .with_children(|parent| {
+                                parent
+                                    .spawn((
+                                        Button,
+                                        button_node.clone(),
+                                        BackgroundColor(NORMAL_BUTTON),
+                                        MenuButtonAction::JoinSteamFriend((
+                                            friend.id,
+                                            friend.lobby,
+                                        )),
+                                        FriendLobby(friend.lobby),
+                                    ))
+                                    .with_children(|parent| {
+                                        parent.spawn((
+                                            Text::new(friend.name),
+                                            button_text_font.clone(),
+                                            TextColor(TEXT_COLOR),
+                                        ));
+                                    });
                                 parent.spawn((
-                                    Text::new(friend),
-                                    button_text_font.clone(),
+                                    Text::new("Fetching lobby info..."),
+                                    TextFont {
+                                        font_size: 20.0,
+                                        ..default()
+                                    },
                                     TextColor(TEXT_COLOR),
+                                    Node {
+                                        width: Val::Px(420.0),
+                                        ..default()
+                                    },
+                                    FriendLobbyInfoText(friend.lobby),
                                 ));
                             });
                     }
@@ -429,6 +656,24 @@ fn join_server_menu_setup(mut commands: Commands, mut steamworks: Option<ResMut<
                             ));
                         });
 
+                    let mut spectate = parent.spawn((
+                        Button,
+                        button_node.clone(),
+                        BackgroundColor(NORMAL_BUTTON),
+                        MenuButtonAction::ToggleSpectate,
+                        SpectateToggle,
+                    ));
+                    if client_setup_info.spectator {
+                        spectate.insert(SelectedOption);
+                    }
+                    spectate.with_children(|parent| {
+                        parent.spawn((
+                            Text::new("Spectate"),
+                            button_text_font.clone(),
+                            TextColor(TEXT_COLOR),
+                        ));
+                    });
+
                     parent
                         .spawn((
                             Button,
@@ -460,9 +705,218 @@ fn listener(
         if Ipv4Addr::from_str(&client_setup_info.address).is_ok() {
             client_setup_info.seperate_mode = false;
             client_setup_info.steam_connect_to = None;
-            game_state.set(GameState::Game);
+            game_state.set(GameState::Lobby);
             menu_state.set(MenuState::Disabled);
             multiplayer_state.set(MultiplayerState::Client)
         }
     }
 }
+
+fn settings_menu_setup(mut commands: Commands, network_settings: Res<NetworkSettings>) {
+    let button_node = Node {
+        width: Val::Px(460.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(15.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_font = TextFont {
+        font_size: 28.0,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::Srgba(Srgba {
+                red: 36.0 / 255.0,
+                green: 22.0 / 255.0,
+                blue: 39.0 / 255.0,
+                alpha: 255.0 / 255.0,
+            })),
+            OnSettingsMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Network Settings"),
+                TextFont {
+                    font_size: 50.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR),
+                Node {
+                    margin: UiRect::all(Val::Px(30.0)),
+                    ..default()
+                },
+            ));
+
+            for (action, setting) in [
+                (MenuButtonAction::CycleInputDelay, NetworkSettingText::InputDelay),
+                (MenuButtonAction::CycleMaxRollback, NetworkSettingText::MaxRollback),
+                (
+                    MenuButtonAction::CycleInterpolationDelay,
+                    NetworkSettingText::InterpolationDelay,
+                ),
+            ] {
+                parent
+                    .spawn((
+                        Button,
+                        button_node.clone(),
+                        BackgroundColor(NORMAL_BUTTON),
+                        action,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Text::new(setting.describe(&network_settings)),
+                            button_text_font.clone(),
+                            TextColor(TEXT_COLOR),
+                            setting,
+                        ));
+                    });
+            }
+
+            parent.spawn((
+                Text::new("Applied the next time you connect"),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR.with_alpha(0.7)),
+            ));
+
+            parent
+                .spawn((
+                    Button,
+                    button_node,
+                    BackgroundColor(NORMAL_BUTTON),
+                    MenuButtonAction::MainMenu,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new("Back"),
+                        button_text_font,
+                        TextColor(TEXT_COLOR),
+                    ));
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+    use crate::networking::client::setup_steam_callbacks;
+    use crate::networking::lobby_join::{LaunchLobby, LobbyJoinPlugin};
+    use crate::networking::shared::GAME_VERSION;
+    use crate::networking::steam_backend::{MockSteamBackend, SteamBackend};
+    use crate::ClientConfigInfo;
+
+    fn friend() -> SteamId {
+        SteamId::from_raw(200)
+    }
+
+    fn friend_lobby() -> LobbyId {
+        LobbyId::from_raw(7)
+    }
+
+    /// The menu and the lobby join flow, on top of a mock Steam with a friend hosting a server
+    fn invite_app(launch_lobby: Option<LobbyId>) -> (App, Arc<MockSteamBackend>) {
+        let steam = Arc::new(MockSteamBackend::new(SteamId::from_raw(100)));
+        let server = friend().raw().to_string();
+        steam.add_friend(
+            "Friend",
+            friend(),
+            friend_lobby(),
+            &[
+                ("server", server.as_str()),
+                ("version", GAME_VERSION),
+                ("players", "1"),
+                ("max_players", "8"),
+            ],
+        );
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin));
+        app.init_state::<GameState>();
+        app.init_state::<MultiplayerState>();
+        app.init_state::<MenuState>();
+        app.init_resource::<ClientConfigInfo>();
+        app.init_resource::<LastDisconnectReason>();
+        app.insert_resource(LaunchLobby(launch_lobby));
+        app.insert_resource(SteamPlatform(steam.clone()));
+        app.insert_resource(ClientStartupResources {
+            client_crossbeam: None,
+            client_sender_commands: None,
+            steam_accept_join_game_request: None,
+        });
+        app.add_systems(OnEnter(GameState::Menu), setup_steam_callbacks);
+        app.add_systems(
+            Update,
+            client_accepts_join_game
+                .run_if(in_state(MultiplayerState::None).and(in_state(GameState::Menu))),
+        );
+        app.add_plugins(LobbyJoinPlugin);
+        app.update();
+        (app, steam)
+    }
+
+    fn run_frames(app: &mut App, steam: &MockSteamBackend, frames: usize) {
+        for _ in 0..frames {
+            steam.run_callbacks();
+            app.update();
+        }
+    }
+
+    fn assert_connecting_to_friend(app: &App) {
+        assert_eq!(
+            *app.world().resource::<State<MultiplayerState>>().get(),
+            MultiplayerState::Client
+        );
+        assert_eq!(
+            app.world().resource::<ClientConfigInfo>().steam_connect_to,
+            Some((friend(), friend_lobby()))
+        );
+    }
+
+    #[test]
+    fn accepted_invite_joins_the_lobby_and_connects() {
+        let (mut app, steam) = invite_app(None);
+        run_frames(&mut app, &steam, 2);
+        assert_eq!(
+            *app.world().resource::<State<MultiplayerState>>().get(),
+            MultiplayerState::None
+        );
+
+        steam.request_join(friend());
+        run_frames(&mut app, &steam, 4);
+
+        assert_connecting_to_friend(&app);
+        assert_eq!(*app.world().resource::<State<GameState>>().get(), GameState::Lobby);
+        assert!(steam
+            .lobby(friend_lobby())
+            .unwrap()
+            .members
+            .contains(&SteamId::from_raw(100)));
+    }
+
+    #[test]
+    fn launch_lobby_is_joined_once_the_menu_is_up() {
+        let (mut app, steam) = invite_app(Some(friend_lobby()));
+        run_frames(&mut app, &steam, 4);
+
+        assert_connecting_to_friend(&app);
+        assert_eq!(app.world().resource::<LaunchLobby>().0, None);
+    }
+}
@@ -0,0 +1,482 @@
+//! In-game text chat.
+//!
+//! Clients send a [`ChatMessage`] to the server, which fills in the sender's `Player.nickname`,
+//! rate limits each link and relays the message to everyone. The server also logs every message,
+//! so a dedicated server (`Mode::Server`) shows the chat in its console and can answer with `say <text>`.
+//!
+//! In game, press Enter to type and Enter again to send (Escape cancels). Older lines fade out
+//! after a few seconds and come back while typing.
+//!
+//! The host can moderate from the chat box:
+//! `/kick <player> [reason]`, `/ban <player> [30m|2h|7d|perm] [reason]` and `/unban <target>`,
+//! where `<player>` is a nickname or a `netcode:`, `steam:` or `ip:` target.
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_simple_text_input::{
+    TextInput, TextInputInactive, TextInputSubmitEvent, TextInputSystem, TextInputTextColor,
+    TextInputTextFont, TextInputValue,
+};
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::client::ClientStartupResources;
+use crate::networking::moderation::{
+    parse_ban_duration, reason_from, BanTarget, ModerationCommand,
+};
+use crate::networking::protocol::{
+    ChatChannel, ChatMessage, Player, PlayerActions, Roster, RosterEntry,
+};
+use crate::networking::shared::peer_label;
+use crate::{ClientCommands, ClientConfigInfo, GameCleanUp, GameState, TEXT_COLOR};
+
+/// Longer messages are truncated by the server
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 200;
+/// Name used for messages sent by the server itself
+const SERVER_SENDER: &str = "Server";
+
+/// Each link can send this many messages in a burst...
+const CHAT_BURST: f32 = 5.0;
+/// ...and then gets one more every second
+const CHAT_REFILL_PER_SEC: f32 = 1.0;
+
+/// Number of lines kept in the chat box
+const CHAT_HISTORY: usize = 10;
+/// Lines stay fully visible for this long, then fade out
+const CHAT_LINE_VISIBLE: Duration = Duration::from_secs(8);
+const CHAT_LINE_FADE: Duration = Duration::from_secs(2);
+
+/// Written by the server console to send a message as [`SERVER_SENDER`]
+#[derive(Event, Clone, Debug)]
+pub struct ChatAnnouncement(pub String);
+
+/// Token bucket limiting how fast a link can chat
+#[derive(Component)]
+struct ChatRateLimit {
+    tokens: f32,
+}
+
+impl Default for ChatRateLimit {
+    fn default() -> Self {
+        Self { tokens: CHAT_BURST }
+    }
+}
+
+pub(crate) struct ChatServerPlugin;
+
+impl Plugin for ChatServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ChatAnnouncement>();
+        app.add_observer(add_chat_rate_limit);
+        app.add_systems(Update, (relay_chat_messages, send_chat_announcements));
+    }
+}
+
+fn add_chat_rate_limit(trigger: Trigger<OnAdd, Connected>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert(ChatRateLimit::default());
+}
+
+fn relay_chat_messages(
+    time: Res<Time>,
+    mut links: Query<
+        (
+            Entity,
+            &RemoteId,
+            &mut ChatRateLimit,
+            &mut MessageReceiver<ChatMessage>,
+        ),
+        With<ClientOf>,
+    >,
+    mut senders: Query<&mut MessageSender<ChatMessage>, (With<ClientOf>, With<Connected>)>,
+    players: Query<(&Player, &ControlledBy)>,
+) {
+    let mut relayed = Vec::new();
+    for (entity, remote_id, mut rate_limit, mut receiver) in links.iter_mut() {
+        rate_limit.tokens =
+            (rate_limit.tokens + time.delta_secs() * CHAT_REFILL_PER_SEC).min(CHAT_BURST);
+        for message in receiver.receive() {
+            let text: String = message
+                .text
+                .trim()
+                .chars()
+                .take(MAX_CHAT_MESSAGE_LENGTH)
+                .collect();
+            if text.is_empty() {
+                continue;
+            }
+            if rate_limit.tokens < 1.0 {
+                // only the spammer is told, nobody else sees the message
+                if let Ok(mut sender) = senders.get_mut(entity) {
+                    sender.send::<ChatChannel>(ChatMessage {
+                        sender: SERVER_SENDER.to_string(),
+                        text: "You are sending messages too fast".to_string(),
+                    });
+                }
+                continue;
+            }
+            rate_limit.tokens -= 1.0;
+            // never trust the name sent by the client
+            let sender = players
+                .iter()
+                .find(|(_, controlled)| controlled.owner == entity)
+                .map_or_else(
+                    || peer_label(remote_id.0),
+                    |(player, _)| player.nickname.clone(),
+                );
+            relayed.push(ChatMessage { sender, text });
+        }
+    }
+
+    for message in relayed {
+        info!("[chat] {}: {}", message.sender, message.text);
+        for mut sender in senders.iter_mut() {
+            sender.send::<ChatChannel>(message.clone());
+        }
+    }
+}
+
+fn send_chat_announcements(
+    mut announcements: EventReader<ChatAnnouncement>,
+    mut senders: Query<&mut MessageSender<ChatMessage>, (With<ClientOf>, With<Connected>)>,
+) {
+    for announcement in announcements.read() {
+        info!("[chat] {SERVER_SENDER}: {}", announcement.0);
+        for mut sender in senders.iter_mut() {
+            sender.send::<ChatChannel>(ChatMessage {
+                sender: SERVER_SENDER.to_string(),
+                text: announcement.0.clone(),
+            });
+        }
+    }
+}
+
+/// Client-side chat box state
+#[derive(Resource, Default)]
+pub struct ChatState {
+    /// true while the chat input has focus. Player inputs and Escape are ignored meanwhile.
+    pub typing: bool,
+}
+
+pub(crate) struct ChatClientPlugin;
+
+impl Plugin for ChatClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatState>();
+        app.add_systems(OnEnter(GameState::Lobby), spawn_chat_box);
+        app.add_systems(OnEnter(GameState::Game), spawn_chat_box);
+        app.add_systems(
+            Update,
+            (
+                receive_chat_messages,
+                open_chat_input,
+                submit_chat_input,
+                fade_chat_lines,
+                block_player_input_while_typing,
+            )
+                .chain()
+                .after(TextInputSystem)
+                .run_if(in_state(GameState::Lobby).or(in_state(GameState::Game))),
+        );
+    }
+}
+
+#[derive(Component)]
+struct ChatLog;
+
+#[derive(Component)]
+struct ChatLine {
+    received_at: Duration,
+}
+
+#[derive(Component)]
+struct ChatInput;
+
+fn spawn_chat_box(
+    mut commands: Commands,
+    mut chat_state: ResMut<ChatState>,
+    chat_log: Query<(), With<ChatLog>>,
+) {
+    chat_state.typing = false;
+    // the chat box stays up when going from the lobby to the game
+    if !chat_log.is_empty() {
+        return;
+    }
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(12.0),
+                bottom: Val::Px(12.0),
+                width: Val::Px(420.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            // above the lobby screen
+            GlobalZIndex(1),
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                ChatLog,
+            ));
+            parent.spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+                Visibility::Hidden,
+                TextInput,
+                TextInputInactive(true),
+                TextInputTextFont(TextFont {
+                    font_size: 16.0,
+                    ..default()
+                }),
+                TextInputTextColor(TextColor(TEXT_COLOR)),
+                ChatInput,
+            ));
+        });
+}
+
+fn receive_chat_messages(
+    time: Res<Time>,
+    mut client: Query<&mut MessageReceiver<ChatMessage>, With<Client>>,
+    chat_log: Single<(Entity, Option<&Children>), With<ChatLog>>,
+    mut commands: Commands,
+) {
+    let (chat_log, lines) = *chat_log;
+    // children are kept in insertion order, so the oldest lines come first
+    let mut count = lines.map_or(0, |lines| lines.len());
+    let mut oldest = lines.into_iter().flatten();
+    for mut receiver in client.iter_mut() {
+        for message in receiver.receive() {
+            info!("[chat] {}: {}", message.sender, message.text);
+            if count >= CHAT_HISTORY {
+                if let Some(line) = oldest.next() {
+                    commands.entity(*line).despawn();
+                }
+            } else {
+                count += 1;
+            }
+            push_chat_line(
+                &mut commands,
+                chat_log,
+                format!("{}: {}", message.sender, message.text),
+                TEXT_COLOR,
+                time.elapsed(),
+            );
+        }
+    }
+}
+
+fn push_chat_line(
+    commands: &mut Commands,
+    chat_log: Entity,
+    text: String,
+    color: Color,
+    now: Duration,
+) {
+    commands.entity(chat_log).with_child((
+        Text::new(text),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        TextColor(color),
+        ChatLine { received_at: now },
+    ));
+}
+
+fn open_chat_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut chat_state: ResMut<ChatState>,
+    mut input: Single<(&mut TextInputInactive, &mut Visibility), With<ChatInput>>,
+) {
+    if chat_state.typing || !keys.just_pressed(KeyCode::Enter) {
+        return;
+    }
+    chat_state.typing = true;
+    let (inactive, visibility) = &mut *input;
+    inactive.0 = false;
+    **visibility = Visibility::Inherited;
+}
+
+pub(crate) fn submit_chat_input(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut events: EventReader<TextInputSubmitEvent>,
+    mut chat_state: ResMut<ChatState>,
+    mut input: Single<
+        (
+            Entity,
+            &mut TextInputInactive,
+            &mut Visibility,
+            &mut TextInputValue,
+        ),
+        With<ChatInput>,
+    >,
+    mut client: Query<&mut MessageSender<ChatMessage>, (With<Client>, With<Connected>)>,
+    rosters: Query<&Roster>,
+    client_config: Res<ClientConfigInfo>,
+    client_startup: Res<ClientStartupResources>,
+    time: Res<Time>,
+    chat_log: Single<Entity, With<ChatLog>>,
+    mut commands: Commands,
+) {
+    if !chat_state.typing {
+        return;
+    }
+    let (entity, inactive, visibility, value) = &mut *input;
+    let mut close = keys.just_pressed(KeyCode::Escape);
+    if close {
+        value.0.clear();
+        // this Escape only closes the chat box, `esc_to_disconnect` must not see it
+        keys.clear_just_pressed(KeyCode::Escape);
+    }
+    for event in events.read().filter(|event| event.entity == *entity) {
+        close = true;
+        let text = event.value.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(command) = text.strip_prefix('/') {
+            let reply = if client_config.seperate_mode {
+                let players: Vec<_> = rosters.iter().flat_map(|roster| &roster.0).collect();
+                run_host_command(command, &players, &client_startup)
+            } else {
+                Err("Only the host can use chat commands".to_string())
+            };
+            if let Err(reply) = reply {
+                push_chat_line(
+                    &mut commands,
+                    *chat_log,
+                    reply,
+                    Color::srgb(1.0, 0.75, 0.3),
+                    time.elapsed(),
+                );
+            }
+            continue;
+        }
+        for mut sender in client.iter_mut() {
+            sender.send::<ChatChannel>(ChatMessage {
+                // filled in by the server
+                sender: String::new(),
+                text: text.to_string(),
+            });
+        }
+    }
+    if close {
+        chat_state.typing = false;
+        inactive.0 = true;
+        **visibility = Visibility::Hidden;
+    }
+}
+
+/// Turn `/kick`, `/ban` and `/unban` into moderation commands for our own server
+fn run_host_command(
+    command: &str,
+    players: &[&RosterEntry],
+    client_startup: &ClientStartupResources,
+) -> Result<(), String> {
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let moderation_command = match name {
+        "kick" => {
+            let (target, rest) = parse_chat_target(args, players)?;
+            ModerationCommand::Kick {
+                target,
+                reason: reason_from(rest.split_whitespace()),
+            }
+        }
+        "ban" => {
+            let (target, rest) = parse_chat_target(args, players)?;
+            let mut words = rest.split_whitespace().peekable();
+            let duration = match words.peek().map(|w| parse_ban_duration(w)) {
+                Some(Some(duration)) => {
+                    words.next();
+                    duration
+                }
+                _ => None,
+            };
+            ModerationCommand::Ban {
+                target,
+                duration,
+                reason: reason_from(words),
+            }
+        }
+        "unban" => ModerationCommand::Unban {
+            target: args.trim().parse()?,
+        },
+        _ => return Err(format!("Unknown command /{name}")),
+    };
+    let Some(sender) = &client_startup.client_sender_commands else {
+        return Err("No server to send the command to".to_string());
+    };
+    sender
+        .send(ClientCommands::Moderation(moderation_command))
+        .map_err(|e| e.to_string())
+}
+
+/// Read a player nickname (which can contain spaces) or an explicit target, and return the rest
+fn parse_chat_target<'a>(
+    args: &'a str,
+    players: &[&RosterEntry],
+) -> Result<(BanTarget, &'a str), String> {
+    let args = args.trim_start();
+    // the whole nickname must match, "Al" isn't "Alice"
+    if let Some(player) = players
+        .iter()
+        .filter(|player| {
+            args.strip_prefix(player.nickname.as_str())
+                .is_some_and(|rest| rest.chars().next().is_none_or(char::is_whitespace))
+        })
+        .max_by_key(|player| player.nickname.len())
+    {
+        return Ok((
+            BanTarget::Peer(player.client_id),
+            &args[player.nickname.len()..],
+        ));
+    }
+    let (target, rest) = args.split_once(' ').unwrap_or((args, ""));
+    target
+        .parse()
+        .map(|target| (target, rest))
+        .map_err(|_| format!("No player named {target}"))
+}
+
+fn fade_chat_lines(
+    time: Res<Time>,
+    chat_state: Res<ChatState>,
+    mut lines: Query<(&ChatLine, &mut TextColor)>,
+) {
+    for (line, mut color) in lines.iter_mut() {
+        let age = time.elapsed().saturating_sub(line.received_at);
+        let alpha = if chat_state.typing || age < CHAT_LINE_VISIBLE {
+            1.0
+        } else {
+            1.0 - (age - CHAT_LINE_VISIBLE).as_secs_f32() / CHAT_LINE_FADE.as_secs_f32()
+        };
+        color.0.set_alpha(alpha.max(0.0));
+    }
+}
+
+/// Don't move or shoot while typing WASD and spaces
+fn block_player_input_while_typing(
+    chat_state: Res<ChatState>,
+    mut player: Query<&mut ActionState<PlayerActions>, (With<Predicted>, With<Controlled>)>,
+) {
+    for mut action_state in player.iter_mut() {
+        if chat_state.typing {
+            action_state.disable_all();
+        } else if action_state.disabled() {
+            action_state.enable_all();
+        }
+    }
+}
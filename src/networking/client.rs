@@ -1,11 +1,25 @@
 //! The client plugin.
+use crate::networking::chat::{submit_chat_input, ChatClientPlugin, ChatState};
+use crate::networking::game_mode::GameModeClientPlugin;
+use crate::networking::handshake::HandshakeClientPlugin;
+use crate::networking::host_migration::{HostMigration, HostMigrationClientPlugin};
+use crate::networking::lobby_join::{LaunchLobby, LobbyJoinPlugin};
+use crate::networking::lobby_room::LobbyRoomClientPlugin;
+use crate::networking::match_state::MatchStateClientPlugin;
+use crate::networking::moderation::ModerationClientPlugin;
+use crate::networking::network_settings::NetworkSettings;
+use crate::networking::power_ups::PowerUpsClientPlugin;
 use crate::networking::protocol::{
     BallMarker, BulletHitEvent, BulletMarker, PhysicsBundle, Player, PlayerActions,
 };
-use crate::networking::server::SteamSingleClient;
 use crate::networking::shared::*;
+use crate::networking::shutdown_notice::ShutdownNoticeClientPlugin;
+use crate::networking::soccer::SoccerClientPlugin;
+use crate::networking::spectator::SpectatorClientPlugin;
+use crate::networking::steam_backend::{SteamPlatform, SteamworksBackend};
+use crate::networking::weapons::{PiercedShips, WeaponsClientPlugin};
 use crate::{ClientCommands, ClientConfigInfo, GameState, MultiplayerState, ServerCommands};
-use avian2d::prelude::Collider;
+use avian2d::prelude::{Collider, Sensor};
 use bevy::prelude::*;
 use core::net::Ipv4Addr;
 use core::net::{IpAddr, SocketAddr};
@@ -18,15 +32,21 @@ use parking_lot::Mutex;
 use std::net::SocketAddrV4;
 use std::sync::Arc;
 use std::time::Duration;
-use steamworks::GameLobbyJoinRequested;
+use steamworks::LobbyId;
 
 #[derive(Resource)]
 pub struct ClientStartupResources {
     pub client_crossbeam: Option<CrossbeamIo>,
     pub client_sender_commands: Option<crossbeam_channel::Sender<ClientCommands>>,
-    pub steam_accept_join_game_request:
-        Option<Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<SteamId>>>>,
+    /// Lobby and friend from the last invite accepted through the steam overlay
+    pub steam_accept_join_game_request: Option<
+        Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<(LobbyId, SteamId)>>>,
+    >,
 }
+/// Why we were last disconnected from a server, shown on the main menu
+#[derive(Resource, Default)]
+pub struct LastDisconnectReason(pub Option<String>);
+
 pub struct ExampleClientPlugin {
     pub client_crossbeam: Option<CrossbeamIo>,
     pub client_sender_commands: Option<crossbeam_channel::Sender<ClientCommands>>,
@@ -47,6 +67,23 @@ impl Plugin for ExampleClientPlugin {
             steam_accept_join_game_request: None,
         });
 
+        app.init_resource::<LastDisconnectReason>();
+        app.init_resource::<NetworkSettings>();
+
+        app.add_plugins(HostMigrationClientPlugin);
+        app.add_plugins(ShutdownNoticeClientPlugin);
+        app.add_plugins(ModerationClientPlugin);
+        app.add_plugins(HandshakeClientPlugin);
+        app.add_plugins(SpectatorClientPlugin);
+        app.add_plugins(ChatClientPlugin);
+        app.add_plugins(LobbyJoinPlugin);
+        app.add_plugins(LobbyRoomClientPlugin);
+        app.add_plugins(MatchStateClientPlugin);
+        app.add_plugins(SoccerClientPlugin);
+        app.add_plugins(WeaponsClientPlugin);
+        app.add_plugins(PowerUpsClientPlugin);
+        app.add_plugins(GameModeClientPlugin);
+
         app.add_systems(OnEnter(GameState::Menu), setup_steam_callbacks);
         app.add_crossbeam_event(self.server_receive_commands.clone().unwrap());
 
@@ -61,10 +98,10 @@ impl Plugin for ExampleClientPlugin {
 
             app.insert_resource(SteamworksClient(steam.clone()));
 
-            let resource = SteamSingleClient {
-                steam: wrapped_single_client.clone(),
-            };
-            app.insert_resource(resource);
+            app.insert_resource(SteamPlatform::new(SteamworksBackend::new(
+                steam.clone(),
+                wrapped_single_client.clone(),
+            )));
             app.add_systems(PreUpdate, steam_callbacks);
         }
 
@@ -80,12 +117,19 @@ impl Plugin for ExampleClientPlugin {
         );
         app.add_systems(
             Update,
-            esc_to_disconnect.run_if(in_state(MultiplayerState::Client)),
+            esc_to_disconnect
+                .after(submit_chat_input)
+                .run_if(in_state(MultiplayerState::Client)),
         );
 
         app.add_systems(PreUpdate, client_stop_server);
 
-        app.add_systems(FixedUpdate, (player_movement, shared_player_firing).chain());
+        app.add_systems(
+            FixedUpdate,
+            (player_movement, shared_player_firing)
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        );
         app.add_observer(add_ball_physics);
         app.add_observer(add_bullet_physics);
         app.add_observer(handle_new_player);
@@ -99,7 +143,7 @@ impl Plugin for ExampleClientPlugin {
     }
 }
 
-fn steam_callbacks(steam: ResMut<SteamSingleClient>, client_config: Res<ClientConfigInfo>) {
+fn steam_callbacks(steam: Res<SteamPlatform>, client_config: Res<ClientConfigInfo>) {
     // This system is responsible for running the Steamworks callbacks
     // It should be run every frame to ensure that the Steamworks API works correctly
     if client_config.seperate_mode {
@@ -107,7 +151,7 @@ fn steam_callbacks(steam: ResMut<SteamSingleClient>, client_config: Res<ClientCo
         return;
     }
 
-    steam.steam.lock().run_callbacks();
+    steam.run_callbacks();
 }
 
 pub fn esc_to_disconnect(
@@ -117,32 +161,48 @@ pub fn esc_to_disconnect(
     mut game_state: ResMut<NextState<GameState>>,
     client_q: Query<Entity, With<Client>>,
     client_config: Res<ClientConfigInfo>,
+    mut host_migration: ResMut<HostMigration>,
+    chat_state: Res<ChatState>,
     mut commands: Commands,
 ) {
     if let Ok(client) = client_q.single_inner() {
-        if keys.just_pressed(KeyCode::Escape) {
+        // Escape closes the chat box first
+        if keys.just_pressed(KeyCode::Escape) && !chat_state.typing {
             if MultiplayerState::Client == *multiplayer_state.get() {
+                // we are leaving on purpose, so don't try to follow the host migration plan
+                host_migration.plan = None;
                 commands.trigger_targets(Disconnect, client);
             }
         }
     }
 }
 
-fn setup_steam_callbacks(
+pub(crate) fn setup_steam_callbacks(
     mut commands: Commands,
     mut client_startup: ResMut<ClientStartupResources>,
-    steam_works: Option<Res<SteamworksClient>>,
+    steam: Option<Res<SteamPlatform>>,
+    mut launch_lobby: ResMut<LaunchLobby>,
 ) -> Result {
-    if let Some(steam_work) = steam_works {
-        let shared_data: Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<SteamId>>> =
-            Arc::new(Mutex::new(None));
+    if client_startup.steam_accept_join_game_request.is_some() {
+        // already registered the first time we got to the menu
+        return Ok(());
+    }
+    if let Some(steam) = steam {
+        let shared_data: Arc<
+            parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<(LobbyId, SteamId)>>,
+        > = Arc::new(Mutex::new(None));
         let cloned_data = shared_data.clone();
 
-        let _lobby_join_callback =
-            steam_work.register_callback(move |p: GameLobbyJoinRequested| {
-                // The closure takes a GameLobbyJoinRequested struct as an argument
-                shared_data.lock().replace(p.friend_steam_id);
-            });
+        // started by steam to join a friend: handled like an invite accepted in game.
+        // We don't know who the friend is yet, the lobby tells us where to connect.
+        if let Some(lobby) = launch_lobby.0.take() {
+            info!("Joining lobby {lobby:?} from the command line");
+            shared_data.lock().replace((lobby, SteamId::from_raw(0)));
+        }
+
+        steam.on_join_requested(Box::new(move |lobby, friend_steam_id| {
+            shared_data.lock().replace((lobby, friend_steam_id));
+        }));
 
         client_startup.steam_accept_join_game_request = Some(cloned_data);
     }
@@ -150,10 +210,18 @@ fn setup_steam_callbacks(
     Ok(())
 }
 
-fn client_start_server(mut client_startup: ResMut<ClientStartupResources>) {
+fn client_start_server(
+    mut client_startup: ResMut<ClientStartupResources>,
+    mut host_migration: ResMut<HostMigration>,
+) {
     // We need to send a command to the server to start the server
     if let Some(sender) = &client_startup.client_sender_commands {
-        let _result = sender.send(ClientCommands::StartServer);
+        // after a host migration, the new server starts from our last view of the world
+        let command = match host_migration.pending_seed.take() {
+            Some(snapshot) => ClientCommands::StartMigratedServer(snapshot),
+            None => ClientCommands::StartServer,
+        };
+        let _result = sender.send(command);
     } else {
         error!("client_sender_commands is None, cannot send StartServer command");
     }
@@ -199,9 +267,11 @@ fn client_connect(
     mut commands: Commands,
     client_q: Query<Entity, With<Client>>,
     client_config: Res<ClientConfigInfo>,
+    network_settings: Res<NetworkSettings>,
     mut client_startup: ResMut<ClientStartupResources>,
-    steam_works: Option<Res<SteamworksClient>>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
 ) -> Result {
+    last_disconnect_reason.0 = None;
     // let client = client_q.single_inner().ok().unwrap();
 
     // commands.entity(client).try_remove::<CrossbeamIo>()
@@ -221,15 +291,17 @@ fn client_connect(
             Name::new("Client"),
             Client::default(),
             ReplicationReceiver::default(),
-            PredictionManager::default(),
+            network_settings.prediction_manager(),
+            network_settings.input_timeline(),
             InterpolationManager::default(),
+            network_settings.interpolation_timeline(),
         ))
         .id();
 
     if client_config.seperate_mode {
         let auth = Authentication::Manual {
             server_addr: SERVER_ADDR,
-            client_id: 1,
+            client_id: HOST_CLIENT_ID,
             private_key: Key::default(),
             protocol_id: 0,
         };
@@ -270,7 +342,7 @@ fn client_connect(
             SteamClientIo {
                 target: ConnectTarget::Peer {
                     steam_id: client_config.steam_connect_to.unwrap().0,
-                    virtual_port: 4001,
+                    virtual_port: STEAM_VIRTUAL_PORT,
                 },
                 config: SessionConfig {
                     timeout_initial: Duration::from_secs(10),
@@ -284,21 +356,7 @@ fn client_connect(
             Link::new(None), // This is the link to the server, which will be established when the client connects
         ));
 
-        // if let Some(steam_work) = steam_works {
-        //     steam_work.matchmaking().join_lobby(client_config.steam_connect_to.unwrap().1,
-        //     |result: Result<LobbyId, ()>| {
-        //             match result {
-        //                 Ok(lobby_id) => {
-        //                     println!("{:?}", lobby_id);
-        //                     // Do something with the LobbyId, like joining it, setting metadata, etc.
-        //                 }
-        //                 Err(e) => {
-        //                     eprintln!("Error joining lobby: {:?}", e);
-        //                 }
-        //             }
-        //         },);
-        // }
-
+        // the lobby itself was joined beforehand, in lobby_join
         commands.trigger_targets(Connect, client);
         info!("Using Steam for client connection");
 
@@ -314,9 +372,17 @@ fn client_connect(
 
     let server_addr = SocketAddr::V4(SocketAddrV4::new(address, port));
 
+    // any id except the one reserved for the host's crossbeam link
+    let client_id = loop {
+        let id = rand::random::<u64>();
+        if id != HOST_CLIENT_ID {
+            break id;
+        }
+    };
+
     let auth = Authentication::Manual {
         server_addr,
-        client_id: rand::random::<u64>(),
+        client_id,
         private_key: Key::default(),
         protocol_id: 0,
     };
@@ -344,10 +410,17 @@ fn client_connect(
 pub fn clean_up_game_on_client_disconnect(
     client_q: Query<Entity, With<Disconnected>>,
     client_startup: Res<ClientStartupResources>,
+    host_migration: Res<HostMigration>,
     mut game_state: ResMut<NextState<GameState>>,
     mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
 ) {
     if let Some(_client) = client_q.single_inner().ok() {
+        if host_migration.should_migrate() {
+            info!("Lost connection to the host, starting host migration");
+            multiplayer_state.set(MultiplayerState::Migrating);
+            return;
+        }
+
         // info!("Client disconnected, cleaning up game state");
         game_state.set(GameState::Menu);
         multiplayer_state.set(MultiplayerState::None);
@@ -385,12 +458,19 @@ fn add_ball_physics(
 fn add_bullet_physics(
     trigger: Trigger<OnAdd, BulletMarker>,
     mut commands: Commands,
-    bullet_query: Query<(), (With<Predicted>, Without<Collider>)>,
+    bullet_query: Query<&BulletMarker, (With<Predicted>, Without<Collider>)>,
 ) {
     let entity = trigger.target();
-    if let Ok(()) = bullet_query.get(entity) {
+    if let Ok(bullet) = bullet_query.get(entity) {
         info!("Adding physics to a replicated bullet: {entity:?}");
-        commands.entity(entity).insert(PhysicsBundle::bullet());
+        commands
+            .entity(entity)
+            .insert(PhysicsBundle::bullet(bullet.size));
+        if bullet.pierce > 0 {
+            commands
+                .entity(entity)
+                .insert((Sensor, PiercedShips::default()));
+        }
     }
 }
 
@@ -417,6 +497,7 @@ fn handle_new_player(
                 (PlayerActions::Left, KeyCode::KeyA),
                 (PlayerActions::Right, KeyCode::KeyD),
                 (PlayerActions::Fire, KeyCode::Space),
+                (PlayerActions::SwitchWeapon, KeyCode::KeyQ),
             ]));
         } else {
             info!("Remote player replicated to us: {entity:?} {player:?}");
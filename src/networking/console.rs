@@ -0,0 +1,134 @@
+//! Commands typed in the terminal of a dedicated server (`Mode::Server`).
+//!
+//! - `status`, `kick <target> [reason]`, `ban <target> [30m|2h|7d|perm] [reason]`,
+//!   `unban <target>` and `bans` are turned into [`ModerationCommand`]s
+//! - `say <text>` sends a chat message to every player
+//! - `bandwidth` prints what lightyear sent on every link against its bandwidth cap
+//! - `quantization` prints the bytes saved by quantizing the motion components
+//! - `rewind <ms>` sets how far back bullet hits are checked
+use std::io::BufRead;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, TryRecvError};
+
+use crate::networking::chat::ChatAnnouncement;
+use crate::networking::lag_compensation::LagCompensationSettings;
+use crate::networking::moderation::{
+    parse_ban_duration, reason_from, BanList, BanTarget, ModerationCommand,
+};
+use crate::networking::priority::PrintBandwidthStats;
+use crate::networking::protocol::Player;
+use crate::networking::quantize::PrintQuantizationReport;
+use crate::networking::shared::peer_label;
+
+pub(crate) struct ConsoleServerPlugin;
+
+impl Plugin for ConsoleServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConsoleLines(spawn_console_reader()));
+        app.add_systems(Update, read_console_commands);
+    }
+}
+
+#[derive(Resource)]
+struct ConsoleLines(Receiver<String>);
+
+fn spawn_console_reader() -> Receiver<String> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn read_console_commands(
+    console: Res<ConsoleLines>,
+    ban_list: Res<BanList>,
+    players: Query<&Player>,
+    mut moderation_commands: EventWriter<ModerationCommand>,
+    mut chat_announcements: EventWriter<ChatAnnouncement>,
+    mut print_bandwidth_stats: EventWriter<PrintBandwidthStats>,
+    mut print_quantization_report: EventWriter<PrintQuantizationReport>,
+    mut lag_compensation: ResMut<LagCompensationSettings>,
+) {
+    loop {
+        let line = match console.0.try_recv() {
+            Ok(line) => line,
+            // nothing typed since last frame, or stdin was closed
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+        };
+        let mut words = line.split_whitespace();
+        let result = match words.next() {
+            None => continue,
+            Some("status") => {
+                for player in players.iter() {
+                    info!("{:<28} {}", peer_label(player.client_id), player.nickname);
+                }
+                Ok(())
+            }
+            Some("bans") => {
+                for ban in ban_list.bans.iter() {
+                    info!("{}: {}", ban.target, ban.describe_now());
+                }
+                Ok(())
+            }
+            Some("kick") => parse_target(words.next()).map(|target| {
+                moderation_commands.write(ModerationCommand::Kick {
+                    target,
+                    reason: reason_from(words),
+                });
+            }),
+            Some("ban") => parse_target(words.next()).map(|target| {
+                let mut words = words.peekable();
+                let duration = match words.peek().map(|w| parse_ban_duration(w)) {
+                    Some(Some(duration)) => {
+                        words.next();
+                        duration
+                    }
+                    _ => None,
+                };
+                moderation_commands.write(ModerationCommand::Ban {
+                    target,
+                    duration,
+                    reason: reason_from(words),
+                });
+            }),
+            Some("unban") => parse_target(words.next()).map(|target| {
+                moderation_commands.write(ModerationCommand::Unban { target });
+            }),
+            Some("bandwidth") => {
+                print_bandwidth_stats.write(PrintBandwidthStats);
+                Ok(())
+            }
+            Some("quantization") => {
+                print_quantization_report.write(PrintQuantizationReport);
+                Ok(())
+            }
+            Some("rewind") => match words.next().map(str::parse::<u64>) {
+                Some(Ok(millis)) => {
+                    lag_compensation.max_rewind = Duration::from_millis(millis);
+                    info!("Bullet hits are rewound by at most {millis}ms");
+                    Ok(())
+                }
+                _ => Err("usage: rewind <ms>".to_string()),
+            },
+            Some("say") => {
+                chat_announcements.write(ChatAnnouncement(words.collect::<Vec<_>>().join(" ")));
+                Ok(())
+            }
+            Some(other) => Err(format!("unknown command {other}")),
+        };
+        if let Err(e) = result {
+            warn!("{e}");
+        }
+    }
+}
+
+fn parse_target(word: Option<&str>) -> Result<BanTarget, String> {
+    word.ok_or_else(|| "missing target".to_string())?.parse()
+}
@@ -0,0 +1,373 @@
+//! Game modes.
+//!
+//! A [`GameMode`] owns the rules of a match: what the server spawns when the match starts, where
+//! ships start, how a bullet hit changes the scores and when a round is won. Modes are registered
+//! with [`GameModeApp::add_game_mode`] in both the client and the server app, so the lobby can list
+//! them, but only the server runs them.
+//!
+//! The host picks the mode in the lobby (`--game-mode <name>` for a dedicated server), and it is
+//! looked up by name in [`GameModes`] from `LobbySettings::mode`. Rounds are timed by
+//! `match_state`, which triggers [`ResetRound`] between them to reset the scores, the ships and
+//! the mode's entities.
+//!
+//! [`Deathmatch`] is the default mode: six gold balls, +1 for a hit and -1 for being hit, and the
+//! first player to reach the score limit wins the round. [`Soccer`] is played in teams, see
+//! `soccer`.
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity, Position, Rotation};
+use bevy::prelude::*;
+
+use crate::networking::host_migration::MigrationSeed;
+use crate::networking::lobby_data::LobbySettings;
+use crate::networking::protocol::{
+    BulletHitEvent, FriendlyFire, Invulnerable, MatchPhase, MatchRoom, MatchState, Player, Score,
+    Shield, Team, TeamGoals,
+};
+use crate::networking::server::spawn_ball;
+use crate::networking::shared::process_collisions;
+use crate::networking::soccer::Soccer;
+use crate::networking::teams::{is_friendly, standings, PlayerScore};
+use crate::GameState;
+
+/// Rules of a match, run by the server
+pub trait GameMode: Send + Sync + 'static {
+    /// Shown in the lobby and the steam lobby data
+    fn name(&self) -> &'static str;
+
+    /// Spawn the mode's static server-authoritative entities (goals...), when the match or a new
+    /// round starts. They need an [`ArenaEntity`] marker.
+    fn spawn_arena(&self, _commands: &mut Commands) {}
+
+    /// Spawn the balls at their starting positions, when the match or a new round starts. After a
+    /// host migration they are put back where they were instead.
+    fn spawn_balls(&self, commands: &mut Commands);
+
+    /// Where the player with the spawn slot `index` starts (see `Player::spawn_slot`), on the side
+    /// of its `team` in modes played in teams
+    fn spawn_position(&self, index: usize, team: Option<Team>) -> Vec2;
+
+    /// How a bullet hit changes the scores of the shooter and the victim
+    fn score_hit(&self, hit: &BulletHitEvent) -> ScoreChange;
+
+    /// Who won the round, if it is over before the time runs out. `goals` is only there in modes
+    /// that count [`TeamGoals`].
+    fn round_winner(
+        &self,
+        players: &[PlayerScore],
+        goals: Option<&TeamGoals>,
+        score_limit: i32,
+    ) -> Option<String>;
+
+    /// The mode is played in teams, the server turns them on when the match starts
+    fn teams(&self) -> bool {
+        false
+    }
+}
+
+/// Spawned by [`GameMode::spawn_arena`] and [`GameMode::spawn_balls`], despawned when a new round
+/// starts
+#[derive(Component, Debug)]
+pub struct ArenaEntity;
+
+/// Added to the `Score` of the players involved in a hit
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScoreChange {
+    pub shooter: i32,
+    pub victim: i32,
+}
+
+/// Every registered game mode, the first one is the default
+#[derive(Resource, Clone, Default)]
+pub struct GameModes(Vec<Arc<dyn GameMode>>);
+
+impl GameModes {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|mode| mode.name()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn GameMode>> {
+        self.0.iter().find(|mode| mode.name() == name).cloned()
+    }
+
+    /// The mode called `name`, or the default one
+    pub fn get_or_default(&self, name: &str) -> Arc<dyn GameMode> {
+        self.get(name).unwrap_or_else(|| {
+            warn!("Unknown game mode {name}, using {}", self.0[0].name());
+            self.0[0].clone()
+        })
+    }
+}
+
+pub trait GameModeApp {
+    fn add_game_mode(&mut self, mode: impl GameMode) -> &mut Self;
+}
+
+impl GameModeApp for App {
+    fn add_game_mode(&mut self, mode: impl GameMode) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_init::<GameModes>()
+            .0
+            .push(Arc::new(mode));
+        self
+    }
+}
+
+/// Registers the game modes, in both apps
+pub(crate) struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_game_mode(Deathmatch);
+        app.add_game_mode(Soccer);
+    }
+}
+
+/// The mode of the current match, set by the server when it starts and by the clients from the
+/// replicated `MatchRoom`
+#[derive(Resource, Clone)]
+pub struct ActiveGameMode(pub Arc<dyn GameMode>);
+
+/// Triggered by the server to get ready for a new round
+#[derive(Event, Debug)]
+pub struct ResetRound;
+
+pub(crate) struct GameModeServerPlugin;
+
+impl Plugin for GameModeServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), start_match);
+        app.add_observer(reset_round);
+        app.add_systems(
+            Update,
+            move_to_team_side.run_if(resource_exists::<ActiveGameMode>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            handle_hit_event
+                .run_if(on_event::<BulletHitEvent>)
+                .after(process_collisions)
+                .run_if(in_state(GameState::Game).and(resource_exists::<ActiveGameMode>)),
+        );
+    }
+}
+
+pub(crate) struct GameModeClientPlugin;
+
+impl Plugin for GameModeClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, follow_room_mode);
+    }
+}
+
+/// Clients predict the respawns, which need the mode's spawn positions
+fn follow_room_mode(
+    modes: Res<GameModes>,
+    room: Query<&MatchRoom, Changed<MatchRoom>>,
+    mut commands: Commands,
+) {
+    if let Ok(room) = room.single() {
+        commands.insert_resource(ActiveGameMode(modes.get_or_default(&room.mode)));
+    }
+}
+
+fn start_match(
+    mut commands: Commands,
+    modes: Res<GameModes>,
+    settings: Res<LobbySettings>,
+    seed: Option<Res<MigrationSeed>>,
+    mut players: Query<(&Player, &mut Score, Option<&Team>, ResetShip)>,
+    mut room: Query<&mut MatchRoom>,
+) {
+    let mode = modes.get_or_default(&settings.mode);
+    info!("Starting a {} match", mode.name());
+    commands.insert_resource(ActiveGameMode(mode.clone()));
+    if let Ok(mut room) = room.single_mut() {
+        if mode.teams() && !room.teams {
+            // `teams` balances the players when this changes
+            room.teams = true;
+        }
+    }
+
+    mode.spawn_arena(&mut commands);
+    // after a host migration, put the balls back where the previous host last had them
+    // and let the players keep their scores and positions
+    if let Some(seed) = seed {
+        for ball in seed.0.balls.iter() {
+            spawn_ball(&mut commands, ball.position, ball.radius);
+        }
+        if seed.0.balls.is_empty() {
+            mode.spawn_balls(&mut commands);
+        }
+        return;
+    }
+    mode.spawn_balls(&mut commands);
+    reset_players(mode.as_ref(), &mut players);
+}
+
+/// Server will manipulate scores when a bullet collides with a player.
+/// the `Score` component is a simple replication. Score is fully server-authoritative.
+fn handle_hit_event(
+    mode: Res<ActiveGameMode>,
+    match_state: Query<&MatchState>,
+    room: Single<&MatchRoom>,
+    mut events: EventReader<BulletHitEvent>,
+    mut player_q: Query<(
+        &Player,
+        &mut Score,
+        Option<&Team>,
+        Has<Invulnerable>,
+        Has<Shield>,
+    )>,
+) {
+    // only live rounds count
+    if !match_state
+        .single()
+        .is_ok_and(|state| state.phase == MatchPhase::Live)
+    {
+        events.clear();
+        return;
+    }
+    for ev in events.read() {
+        // did they hit a player?
+        let Some(victim) = ev.victim_client_id else {
+            continue;
+        };
+        let team_of = |client_id| {
+            player_q
+                .iter()
+                .find(|(player, ..)| player.client_id == client_id)
+                .and_then(|(_, _, team, ..)| team.copied())
+        };
+        // ships that just respawned or have a shield can't be hit
+        if player_q.iter().any(|(player, .., invulnerable, shielded)| {
+            player.client_id == victim && (invulnerable || shielded)
+        }) {
+            continue;
+        }
+        let friendly = is_friendly(team_of(ev.bullet_owner).as_ref(), team_of(victim).as_ref());
+        let change = match room.friendly_fire {
+            _ if !friendly => mode.0.score_hit(ev),
+            FriendlyFire::Off => continue,
+            FriendlyFire::Penalty => ScoreChange {
+                shooter: -1,
+                victim: 0,
+            },
+            FriendlyFire::Full => mode.0.score_hit(ev),
+        };
+        for (player, mut score, ..) in player_q.iter_mut() {
+            if player.client_id == victim {
+                score.0 += change.victim;
+            } else if player.client_id == ev.bullet_owner {
+                score.0 += change.shooter;
+            }
+        }
+    }
+}
+
+fn reset_round(
+    _trigger: Trigger<ResetRound>,
+    mode: Res<ActiveGameMode>,
+    mut players: Query<(&Player, &mut Score, Option<&Team>, ResetShip)>,
+    arena: Query<Entity, With<ArenaEntity>>,
+    mut commands: Commands,
+) {
+    for entity in arena.iter() {
+        commands.entity(entity).despawn();
+    }
+    mode.0.spawn_arena(&mut commands);
+    mode.0.spawn_balls(&mut commands);
+    reset_players(mode.0.as_ref(), &mut players);
+}
+
+/// Teams are balanced when the match starts and between rounds, after the ships were reset:
+/// send the players who changed team to their new side
+fn move_to_team_side(
+    mode: Res<ActiveGameMode>,
+    mut players: Query<(Ref<Player>, &Team, ResetShip), Changed<Team>>,
+) {
+    for (player, team, (mut position, mut rotation, mut velocity, mut angular)) in
+        players.iter_mut()
+    {
+        // new ships were spawned with their team, at their spawn position or the one they had
+        // before a host migration
+        if player.is_added() {
+            continue;
+        }
+        position.0 = mode.0.spawn_position(player.spawn_slot, Some(*team));
+        *rotation = Rotation::default();
+        velocity.0 = Vec2::ZERO;
+        angular.0 = 0.0;
+    }
+}
+
+type ResetShip = (
+    &'static mut Position,
+    &'static mut Rotation,
+    &'static mut LinearVelocity,
+    &'static mut AngularVelocity,
+);
+
+/// Scores back to zero and ships back to their spawn positions
+fn reset_players(
+    mode: &dyn GameMode,
+    players: &mut Query<(&Player, &mut Score, Option<&Team>, ResetShip)>,
+) {
+    for (player, mut score, team, (mut position, mut rotation, mut velocity, mut angular)) in
+        players.iter_mut()
+    {
+        score.0 = 0;
+        position.0 = mode.spawn_position(player.spawn_slot, team.copied());
+        *rotation = Rotation::default();
+        velocity.0 = Vec2::ZERO;
+        angular.0 = 0.0;
+    }
+}
+
+/// Free for all: shoot the other ships
+pub struct Deathmatch;
+
+impl Deathmatch {
+    const NUM_BALLS: usize = 6;
+}
+
+impl GameMode for Deathmatch {
+    fn name(&self) -> &'static str {
+        "Deathmatch"
+    }
+
+    fn spawn_balls(&self, commands: &mut Commands) {
+        // the balls are server-authoritative
+        for i in 0..Self::NUM_BALLS {
+            let radius = 10.0 + i as f32 * 4.0;
+            let angle: f32 = i as f32 * (TAU / Self::NUM_BALLS as f32);
+            let pos = Vec2::new(125.0 * angle.cos(), 125.0 * angle.sin());
+            spawn_ball(commands, pos, radius);
+        }
+    }
+
+    fn spawn_position(&self, index: usize, _team: Option<Team>) -> Vec2 {
+        let angle: f32 = index as f32 * 5.0;
+        Vec2::new(200.0 * angle.cos(), 200.0 * angle.sin())
+    }
+
+    fn score_hit(&self, _hit: &BulletHitEvent) -> ScoreChange {
+        ScoreChange {
+            shooter: 1,
+            victim: -1,
+        }
+    }
+
+    fn round_winner(
+        &self,
+        players: &[PlayerScore],
+        goals: Option<&TeamGoals>,
+        score_limit: i32,
+    ) -> Option<String> {
+        standings(players, goals)
+            .into_iter()
+            .find(|(_, score)| *score >= score_limit)
+            .map(|(name, _)| name)
+    }
+}
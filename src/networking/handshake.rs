@@ -0,0 +1,294 @@
+//! Protocol version handshake.
+//!
+//! Builds that registered different components, inputs or channels can't understand each other,
+//! but lightyear would still connect them. Each build computes a [`ProtocolFingerprint`] while
+//! `ProtocolPlugin` registers the protocol: every registration records the type, in order, with
+//! the names and types of its fields found through reflection. Clients send the fingerprint in a
+//! [`ProtocolHello`] as soon as they are connected.
+//!
+//! The hello and the `KickNotice` never change and are registered first, on their own
+//! `HandshakeChannel`, so any two builds can read them. When the hello doesn't match, the link is
+//! kicked with a "version mismatch (server X, you Y)" reason that the client shows on the main
+//! menu. A client whose hello can't even be read (a build from before the hello was frozen) is
+//! kicked as an "incompatible version" once `HELLO_TIMEOUT` is over.
+//!
+//! Once the hello matched, the server answers with a [`HelloAccepted`] and the client sends what
+//! the server needs to know about its session in a [`SessionInfo`]. The server only starts
+//! replicating to a link, and spawns its ship, after that (see [`HandshakeAccepted`]).
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::reflect::{TypeInfo, Typed, VariantInfo};
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::host_migration::SessionKey;
+use crate::networking::moderation::PendingKick;
+use crate::networking::network_settings::NetworkSettings;
+use crate::networking::protocol::{
+    ControlChannel, HandshakeChannel, HelloAccepted, ProtocolHello, SessionInfo,
+};
+use crate::networking::shared::peer_label;
+use crate::ClientConfigInfo;
+
+/// How long a new link has to send its [`ProtocolHello`], then its [`SessionInfo`]
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How deep nested field types are recorded, enough for our types and it stops on recursive ones
+const MAX_LAYOUT_DEPTH: usize = 4;
+
+/// Hash of everything registered in `ProtocolPlugin`, plus the crate version
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolFingerprint {
+    pub version: &'static str,
+    pub hash: u64,
+}
+
+impl ProtocolFingerprint {
+    pub fn new(version: &'static str) -> Self {
+        let mut fingerprint = Self {
+            version,
+            hash: FNV_OFFSET,
+        };
+        fingerprint.feed(version);
+        fingerprint
+    }
+
+    /// Record a registered component, message, channel or input, with its field layout
+    pub fn record<T: Typed>(&mut self) {
+        self.feed_type(T::type_info(), 0);
+    }
+
+    /// Record something that changes the wire format without changing the types, like an encoding
+    pub fn record_tag(&mut self, tag: &str) {
+        self.feed(tag);
+    }
+
+    /// The type path, then the fields of structs and the variants of enums, recursively
+    fn feed_type(&mut self, info: &TypeInfo, depth: usize) {
+        self.feed(info.type_path());
+        if depth == MAX_LAYOUT_DEPTH {
+            return;
+        }
+        match info {
+            TypeInfo::Struct(info) => {
+                for field in info.iter() {
+                    self.feed(field.name());
+                    self.feed_field(field.type_path(), field.type_info(), depth);
+                }
+            }
+            TypeInfo::TupleStruct(info) => {
+                for field in info.iter() {
+                    self.feed_field(field.type_path(), field.type_info(), depth);
+                }
+            }
+            TypeInfo::Enum(info) => {
+                for variant in info.iter() {
+                    self.feed(variant.name());
+                    match variant {
+                        VariantInfo::Struct(variant) => {
+                            for field in variant.iter() {
+                                self.feed(field.name());
+                                self.feed_field(field.type_path(), field.type_info(), depth);
+                            }
+                        }
+                        VariantInfo::Tuple(variant) => {
+                            for field in variant.iter() {
+                                self.feed_field(field.type_path(), field.type_info(), depth);
+                            }
+                        }
+                        VariantInfo::Unit(_) => {}
+                    }
+                }
+            }
+            // lists, maps and opaque types like numbers are covered by their type path
+            _ => {}
+        }
+    }
+
+    fn feed_field(&mut self, type_path: &str, info: Option<&'static TypeInfo>, depth: usize) {
+        match info {
+            Some(info) => self.feed_type(info, depth + 1),
+            None => self.feed(type_path),
+        }
+    }
+
+    pub fn hello(&self) -> ProtocolHello {
+        ProtocolHello {
+            version: self.version.to_string(),
+            fingerprint: self.hash,
+        }
+    }
+
+    pub fn matches(&self, hello: &ProtocolHello) -> bool {
+        hello.version == self.version && hello.fingerprint == self.hash
+    }
+
+    /// Reason given to a client whose hello doesn't match ours
+    pub fn mismatch_reason(&self, hello: &ProtocolHello) -> String {
+        // two builds of the same version can still differ, show the hash then
+        let (server, client) = if hello.version == self.version {
+            (
+                format!("{} ({:016x})", self.version, self.hash),
+                format!("{} ({:016x})", hello.version, hello.fingerprint),
+            )
+        } else {
+            (self.version.to_string(), hello.version.clone())
+        };
+        format!("version mismatch (server {server}, you {client})")
+    }
+
+    /// FNV-1a, so the hash doesn't depend on the std hasher of the build
+    fn feed(&mut self, text: &str) {
+        for byte in text.bytes().chain([0]) {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Triggered on a link once its [`ProtocolHello`] matched and its [`SessionInfo`] arrived, the
+/// server can spawn its player
+#[derive(Event, Debug)]
+pub struct HandshakeAccepted {
+    pub spectator: bool,
+    pub interpolation_delay: Duration,
+    pub session_key: u64,
+}
+
+/// Added to new links until their [`SessionInfo`] arrives
+#[derive(Component)]
+struct Handshaking {
+    timer: Timer,
+    /// the hello matched, we are waiting for the session info
+    hello_accepted: bool,
+}
+
+pub(crate) struct HandshakeServerPlugin;
+
+impl Plugin for HandshakeServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(await_hello);
+        app.add_systems(
+            Update,
+            (verify_protocol_hello, receive_session_info).chain(),
+        );
+    }
+}
+
+fn await_hello(
+    trigger: Trigger<OnAdd, Connected>,
+    links: Query<(), With<ClientOf>>,
+    mut commands: Commands,
+) {
+    if links.get(trigger.target()).is_ok() {
+        commands.entity(trigger.target()).insert(Handshaking {
+            timer: Timer::new(HELLO_TIMEOUT, TimerMode::Once),
+            hello_accepted: false,
+        });
+    }
+}
+
+fn verify_protocol_hello(
+    time: Res<Time>,
+    fingerprint: Res<ProtocolFingerprint>,
+    mut links: Query<(
+        Entity,
+        &RemoteId,
+        &mut Handshaking,
+        &mut MessageReceiver<ProtocolHello>,
+        &mut MessageSender<HelloAccepted>,
+    )>,
+    mut commands: Commands,
+) {
+    for (link, remote_id, mut handshaking, mut receiver, mut sender) in links.iter_mut() {
+        let reason = match receiver.receive().last() {
+            Some(hello) if !handshaking.hello_accepted && fingerprint.matches(&hello) => {
+                handshaking.hello_accepted = true;
+                sender.send::<ControlChannel>(HelloAccepted);
+                None
+            }
+            Some(hello) if !handshaking.hello_accepted => Some(fingerprint.mismatch_reason(&hello)),
+            // the timer covers the whole handshake, the session info has to arrive in time too
+            _ if handshaking.timer.tick(time.delta()).finished() => {
+                Some(match handshaking.hello_accepted {
+                    true => "the handshake timed out".to_string(),
+                    // no hello we could read: we can't tell if its build is older or newer
+                    false => format!("incompatible version (server {})", fingerprint.version),
+                })
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            info!("Rejecting {}: {reason}", peer_label(remote_id.0));
+            commands.entity(link).remove::<Handshaking>();
+            commands.entity(link).insert(PendingKick::new(reason));
+        }
+    }
+}
+
+fn receive_session_info(
+    mut links: Query<(Entity, &Handshaking, &mut MessageReceiver<SessionInfo>)>,
+    mut commands: Commands,
+) {
+    for (link, handshaking, mut receiver) in links.iter_mut() {
+        // a session info sent before the hello was accepted is ignored
+        let Some(session) = receiver.receive().last() else {
+            continue;
+        };
+        if !handshaking.hello_accepted {
+            continue;
+        }
+        commands.entity(link).remove::<Handshaking>();
+        commands.trigger_targets(
+            HandshakeAccepted {
+                spectator: session.spectator,
+                interpolation_delay: session.interpolation_delay,
+                session_key: session.session_key,
+            },
+            link,
+        );
+    }
+}
+
+pub(crate) struct HandshakeClientPlugin;
+
+impl Plugin for HandshakeClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (send_protocol_hello, send_session_info));
+    }
+}
+
+fn send_protocol_hello(
+    fingerprint: Res<ProtocolFingerprint>,
+    mut client: Query<&mut MessageSender<ProtocolHello>, (With<Client>, Added<Connected>)>,
+) {
+    for mut sender in client.iter_mut() {
+        sender.send::<HandshakeChannel>(fingerprint.hello());
+    }
+}
+
+fn send_session_info(
+    client_config: Res<ClientConfigInfo>,
+    network_settings: Res<NetworkSettings>,
+    session_key: Res<SessionKey>,
+    mut client: Query<
+        (
+            &mut MessageReceiver<HelloAccepted>,
+            &mut MessageSender<SessionInfo>,
+        ),
+        With<Client>,
+    >,
+) {
+    for (mut receiver, mut sender) in client.iter_mut() {
+        if receiver.receive().last().is_some() {
+            sender.send::<ControlChannel>(SessionInfo {
+                spectator: client_config.spectator,
+                interpolation_delay: network_settings.interpolation_delay,
+                session_key: session_key.0,
+            });
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Ship health, destruction and respawning.
+//!
+//! Every ship has a replicated [`Health`]. Bullet hits reported by `process_collisions` (or by the
+//! rewound check of `lag_compensation` on the server) cost the damage of the weapon that fired the
+//! bullet, see `weapons`. The damage is applied
+//! on the server and on the predicting clients, so a client sees its hits land right away.
+//!
+//! A ship at zero health gets [`Destroyed`]: its collider and rigid body are disabled, it can't
+//! move or fire, and [`RESPAWN_DELAY_TICKS`] later it comes back at a spawn point of the game mode
+//! with full health and [`Invulnerable`] for [`INVULNERABLE_TICKS`]. Both are timed with ticks,
+//! so clients predict the respawn too.
+//!
+//! Hitting a teammate only does damage with `FriendlyFire::Full`, and a `Shield` power-up blocks
+//! all damage.
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::game_mode::{ActiveGameMode, ResetRound};
+use crate::networking::protocol::{
+    BulletHitEvent, Destroyed, FriendlyFire, Health, Invulnerable, MatchRoom, Player, Shield, Team,
+};
+use crate::networking::shared::{process_collisions, FIXED_TIMESTEP_HZ};
+use crate::networking::teams::is_friendly;
+
+pub const MAX_HEALTH: u16 = 100;
+/// 3 seconds
+pub const RESPAWN_DELAY_TICKS: u16 = 3 * FIXED_TIMESTEP_HZ as u16;
+/// 2 seconds
+pub const INVULNERABLE_TICKS: u16 = 2 * FIXED_TIMESTEP_HZ as u16;
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: MAX_HEALTH,
+            max: MAX_HEALTH,
+        }
+    }
+}
+
+impl Health {
+    /// Between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        self.current as f32 / self.max as f32
+    }
+}
+
+/// Ships simulated by this peer: the server's, or the predicted ones on a client
+pub(crate) type Simulated = Or<(With<Predicted>, With<Replicate>)>;
+
+/// Damage, destruction and respawn, in both apps
+pub(crate) struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(take_out_of_play);
+        app.add_observer(put_back_in_play);
+        app.add_systems(
+            FixedUpdate,
+            (
+                apply_bullet_damage,
+                respawn_ships.run_if(resource_exists::<ActiveGameMode>),
+                expire_invulnerability,
+            )
+                .chain()
+                .after(process_collisions),
+        );
+    }
+}
+
+pub(crate) struct HealthServerPlugin;
+
+impl Plugin for HealthServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(add_health);
+        app.add_observer(restore_between_rounds);
+    }
+}
+
+fn add_health(trigger: Trigger<OnAdd, Player>, mut commands: Commands) {
+    commands.entity(trigger.target()).insert(Health::default());
+}
+
+fn take_out_of_play(trigger: Trigger<OnAdd, Destroyed>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert((ColliderDisabled, RigidBodyDisabled));
+}
+
+fn put_back_in_play(trigger: Trigger<OnRemove, Destroyed>, mut commands: Commands) {
+    if let Ok(mut entity) = commands.get_entity(trigger.target()) {
+        entity.remove::<(ColliderDisabled, RigidBodyDisabled)>();
+    }
+}
+
+fn apply_bullet_damage(
+    mut events: EventReader<BulletHitEvent>,
+    room: Query<&MatchRoom>,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    mut ships: Query<
+        (
+            Entity,
+            &Player,
+            &mut Health,
+            Option<&Team>,
+            Has<Destroyed>,
+            Has<Invulnerable>,
+            Has<Shield>,
+        ),
+        Simulated,
+    >,
+    mut commands: Commands,
+) {
+    let tick = timeline.tick();
+    let friendly_fire = room
+        .single()
+        .map_or(FriendlyFire::default(), |room| room.friendly_fire);
+    for hit in events.read() {
+        let Some(victim) = hit.victim_client_id else {
+            continue;
+        };
+        let shooter_team = ships
+            .iter()
+            .find(|(_, player, ..)| player.client_id == hit.bullet_owner)
+            .and_then(|(_, _, _, team, ..)| team.copied());
+        let Some((entity, _, mut health, team, destroyed, invulnerable, shielded)) = ships
+            .iter_mut()
+            .find(|(_, player, ..)| player.client_id == victim)
+        else {
+            continue;
+        };
+        if destroyed || invulnerable || shielded {
+            continue;
+        }
+        if friendly_fire != FriendlyFire::Full && is_friendly(shooter_team.as_ref(), team) {
+            continue;
+        }
+        health.current = health.current.saturating_sub(hit.damage);
+        if health.current == 0 {
+            commands.entity(entity).insert(Destroyed {
+                respawn_tick: tick + RESPAWN_DELAY_TICKS,
+            });
+        }
+    }
+}
+
+fn respawn_ships(
+    mode: Res<ActiveGameMode>,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    mut ships: Query<
+        (
+            Entity,
+            &Player,
+            Option<&Team>,
+            &Destroyed,
+            &mut Health,
+            &mut Position,
+            &mut Rotation,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+        ),
+        Simulated,
+    >,
+    mut commands: Commands,
+) {
+    let tick = timeline.tick();
+    for (
+        entity,
+        player,
+        team,
+        destroyed,
+        mut health,
+        mut position,
+        mut rotation,
+        mut velocity,
+        mut angular,
+    ) in ships.iter_mut()
+    {
+        if tick - destroyed.respawn_tick < 0 {
+            continue;
+        }
+        // the slot is replicated, so the server and the clients pick the same spot
+        position.0 = mode.0.spawn_position(player.spawn_slot, team.copied());
+        *rotation = Rotation::default();
+        velocity.0 = Vec2::ZERO;
+        angular.0 = 0.0;
+        health.current = health.max;
+        commands
+            .entity(entity)
+            .remove::<Destroyed>()
+            .insert(Invulnerable {
+                until: tick + INVULNERABLE_TICKS,
+            });
+    }
+}
+
+fn expire_invulnerability(
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    ships: Query<(Entity, &Invulnerable), Simulated>,
+    mut commands: Commands,
+) {
+    let tick = timeline.tick();
+    for (entity, invulnerable) in ships.iter() {
+        if tick - invulnerable.until >= 0 {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+/// Every ship starts the round in play with full health
+fn restore_between_rounds(
+    _trigger: Trigger<ResetRound>,
+    mut ships: Query<(Entity, &mut Health)>,
+    mut commands: Commands,
+) {
+    for (entity, mut health) in ships.iter_mut() {
+        health.current = health.max;
+        commands
+            .entity(entity)
+            .remove::<(Destroyed, Invulnerable)>();
+    }
+}
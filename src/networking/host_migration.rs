@@ -0,0 +1,374 @@
+//! Host migration for listen-servers (`Mode::Full`).
+//!
+//! While a match is running, the host's server periodically picks a successor among the remote
+//! clients that can host (they run in `Mode::Full` too) and sends everyone a [`HostMigrationPlan`].
+//! Every client also keeps a [`WorldSnapshot`] of the last replicated state. Interest management
+//! only replicates the balls near each ship, so the plan sent to the successor also carries every
+//! ball of the arena.
+//!
+//! When the connection to the host is lost:
+//! - the successor starts its own background server, seeded with its snapshot, and connects to it
+//!   through crossbeam like a normal host
+//! - the other clients wait a moment for that server to come up, then reconnect to the successor
+//!
+//! Netcode client ids are picked again on every connection, so players are recognized on the new
+//! host by the [`SessionKey`] they send in their `SessionInfo`.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use avian2d::prelude::Position;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::networking::client::LastDisconnectReason;
+use crate::networking::protocol::{
+    BallMarker, ConnectionKind, ControlChannel, HostCapability, HostMigrationPlan, MigrationTarget,
+    Player, Roster, TeamGoals,
+};
+use crate::networking::shared::SERVER_ADDR;
+use crate::{ClientConfigInfo, GameState, MultiplayerState};
+
+/// How often the server refreshes the plan sent to clients
+const MIGRATION_PLAN_INTERVAL: Duration = Duration::from_secs(2);
+/// How long the other clients wait for the successor's server to start before reconnecting
+const MIGRATION_RECONNECT_DELAY: Duration = Duration::from_secs(3);
+
+/// Random key picked by each client when the game starts
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SessionKey(pub u64);
+
+#[derive(Clone, Debug, Default)]
+pub struct PlayerSnapshot {
+    pub session_key: u64,
+    pub nickname: String,
+    pub score: i32,
+    /// None when the ship was too far away to be replicated to this client
+    pub position: Option<Vec2>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Reflect)]
+pub struct BallSnapshot {
+    pub position: Vec2,
+    pub radius: f32,
+}
+
+/// The last replicated world state seen by a client, used to seed the successor's server
+#[derive(Clone, Debug, Default)]
+pub struct WorldSnapshot {
+    pub players: Vec<PlayerSnapshot>,
+    pub balls: Vec<BallSnapshot>,
+    /// None in modes that don't count goals
+    pub team_goals: Option<TeamGoals>,
+}
+
+impl WorldSnapshot {
+    /// Find the saved state of a reconnecting player
+    pub fn find_player(&self, session_key: u64) -> Option<&PlayerSnapshot> {
+        self.players.iter().find(|p| p.session_key == session_key)
+    }
+}
+
+/// Inserted on the server when it was started by a host migration
+#[derive(Resource)]
+pub struct MigrationSeed(pub WorldSnapshot);
+
+/// Marks a client link that told us it can take over as host
+#[derive(Component)]
+struct HostCandidate {
+    connected_at: Duration,
+}
+
+pub(crate) struct HostMigrationServerPlugin;
+
+impl Plugin for HostMigrationServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                receive_host_capability,
+                send_migration_plan.run_if(on_timer(MIGRATION_PLAN_INTERVAL)),
+            )
+                .chain()
+                .run_if(in_state(MultiplayerState::Server)),
+        );
+    }
+}
+
+fn receive_host_capability(
+    time: Res<Time>,
+    mut links: Query<(Entity, &mut MessageReceiver<HostCapability>), With<ClientOf>>,
+    mut commands: Commands,
+) {
+    for (entity, mut receiver) in links.iter_mut() {
+        for capability in receiver.receive() {
+            if capability.can_host {
+                commands.entity(entity).insert(HostCandidate {
+                    connected_at: time.elapsed(),
+                });
+            }
+        }
+    }
+}
+
+/// Pick the remote client that has been connected the longest and tell everyone about it
+fn send_migration_plan(
+    mut links: Query<
+        (
+            Entity,
+            &RemoteId,
+            Option<&PeerAddr>,
+            Option<&HostCandidate>,
+            &mut MessageSender<HostMigrationPlan>,
+        ),
+        (With<ClientOf>, With<Connected>),
+    >,
+    players: Query<(&ConnectionKind, &ControlledBy), With<Player>>,
+    balls: Query<(&BallMarker, &Position)>,
+) {
+    let is_local = |link: Entity| {
+        players
+            .iter()
+            .any(|(kind, controlled)| controlled.owner == link && *kind == ConnectionKind::Local)
+    };
+
+    let successor = links
+        .iter()
+        .filter(|(entity, ..)| !is_local(*entity))
+        .filter_map(|(entity, remote_id, peer_addr, candidate, _)| {
+            let target = match remote_id.0 {
+                PeerId::Steam(steam_id) => MigrationTarget::Steam(steam_id),
+                _ => MigrationTarget::Udp(SocketAddr::new(peer_addr?.0.ip(), SERVER_ADDR.port())),
+            };
+            candidate.map(|c| (entity, c.connected_at, target))
+        })
+        .min_by_key(|(_, connected_at, _)| *connected_at);
+
+    for (entity, _, _, _, mut sender) in links.iter_mut() {
+        if is_local(entity) {
+            // the host doesn't migrate to anyone, its server goes away with it
+            continue;
+        }
+        let you_are_successor = successor.as_ref().is_some_and(|(e, _, _)| *e == entity);
+        let balls = match you_are_successor {
+            true => balls
+                .iter()
+                .map(|(ball, position)| BallSnapshot {
+                    position: position.0,
+                    radius: ball.radius,
+                })
+                .collect(),
+            false => Vec::new(),
+        };
+        sender.send::<ControlChannel>(HostMigrationPlan {
+            successor: successor.as_ref().map(|(_, _, target)| target.clone()),
+            you_are_successor,
+            balls,
+        });
+    }
+}
+
+/// Client-side migration state
+#[derive(Resource, Default)]
+pub struct HostMigration {
+    /// Latest plan received from the host. Cleared when we leave on purpose.
+    pub plan: Option<HostMigrationPlan>,
+    pub snapshot: WorldSnapshot,
+    /// Set when this client should start a server seeded with this snapshot
+    pub pending_seed: Option<WorldSnapshot>,
+    reconnect_timer: Option<Timer>,
+}
+
+impl HostMigration {
+    pub fn should_migrate(&self) -> bool {
+        self.plan
+            .as_ref()
+            .is_some_and(|plan| plan.successor.is_some())
+    }
+}
+
+pub(crate) struct HostMigrationClientPlugin;
+
+impl Plugin for HostMigrationClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HostMigration>();
+        app.insert_resource(SessionKey(rand::random()));
+        app.add_systems(
+            Update,
+            (
+                send_host_capability,
+                receive_migration_plan,
+                record_world_snapshot.run_if(on_timer(Duration::from_secs(1))),
+            )
+                .run_if(in_state(MultiplayerState::Client)),
+        );
+        app.add_systems(OnEnter(MultiplayerState::Migrating), begin_migration);
+        app.add_systems(
+            Update,
+            reconnect_after_migration.run_if(in_state(MultiplayerState::Migrating)),
+        );
+    }
+}
+
+fn send_host_capability(
+    client_config: Res<ClientConfigInfo>,
+    mut client: Query<&mut MessageSender<HostCapability>, (With<Client>, Added<Connected>)>,
+) {
+    for mut sender in client.iter_mut() {
+        // the current host never needs to migrate to itself
+        sender.send::<ControlChannel>(HostCapability {
+            can_host: client_config.can_host
+                && !client_config.seperate_mode
+                && !client_config.spectator,
+        });
+    }
+}
+
+fn receive_migration_plan(
+    mut host_migration: ResMut<HostMigration>,
+    mut client: Query<&mut MessageReceiver<HostMigrationPlan>, With<Client>>,
+) {
+    for mut receiver in client.iter_mut() {
+        for plan in receiver.receive() {
+            host_migration.plan = Some(plan);
+        }
+    }
+}
+
+fn record_world_snapshot(
+    mut host_migration: ResMut<HostMigration>,
+    rosters: Query<&Roster>,
+    ships: Query<(&Player, &Position), Or<(With<Predicted>, With<Interpolated>)>>,
+    balls: Query<(&BallMarker, &Position), With<Confirmed>>,
+    goals: Query<&TeamGoals>,
+) {
+    let host_migration = &mut *host_migration;
+    let snapshot = &mut host_migration.snapshot;
+    snapshot.players.clear();
+    snapshot.balls.clear();
+    snapshot.team_goals = goals.single().ok().copied();
+    for entry in rosters.iter().flat_map(|roster| &roster.0) {
+        let position = ships
+            .iter()
+            .find(|(player, _)| player.session_key == entry.session_key)
+            .map(|(_, position)| position.0);
+        snapshot.players.push(PlayerSnapshot {
+            session_key: entry.session_key,
+            nickname: entry.nickname.clone(),
+            score: entry.score,
+            position,
+        });
+    }
+    // we may not see every ball, the host sends all of them to its successor
+    match host_migration
+        .plan
+        .as_ref()
+        .filter(|plan| plan.you_are_successor)
+    {
+        Some(plan) => snapshot.balls.clone_from(&plan.balls),
+        None => {
+            for (ball, position) in balls.iter() {
+                snapshot.balls.push(BallSnapshot {
+                    position: position.0,
+                    radius: ball.radius,
+                });
+            }
+        }
+    }
+}
+
+fn begin_migration(
+    mut host_migration: ResMut<HostMigration>,
+    mut client_config: ResMut<ClientConfigInfo>,
+    mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+    replicated: Query<
+        Entity,
+        Or<(
+            With<Replicated>,
+            With<Predicted>,
+            With<Confirmed>,
+            With<PreSpawned>,
+        )>,
+    >,
+    mut commands: Commands,
+) {
+    // the old host's entities are stale, the new server will replicate fresh ones
+    for entity in replicated.iter() {
+        commands.entity(entity).try_despawn();
+    }
+
+    // a plan is only used once: if the new host also goes away we return to the menu
+    let Some(plan) = host_migration.plan.take() else {
+        abandon_migration(
+            &mut multiplayer_state,
+            &mut game_state,
+            &mut last_disconnect_reason,
+        );
+        return;
+    };
+
+    if plan.you_are_successor {
+        info!("Host left, taking over as the new host");
+        host_migration.pending_seed = Some(host_migration.snapshot.clone());
+        client_config.seperate_mode = true;
+        client_config.steam_connect_to = None;
+        multiplayer_state.set(MultiplayerState::ClientSpawnServer);
+        return;
+    }
+
+    match plan.successor {
+        Some(MigrationTarget::Steam(steam_id)) => {
+            info!("Host left, reconnecting to the new host over steam: {steam_id}");
+            client_config.steam_connect_to = Some((
+                SteamId::from_raw(steam_id),
+                steamworks::LobbyId::from_raw(0),
+            ));
+        }
+        Some(MigrationTarget::Udp(addr)) => {
+            info!("Host left, reconnecting to the new host over udp: {addr}");
+            client_config.steam_connect_to = None;
+            client_config.address = addr.ip().to_string();
+            client_config.port = addr.port().to_string();
+        }
+        None => {
+            abandon_migration(
+                &mut multiplayer_state,
+                &mut game_state,
+                &mut last_disconnect_reason,
+            );
+            return;
+        }
+    }
+    client_config.seperate_mode = false;
+    host_migration.reconnect_timer = Some(Timer::new(MIGRATION_RECONNECT_DELAY, TimerMode::Once));
+}
+
+/// Nobody can take over, go back to the menu
+fn abandon_migration(
+    multiplayer_state: &mut NextState<MultiplayerState>,
+    game_state: &mut NextState<GameState>,
+    last_disconnect_reason: &mut LastDisconnectReason,
+) {
+    info!("Host left and nobody can take over");
+    last_disconnect_reason.0 = Some("Host left the game".to_string());
+    multiplayer_state.set(MultiplayerState::None);
+    game_state.set(GameState::Menu);
+}
+
+fn reconnect_after_migration(
+    time: Res<Time>,
+    mut host_migration: ResMut<HostMigration>,
+    mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
+) {
+    let Some(timer) = host_migration.reconnect_timer.as_mut() else {
+        return;
+    };
+    if timer.tick(time.delta()).finished() {
+        host_migration.reconnect_timer = None;
+        multiplayer_state.set(MultiplayerState::Client);
+    }
+}
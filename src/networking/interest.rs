@@ -0,0 +1,167 @@
+//! Radius-based interest management.
+//!
+//! Ships, balls and bullets are only replicated to the clients whose ship is close to them. An
+//! entity becomes visible to a client when it gets within [`InterestSettings::enter_radius`] of
+//! its ship and stays visible until it is further than [`InterestSettings::exit_radius`], so
+//! entities near the edge don't flicker in and out. A client always sees its own ship.
+//!
+//! Some entities are always sent to everyone:
+//! - the `MatchRoom`, with the [`Roster`] of every player that the scoreboard, the lobby and the
+//!   results read, since the `Player` and `Score` of far away ships aren't replicated
+//! - everything, for links without a ship (spectators), since the server doesn't know where they look
+use avian2d::prelude::Position;
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::protocol::{
+    BallMarker, BulletMarker, ColorComponent, ConnectionKind, MatchRoom, Player, Ready, Roster,
+    RosterEntry, Score, Team,
+};
+
+/// Distances from a client's ship within which entities are replicated to it
+#[derive(Resource, Clone, Debug)]
+pub struct InterestSettings {
+    pub enter_radius: f32,
+    /// larger than `enter_radius`, the gap is the hysteresis
+    pub exit_radius: f32,
+}
+
+impl Default for InterestSettings {
+    fn default() -> Self {
+        Self {
+            enter_radius: 600.0,
+            exit_radius: 700.0,
+        }
+    }
+}
+
+/// Links that can currently see an interest-managed entity
+#[derive(Component, Default)]
+pub(crate) struct InterestState {
+    visible_to: EntityHashSet,
+}
+
+impl InterestState {
+    pub(crate) fn is_visible_to(&self, link: Entity) -> bool {
+        self.visible_to.contains(&link)
+    }
+}
+
+pub(crate) struct InterestServerPlugin;
+
+impl Plugin for InterestServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterestSettings>();
+        app.add_observer(manage_interest);
+        app.add_observer(forget_link);
+        app.add_systems(Update, publish_roster);
+        // after the physics moved everything, and for bullets in the tick they are fired
+        app.add_systems(FixedPostUpdate, update_interest);
+    }
+}
+
+/// Ships, balls and bullets start hidden from everyone, [`update_interest`] reveals them
+fn manage_interest(
+    trigger: Trigger<OnAdd, Replicate>,
+    managed: Query<(), Or<(With<Player>, With<BallMarker>, With<BulletMarker>)>>,
+    mut commands: Commands,
+) {
+    if managed.get(trigger.target()).is_ok() {
+        commands
+            .entity(trigger.target())
+            .insert((NetworkVisibility::default(), InterestState::default()));
+    }
+}
+
+/// A disconnected link can't see anything anymore, drop it from every [`InterestState`]
+fn forget_link(
+    trigger: Trigger<OnRemove, Connected>,
+    links: Query<(), With<ClientOf>>,
+    mut entities: Query<&mut InterestState>,
+) {
+    let link = trigger.target();
+    if links.get(link).is_err() {
+        return;
+    }
+    for mut state in entities.iter_mut() {
+        state.visible_to.remove(&link);
+    }
+}
+
+/// Copy the scoreboard data of every ship to the room's [`Roster`]
+fn publish_roster(
+    ships: Query<
+        (
+            &Player,
+            &Score,
+            &ColorComponent,
+            Option<&Team>,
+            Option<&ConnectionKind>,
+            Option<&Ready>,
+        ),
+        With<Replicate>,
+    >,
+    mut roster: Single<&mut Roster, With<MatchRoom>>,
+) {
+    let mut entries: Vec<_> = ships
+        .iter()
+        .map(|(player, score, color, team, kind, ready)| RosterEntry {
+            client_id: player.client_id,
+            nickname: player.nickname.clone(),
+            session_key: player.session_key,
+            score: score.0,
+            team: team.copied(),
+            connection_kind: kind.copied(),
+            ready: ready.is_some_and(|ready| ready.0),
+            rtt: player.rtt,
+            color: color.0,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+    roster.set_if_neq(Roster(entries));
+}
+
+fn update_interest(
+    settings: Res<InterestSettings>,
+    links: Query<Entity, (With<ClientOf>, With<Connected>)>,
+    ships: Query<(&ControlledBy, &Position), With<Player>>,
+    mut entities: Query<(
+        &Position,
+        Option<&ControlledBy>,
+        &mut NetworkVisibility,
+        &mut InterestState,
+    )>,
+) {
+    let viewers: Vec<(Entity, Option<Vec2>)> = links
+        .iter()
+        .map(|link| {
+            let ship = ships
+                .iter()
+                .find(|(controlled_by, _)| controlled_by.owner == link)
+                .map(|(_, position)| position.0);
+            (link, ship)
+        })
+        .collect();
+
+    for (position, controlled_by, mut visibility, mut state) in entities.iter_mut() {
+        for (link, ship) in viewers.iter() {
+            let visible = state.visible_to.contains(link);
+            let should_see = match ship {
+                None => true,
+                // our own ship
+                Some(_) if controlled_by.is_some_and(|owner| owner.owner == *link) => true,
+                Some(ship) if visible => ship.distance(position.0) <= settings.exit_radius,
+                Some(ship) => ship.distance(position.0) <= settings.enter_radius,
+            };
+            if should_see && !visible {
+                visibility.gain_visibility(*link);
+                state.visible_to.insert(*link);
+            } else if !should_see && visible {
+                visibility.lose_visibility(*link);
+                state.visible_to.remove(link);
+            }
+        }
+    }
+}
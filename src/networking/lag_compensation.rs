@@ -0,0 +1,220 @@
+//! Server-side lag compensation for bullet hits.
+//!
+//! A shooter sees the other ships where they were a while ago: their inputs take half the RTT to
+//! reach the server, and the ships they aim at are shown with their interpolation delay. Checking
+//! hits against the current ship positions makes high ping players miss shots that looked good on
+//! their screen.
+//!
+//! The server keeps a [`ColliderHistory`] of the last positions of every ship, one entry per tick.
+//! Each tick, bullets are tested against the ships as they were `rtt / 2 + interpolation delay`
+//! ago for the bullet's owner, but never more than [`LagCompensationSettings::max_rewind`]. The
+//! interpolation delay is the one the client sent in its `SessionInfo`.
+//!
+//! On the server, bullets don't collide with the current ships in the physics world (see
+//! [`GameLayer`]), so a bullet is not stopped by a ship that has moved into its path since the
+//! shooter saw it. The rewound check despawns the bullet and reports the hit.
+//!
+//! Type `rewind <ms>` in the server console to change the maximum rewind. With 0, hits are checked
+//! against the current positions.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use avian2d::prelude::{Collider, CollisionLayers, LayerMask, PhysicsLayer, Position, Rotation};
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::handshake::HandshakeAccepted;
+use crate::networking::protocol::{
+    BulletHitEvent, BulletMarker, ColorComponent, Destroyed, FriendlyFire, MatchRoom,
+    PhysicsBundle, Player, Team,
+};
+use crate::networking::shared::{process_collisions, FIXED_TIMESTEP_HZ};
+use crate::networking::teams::is_friendly;
+use crate::networking::weapons::PiercedShips;
+
+/// How far back bullet hits can be checked
+#[derive(Resource, Clone, Debug)]
+pub struct LagCompensationSettings {
+    /// also the length of the [`ColliderHistory`]
+    pub max_rewind: Duration,
+}
+
+impl Default for LagCompensationSettings {
+    fn default() -> Self {
+        Self {
+            max_rewind: Duration::from_millis(250),
+        }
+    }
+}
+
+impl LagCompensationSettings {
+    fn max_rewind_ticks(&self) -> u16 {
+        ticks(self.max_rewind)
+    }
+}
+
+fn ticks(duration: Duration) -> u16 {
+    (duration.as_secs_f64() * FIXED_TIMESTEP_HZ).round() as u16
+}
+
+/// Where a ship was during the last ticks, newest last
+#[derive(Component, Default, Debug)]
+pub struct ColliderHistory(VecDeque<(Tick, Position, Rotation)>);
+
+impl ColliderHistory {
+    /// The last recorded pose at or before `tick`, or the oldest one we have
+    fn at(&self, tick: Tick) -> Option<(Position, Rotation)> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(recorded, ..)| *recorded <= tick)
+            .or(self.0.front())
+            .map(|(_, position, rotation)| (*position, *rotation))
+    }
+}
+
+/// Collision layers of the server: ships with a [`ColliderHistory`] are on their own layer, which
+/// bullets ignore
+#[derive(PhysicsLayer, Clone, Copy, Debug, Default)]
+pub enum GameLayer {
+    #[default]
+    Default,
+    CompensatedShip,
+}
+
+/// Interpolation delay a client reported in its `SessionInfo`
+#[derive(Component, Debug)]
+struct ReportedInterpolationDelay(Duration);
+
+pub(crate) struct LagCompensationServerPlugin;
+
+impl Plugin for LagCompensationServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LagCompensationSettings>();
+        app.add_observer(add_collider_history);
+        app.add_observer(ignore_compensated_ships);
+        app.add_observer(store_interpolation_delay);
+        app.add_systems(
+            FixedUpdate,
+            (record_collider_history, check_rewound_hits)
+                .chain()
+                .before(process_collisions),
+        );
+    }
+}
+
+fn add_collider_history(trigger: Trigger<OnAdd, Player>, mut commands: Commands) {
+    commands.entity(trigger.target()).insert((
+        ColliderHistory::default(),
+        CollisionLayers::new(GameLayer::CompensatedShip, LayerMask::ALL),
+    ));
+}
+
+fn ignore_compensated_ships(trigger: Trigger<OnAdd, BulletMarker>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert(CollisionLayers::new(GameLayer::Default, GameLayer::Default));
+}
+
+fn store_interpolation_delay(trigger: Trigger<HandshakeAccepted>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert(ReportedInterpolationDelay(trigger.interpolation_delay));
+}
+
+fn record_collider_history(
+    settings: Res<LagCompensationSettings>,
+    timeline: Single<&LocalTimeline, With<Server>>,
+    mut ships: Query<(&Position, &Rotation, &mut ColliderHistory)>,
+) {
+    let tick = timeline.tick();
+    let length = settings.max_rewind_ticks() as usize + 1;
+    for (position, rotation, mut history) in ships.iter_mut() {
+        history.0.push_back((tick, *position, *rotation));
+        while history.0.len() > length {
+            history.0.pop_front();
+        }
+    }
+}
+
+fn check_rewound_hits(
+    settings: Res<LagCompensationSettings>,
+    timeline: Single<&LocalTimeline, With<Server>>,
+    links: Query<(&RemoteId, &Link, Option<&ReportedInterpolationDelay>), With<ClientOf>>,
+    mut bullets: Query<(
+        Entity,
+        &BulletMarker,
+        &ColorComponent,
+        &Position,
+        Option<&mut PiercedShips>,
+    )>,
+    // destroyed ships are out of play
+    ships: Query<(&Player, &ColliderHistory, Option<&Team>), Without<Destroyed>>,
+    room: Query<&MatchRoom>,
+    mut hit_ev_writer: EventWriter<BulletHitEvent>,
+    mut commands: Commands,
+) {
+    let max_rewind = settings.max_rewind_ticks();
+    if bullets.is_empty() {
+        return;
+    }
+    let tick = timeline.tick();
+    let ship_collider = PhysicsBundle::player_ship().collider;
+    let friendly_fire = room
+        .single()
+        .map_or(FriendlyFire::default(), |room| room.friendly_fire);
+
+    for (bullet_entity, bullet, color, bullet_pos, mut pierced) in bullets.iter_mut() {
+        // bullets of unknown owners are checked against the current positions
+        let rewind = links
+            .iter()
+            .find(|(remote_id, ..)| remote_id.0 == bullet.owner)
+            .map_or(0, |(_, link, delay)| {
+                let delay = delay.map_or(Duration::ZERO, |delay| delay.0);
+                ticks(link.stats.rtt / 2 + delay).min(max_rewind)
+            });
+        let shooter_team = ships
+            .iter()
+            .find(|(player, ..)| player.client_id == bullet.owner)
+            .and_then(|(.., team)| team);
+        let Some(victim) = ships.iter().find(|(player, history, team)| {
+            player.client_id != bullet.owner
+                // piercing bullets hit each ship once
+                && !pierced
+                    .as_ref()
+                    .is_some_and(|pierced| pierced.0.contains(&player.client_id))
+                && history
+                    .at(tick - rewind)
+                    .is_some_and(|(position, rotation)| {
+                        hits(&ship_collider, position, rotation, bullet_pos.0, bullet.size)
+                    })
+        }) else {
+            continue;
+        };
+        match pierced.as_mut() {
+            Some(pierced) => {
+                pierced.0.push(victim.0.client_id);
+                if pierced.0.len() > bullet.pierce as usize {
+                    commands.entity(bullet_entity).prediction_despawn();
+                }
+            }
+            None => commands.entity(bullet_entity).prediction_despawn(),
+        }
+        // with friendly fire off, hitting a teammate only destroys the bullet
+        if friendly_fire == FriendlyFire::Off && is_friendly(shooter_team, victim.2) {
+            continue;
+        }
+        hit_ev_writer.write(BulletHitEvent {
+            bullet_owner: bullet.owner,
+            victim_client_id: Some(victim.0.client_id),
+            position: bullet_pos.0,
+            bullet_color: color.0,
+            damage: bullet.damage,
+        });
+    }
+}
+
+fn hits(ship: &Collider, position: Position, rotation: Rotation, bullet: Vec2, size: f32) -> bool {
+    ship.distance_to_point(position, rotation, bullet, true) <= size
+}
@@ -0,0 +1,345 @@
+//! Lobby data published by the host in its Steam lobby.
+//!
+//! Friends can read it without joining the lobby, so the join screen shows what each friend is
+//! hosting and refuses to join a full server or one running another version of the game.
+use bevy::prelude::*;
+use lightyear::prelude::SteamId;
+use steamworks::LobbyId;
+
+use crate::networking::lobby_join::{connect_string, RICH_PRESENCE_CONNECT};
+use crate::networking::protocol::{FriendlyFire, Player};
+use crate::networking::server::ServerStartupResources;
+use crate::networking::shared::{GAME_VERSION, MAX_PLAYERS};
+use crate::networking::steam_backend::{SteamBackend, SteamPlatform};
+
+const KEY_SERVER: &str = "server";
+const KEY_VERSION: &str = "version";
+const KEY_MAP: &str = "map";
+const KEY_MODE: &str = "mode";
+const KEY_PLAYERS: &str = "players";
+const KEY_MAX_PLAYERS: &str = "max_players";
+const KEY_PASSWORD: &str = "password";
+
+/// What the host publishes about its server
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbyInfo {
+    /// SteamId to connect to with `SteamClientIo`
+    pub server: SteamId,
+    pub version: String,
+    pub map: String,
+    pub mode: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub password: bool,
+}
+
+impl LobbyInfo {
+    pub fn publish(&self, steam: &dyn SteamBackend, lobby: LobbyId) {
+        steam.set_lobby_data(lobby, KEY_SERVER, &self.server.raw().to_string());
+        steam.set_lobby_data(lobby, KEY_VERSION, &self.version);
+        steam.set_lobby_data(lobby, KEY_MAP, &self.map);
+        steam.set_lobby_data(lobby, KEY_MODE, &self.mode);
+        steam.set_lobby_data(lobby, KEY_PLAYERS, &self.players.to_string());
+        steam.set_lobby_data(lobby, KEY_MAX_PLAYERS, &self.max_players.to_string());
+        steam.set_lobby_data(lobby, KEY_PASSWORD, if self.password { "1" } else { "0" });
+    }
+
+    /// `None` until Steam has sent us the data, or if the lobby isn't one of ours
+    pub fn read(steam: &dyn SteamBackend, lobby: LobbyId) -> Option<Self> {
+        let get = |key| steam.lobby_data(lobby, key);
+        Some(Self {
+            server: SteamId::from_raw(get(KEY_SERVER)?.parse().ok()?),
+            version: get(KEY_VERSION)?,
+            map: get(KEY_MAP).unwrap_or_default(),
+            mode: get(KEY_MODE).unwrap_or_default(),
+            players: get(KEY_PLAYERS)?.parse().ok()?,
+            max_players: get(KEY_MAX_PLAYERS)?.parse().ok()?,
+            password: get(KEY_PASSWORD).is_some_and(|password| password == "1"),
+        })
+    }
+
+    /// Why we can't join this server, if we can't
+    pub fn join_blocker(&self) -> Option<String> {
+        if self.version != GAME_VERSION {
+            Some(format!(
+                "version mismatch (server {}, you {GAME_VERSION})",
+                self.version
+            ))
+        } else if self.players >= self.max_players {
+            Some("server is full".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// One line summary for the join screen
+    pub fn describe(&self) -> String {
+        format!(
+            "{} - {} - {}/{}{}",
+            self.mode,
+            self.map,
+            self.players,
+            self.max_players,
+            if self.password { " - password" } else { "" },
+        )
+    }
+}
+
+/// Host settings published in the lobby
+#[derive(Resource, Clone, Debug)]
+pub struct LobbySettings {
+    pub map: String,
+    pub mode: String,
+    /// see `teams`
+    pub teams: bool,
+    pub friendly_fire: FriendlyFire,
+    pub max_players: u32,
+    /// Only advertised for now, joining doesn't ask for it
+    pub password: Option<String>,
+}
+
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            map: "Arena".to_string(),
+            mode: "Deathmatch".to_string(),
+            teams: false,
+            friendly_fire: FriendlyFire::Off,
+            max_players: MAX_PLAYERS,
+            password: None,
+        }
+    }
+}
+
+pub(crate) struct LobbyDataServerPlugin;
+
+impl Plugin for LobbyDataServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LobbySettings>();
+        app.add_systems(
+            Update,
+            publish_lobby_data.run_if(resource_exists::<SteamPlatform>),
+        );
+    }
+}
+
+/// Publish the lobby data once the lobby exists, then again every time it changes
+fn publish_lobby_data(
+    steam: Res<SteamPlatform>,
+    server_startup: Res<ServerStartupResources>,
+    settings: Res<LobbySettings>,
+    players: Query<(), With<Player>>,
+    mut published: Local<Option<(LobbyId, LobbyInfo)>>,
+) {
+    let Some(lobby) = server_startup
+        .steam_lobby_id
+        .as_ref()
+        .and_then(|lobby| *lobby.lock())
+    else {
+        *published = None;
+        return;
+    };
+    let info = LobbyInfo {
+        server: steam.local_user(),
+        version: GAME_VERSION.to_string(),
+        map: settings.map.clone(),
+        mode: settings.mode.clone(),
+        players: players.iter().count() as u32,
+        max_players: settings.max_players,
+        password: settings.password.is_some(),
+    };
+    if published
+        .as_ref()
+        .is_some_and(|(published_lobby, published_info)| {
+            *published_lobby == lobby && *published_info == info
+        })
+    {
+        return;
+    }
+    info!("Publishing lobby data for {lobby:?}: {}", info.describe());
+    info.publish(&*steam.0, lobby);
+    if published
+        .as_ref()
+        .is_none_or(|(published_lobby, _)| *published_lobby != lobby)
+    {
+        // lets friends join from the steam friend list, even when the game isn't running
+        steam.set_rich_presence(RICH_PRESENCE_CONNECT, Some(&connect_string(lobby)));
+    }
+    *published = Some((lobby, info));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use lightyear::prelude::PeerId;
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::networking::lobby_join::parse_connect_lobby;
+    use crate::networking::steam_backend::MockSteamBackend;
+
+    fn me() -> SteamId {
+        SteamId::from_raw(100)
+    }
+
+    fn friend() -> SteamId {
+        SteamId::from_raw(200)
+    }
+
+    fn friend_lobby() -> LobbyId {
+        LobbyId::from_raw(7)
+    }
+
+    fn lobby_info(server: SteamId) -> LobbyInfo {
+        LobbyInfo {
+            server,
+            version: GAME_VERSION.to_string(),
+            map: "Arena".to_string(),
+            mode: "Soccer".to_string(),
+            players: 3,
+            max_players: 8,
+            password: false,
+        }
+    }
+
+    /// A lobby owned by the local user
+    fn create_lobby(steam: &MockSteamBackend) -> LobbyId {
+        let lobby = Arc::new(Mutex::new(None));
+        let created = lobby.clone();
+        steam.create_lobby(
+            MAX_PLAYERS,
+            Box::new(move |result| {
+                *created.lock() = result.ok();
+            }),
+        );
+        steam.run_callbacks();
+        let result = *lobby.lock();
+        result.expect("the mock creates the lobby")
+    }
+
+    /// A host app owning a lobby of the mock Steam
+    fn host_app() -> (App, Arc<MockSteamBackend>, LobbyId) {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        let lobby = create_lobby(&steam);
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        app.insert_resource(SteamPlatform(steam.clone()));
+        app.insert_resource(ServerStartupResources {
+            just_server: false,
+            server_crossbeam: None,
+            steam_lobby_id: Some(Arc::new(Mutex::new(Some(lobby)))),
+        });
+        app.add_plugins(LobbyDataServerPlugin);
+        (app, steam, lobby)
+    }
+
+    fn spawn_player(app: &mut App, id: u64) {
+        app.world_mut().spawn(Player::new(
+            PeerId::Netcode(id),
+            format!("Player {id}"),
+            id,
+            0,
+        ));
+    }
+
+    #[test]
+    fn publishes_lobby_data_and_rich_presence() {
+        let (mut app, steam, lobby) = host_app();
+        spawn_player(&mut app, 1);
+        app.update();
+
+        let info = LobbyInfo::read(&*steam, lobby).unwrap();
+        assert_eq!(info.server, me());
+        assert_eq!(info.version, GAME_VERSION);
+        assert_eq!(info.mode, "Deathmatch");
+        assert_eq!(info.players, 1);
+        assert_eq!(info.max_players, MAX_PLAYERS);
+        assert!(!info.password);
+        assert_eq!(info.join_blocker(), None);
+        let connect = steam.rich_presence(RICH_PRESENCE_CONNECT).unwrap();
+        assert_eq!(parse_connect_lobby(&connect), Some(lobby));
+    }
+
+    #[test]
+    fn republishes_when_players_or_settings_change() {
+        let (mut app, steam, lobby) = host_app();
+        app.update();
+        assert_eq!(LobbyInfo::read(&*steam, lobby).unwrap().players, 0);
+
+        spawn_player(&mut app, 1);
+        spawn_player(&mut app, 2);
+        app.world_mut().resource_mut::<LobbySettings>().mode = "Soccer".to_string();
+        app.update();
+        let info = LobbyInfo::read(&*steam, lobby).unwrap();
+        assert_eq!(info.players, 2);
+        assert_eq!(info.mode, "Soccer");
+
+        app.world_mut().resource_mut::<LobbySettings>().max_players = 2;
+        app.update();
+        assert_eq!(
+            LobbyInfo::read(&*steam, lobby).unwrap().join_blocker(),
+            Some("server is full".to_string())
+        );
+    }
+
+    #[test]
+    fn nothing_is_published_without_a_lobby() {
+        let (mut app, steam, lobby) = host_app();
+        *app.world()
+            .resource::<ServerStartupResources>()
+            .steam_lobby_id
+            .as_ref()
+            .unwrap()
+            .lock() = None;
+        app.update();
+        assert_eq!(LobbyInfo::read(&*steam, lobby), None);
+        assert_eq!(steam.rich_presence(RICH_PRESENCE_CONNECT), None);
+    }
+
+    #[test]
+    fn lobby_data_round_trip() {
+        let steam = MockSteamBackend::new(me());
+        let lobby = create_lobby(&steam);
+        assert_eq!(LobbyInfo::read(&steam, lobby), None);
+
+        let info = lobby_info(me());
+        info.publish(&steam, lobby);
+        let read = LobbyInfo::read(&steam, lobby).unwrap();
+        assert_eq!(read, info);
+        assert_eq!(read.join_blocker(), None);
+    }
+
+    #[test]
+    fn friend_lobby_data_blocks_join() {
+        let steam = MockSteamBackend::new(me());
+        let server = friend().raw().to_string();
+        steam.add_friend(
+            "Friend",
+            friend(),
+            friend_lobby(),
+            &[
+                ("server", server.as_str()),
+                ("version", "0.0.0-other"),
+                ("players", "3"),
+                ("max_players", "8"),
+            ],
+        );
+        let info = LobbyInfo::read(&steam, friend_lobby()).unwrap();
+        assert_eq!(info.server, friend());
+        assert!(info
+            .join_blocker()
+            .is_some_and(|reason| reason.starts_with("version mismatch")));
+
+        let full = LobbyInfo {
+            players: 8,
+            ..lobby_info(friend())
+        };
+        assert_eq!(full.join_blocker(), Some("server is full".to_string()));
+
+        // only the owner publishes data
+        lobby_info(me()).publish(&steam, friend_lobby());
+        assert_eq!(LobbyInfo::read(&steam, friend_lobby()), Some(info));
+    }
+}
@@ -0,0 +1,436 @@
+//! Joining a friend's server through its Steam lobby.
+//!
+//! Used by the join screen and by accepted invites:
+//! 1. set `ClientConfigInfo.steam_connect_to` to the friend and their lobby, then switch to
+//!    [`MultiplayerState::JoiningLobby`]
+//! 2. we join the lobby and read the server SteamId from the lobby data (or fall back to the owner)
+//! 3. we connect to that SteamId through `SteamClientIo` in `MultiplayerState::Client`
+//! 4. the lobby is left when we get back to the menu
+//!
+//! Any failure (full lobby, closed lobby, version mismatch, host not answering) brings us back to
+//! the menu with the reason in `LastDisconnectReason`. If Steam answers a join after we stopped
+//! waiting for it, the lobby is left as soon as the answer arrives.
+//!
+//! The host also sets its rich presence `connect` string to `+connect_lobby <id>`. When a friend
+//! joins through it while the game isn't running, Steam starts the game with those arguments:
+//! [`split_launch_args`] removes them before clap sees them, and the lobby is joined as soon as
+//! the menu is up. If the game is already running, Steam sends the same string through a callback.
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use lightyear::prelude::*;
+use parking_lot::Mutex;
+use steamworks::LobbyId;
+
+use crate::networking::client::LastDisconnectReason;
+use crate::networking::lobby_data::LobbyInfo;
+use crate::networking::steam_backend::SteamPlatform;
+use crate::{ClientConfigInfo, GameState, MultiplayerState};
+
+/// Rich presence key Steam reads to build the launch arguments of a friend joining us
+pub const RICH_PRESENCE_CONNECT: &str = "connect";
+const CONNECT_LOBBY_ARG: &str = "+connect_lobby";
+
+/// How long we wait for Steam to answer the join request
+const LOBBY_JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+type LobbyJoinResult = Arc<Mutex<Option<Result<LobbyId, String>>>>;
+
+/// Lobby given on the command line, joined once the menu is up
+#[derive(Resource, Default)]
+pub struct LaunchLobby(pub Option<LobbyId>);
+
+/// The rich presence connect string pointing friends at `lobby`
+pub fn connect_string(lobby: LobbyId) -> String {
+    format!("{CONNECT_LOBBY_ARG} {}", lobby.raw())
+}
+
+/// Find `+connect_lobby <id>` in a rich presence connect string
+pub fn parse_connect_lobby(connect: &str) -> Option<LobbyId> {
+    let mut words = connect.split_whitespace();
+    words.find(|word| *word == CONNECT_LOBBY_ARG)?;
+    words.next()?.parse().ok().map(LobbyId::from_raw)
+}
+
+/// Remove `+connect_lobby <id>` from the process arguments, returning the others and the lobby
+pub fn split_launch_args(args: impl IntoIterator<Item = String>) -> (Vec<String>, Option<LobbyId>) {
+    let mut remaining = Vec::new();
+    let mut lobby = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == CONNECT_LOBBY_ARG {
+            match args.next().and_then(|id| id.parse().ok()) {
+                Some(id) => lobby = Some(LobbyId::from_raw(id)),
+                None => warn!("Ignoring {CONNECT_LOBBY_ARG} without a valid lobby id"),
+            }
+        } else {
+            remaining.push(arg);
+        }
+    }
+    (remaining, lobby)
+}
+
+/// The Steam lobby this client is in, if any
+#[derive(Resource, Default)]
+pub struct CurrentLobby {
+    pub lobby: Option<LobbyId>,
+    /// set once we are connected to the lobby's server, so we know if the host was reachable
+    reached_server: bool,
+    pending: Option<(LobbyJoinResult, Timer)>,
+    /// joins we gave up on, left if Steam still completes them
+    abandoned: Vec<LobbyJoinResult>,
+}
+
+pub(crate) struct LobbyJoinPlugin;
+
+impl Plugin for LobbyJoinPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CurrentLobby>();
+        app.init_resource::<LaunchLobby>();
+        app.add_systems(OnEnter(MultiplayerState::JoiningLobby), join_lobby);
+        app.add_systems(
+            Update,
+            wait_for_lobby_join.run_if(in_state(MultiplayerState::JoiningLobby)),
+        );
+        app.add_systems(Update, leave_abandoned_lobbies);
+        app.add_systems(OnEnter(GameState::Menu), leave_lobby);
+        app.add_observer(mark_server_reached);
+    }
+}
+
+fn join_lobby(
+    steam: Option<Res<SteamPlatform>>,
+    client_config: Res<ClientConfigInfo>,
+    mut current_lobby: ResMut<CurrentLobby>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let (Some(steam), Some((friend, lobby))) = (steam, client_config.steam_connect_to) else {
+        last_disconnect_reason.0 = Some("Steam is not available".to_string());
+        game_state.set(GameState::Menu);
+        return;
+    };
+    info!("Joining the lobby {lobby:?} of {friend:?}");
+    current_lobby.reached_server = false;
+    let result: LobbyJoinResult = Arc::new(Mutex::new(None));
+    let callback_result = result.clone();
+    steam.join_lobby(
+        lobby,
+        Box::new(move |joined| {
+            callback_result.lock().replace(joined);
+        }),
+    );
+    current_lobby.pending = Some((result, Timer::new(LOBBY_JOIN_TIMEOUT, TimerMode::Once)));
+}
+
+fn wait_for_lobby_join(
+    time: Res<Time>,
+    steam: Res<SteamPlatform>,
+    mut client_config: ResMut<ClientConfigInfo>,
+    mut current_lobby: ResMut<CurrentLobby>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
+) {
+    let Some((result, timer)) = current_lobby.pending.as_mut() else {
+        return;
+    };
+    let joined = result.lock().take();
+    let joined = match joined {
+        Some(joined) => joined,
+        None if timer.tick(time.delta()).finished() => {
+            let result = result.clone();
+            current_lobby.abandoned.push(result);
+            Err("Steam did not answer the join request".to_string())
+        }
+        None => return,
+    };
+    current_lobby.pending = None;
+
+    let server = joined.and_then(|lobby| {
+        current_lobby.lobby = Some(lobby);
+        match LobbyInfo::read(&*steam.0, lobby) {
+            Some(info) => match info.join_blocker() {
+                Some(blocker) => Err(blocker),
+                None => Ok(info.server),
+            },
+            // older hosts don't publish lobby data, their server runs on the lobby owner
+            None => steam
+                .lobby_owner(lobby)
+                .ok_or_else(|| "the lobby has no owner".to_string()),
+        }
+    });
+
+    match server {
+        Ok(server) => {
+            info!(
+                "Joined lobby {:?}, connecting to {server:?}",
+                current_lobby.lobby
+            );
+            let lobby = current_lobby.lobby.unwrap_or(LobbyId::from_raw(0));
+            client_config.steam_connect_to = Some((server, lobby));
+            multiplayer_state.set(MultiplayerState::Client);
+        }
+        Err(e) => {
+            warn!("Could not join the lobby: {e}");
+            last_disconnect_reason.0 = Some(format!("Could not join lobby: {e}"));
+            game_state.set(GameState::Menu);
+        }
+    }
+}
+
+fn mark_server_reached(
+    trigger: Trigger<OnAdd, Connected>,
+    client: Query<(), With<Client>>,
+    mut current_lobby: ResMut<CurrentLobby>,
+) {
+    if client.get(trigger.target()).is_ok() {
+        current_lobby.reached_server = true;
+    }
+}
+
+fn leave_lobby(
+    steam: Option<Res<SteamPlatform>>,
+    mut current_lobby: ResMut<CurrentLobby>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+) {
+    if let Some((result, _)) = current_lobby.pending.take() {
+        current_lobby.abandoned.push(result);
+    }
+    let Some(lobby) = current_lobby.lobby.take() else {
+        return;
+    };
+    info!("Leaving lobby {lobby:?}");
+    if let Some(steam) = steam {
+        steam.leave_lobby(lobby);
+    }
+    if !current_lobby.reached_server && last_disconnect_reason.0.is_none() {
+        last_disconnect_reason.0 = Some("Could not reach the host".to_string());
+    }
+}
+
+/// Leave the lobbies Steam let us into after we stopped waiting for them
+fn leave_abandoned_lobbies(
+    steam: Option<Res<SteamPlatform>>,
+    mut current_lobby: ResMut<CurrentLobby>,
+) {
+    current_lobby.abandoned.retain(|result| {
+        let Some(joined) = result.lock().take() else {
+            return true;
+        };
+        if let (Ok(lobby), Some(steam)) = (joined, steam.as_ref()) {
+            info!("Leaving lobby {lobby:?}, joined after the timeout");
+            steam.leave_lobby(lobby);
+        }
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+    use bevy::time::TimeUpdateStrategy;
+    use lightyear::prelude::SteamId;
+
+    use super::*;
+    use crate::networking::shared::GAME_VERSION;
+    use crate::networking::steam_backend::{MockSteamBackend, SteamBackend};
+
+    fn me() -> SteamId {
+        SteamId::from_raw(100)
+    }
+
+    fn friend() -> SteamId {
+        SteamId::from_raw(200)
+    }
+
+    /// The dedicated server the friend's lobby points at
+    fn server() -> SteamId {
+        SteamId::from_raw(300)
+    }
+
+    fn friend_lobby() -> LobbyId {
+        LobbyId::from_raw(7)
+    }
+
+    fn add_friend(steam: &MockSteamBackend, version: &str) {
+        let server = server().raw().to_string();
+        steam.add_friend(
+            "Friend",
+            friend(),
+            friend_lobby(),
+            &[
+                ("server", server.as_str()),
+                ("version", version),
+                ("players", "1"),
+                ("max_players", "8"),
+            ],
+        );
+    }
+
+    fn lobby_app(steam: &Arc<MockSteamBackend>) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin));
+        // `Time<Virtual>` never advances more than 250ms per frame
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            200,
+        )));
+        app.init_state::<GameState>();
+        app.init_state::<MultiplayerState>();
+        app.init_resource::<ClientConfigInfo>();
+        app.init_resource::<LastDisconnectReason>();
+        app.insert_resource(SteamPlatform(steam.clone()));
+        app.add_plugins(LobbyJoinPlugin);
+        app.update();
+        app
+    }
+
+    /// What the join screen does when a friend's lobby is clicked
+    fn start_joining(app: &mut App) {
+        app.world_mut()
+            .resource_mut::<ClientConfigInfo>()
+            .steam_connect_to = Some((friend(), friend_lobby()));
+        app.world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Lobby);
+        app.world_mut()
+            .resource_mut::<NextState<MultiplayerState>>()
+            .set(MultiplayerState::JoiningLobby);
+    }
+
+    /// One frame, with the steam callbacks delivered first like `steam_callbacks` does
+    fn frame(app: &mut App, steam: &MockSteamBackend) {
+        steam.run_callbacks();
+        app.update();
+    }
+
+    fn game_state(app: &App) -> GameState {
+        *app.world().resource::<State<GameState>>().get()
+    }
+
+    fn multiplayer_state(app: &App) -> MultiplayerState {
+        *app.world().resource::<State<MultiplayerState>>().get()
+    }
+
+    fn disconnect_reason(app: &App) -> Option<String> {
+        app.world().resource::<LastDisconnectReason>().0.clone()
+    }
+
+    #[test]
+    fn joins_the_lobby_and_connects_to_its_server() {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        add_friend(&steam, GAME_VERSION);
+        let mut app = lobby_app(&steam);
+
+        start_joining(&mut app);
+        for _ in 0..3 {
+            frame(&mut app, &steam);
+        }
+
+        assert_eq!(multiplayer_state(&app), MultiplayerState::Client);
+        assert_eq!(
+            app.world().resource::<ClientConfigInfo>().steam_connect_to,
+            Some((server(), friend_lobby()))
+        );
+        assert_eq!(
+            app.world().resource::<CurrentLobby>().lobby,
+            Some(friend_lobby())
+        );
+        assert_eq!(
+            steam.lobby(friend_lobby()).unwrap().members,
+            vec![friend(), me()]
+        );
+
+        // back to the menu: the lobby is left
+        app.world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Menu);
+        frame(&mut app, &steam);
+        assert_eq!(app.world().resource::<CurrentLobby>().lobby, None);
+        assert_eq!(steam.lobby(friend_lobby()).unwrap().members, vec![friend()]);
+    }
+
+    #[test]
+    fn full_lobby_goes_back_to_the_menu() {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        add_friend(&steam, GAME_VERSION);
+        steam.set_max_members(friend_lobby(), 1);
+        let mut app = lobby_app(&steam);
+
+        start_joining(&mut app);
+        for _ in 0..3 {
+            frame(&mut app, &steam);
+        }
+
+        assert_eq!(game_state(&app), GameState::Menu);
+        assert_eq!(
+            disconnect_reason(&app),
+            Some("Could not join lobby: lobby is full".to_string())
+        );
+        assert_eq!(steam.lobby(friend_lobby()).unwrap().members, vec![friend()]);
+    }
+
+    #[test]
+    fn missing_lobby_goes_back_to_the_menu() {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        let mut app = lobby_app(&steam);
+
+        start_joining(&mut app);
+        for _ in 0..3 {
+            frame(&mut app, &steam);
+        }
+
+        assert_eq!(game_state(&app), GameState::Menu);
+        assert_eq!(
+            disconnect_reason(&app),
+            Some("Could not join lobby: lobby no longer exists".to_string())
+        );
+    }
+
+    #[test]
+    fn version_mismatch_goes_back_to_the_menu() {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        add_friend(&steam, "0.0.0-other");
+        let mut app = lobby_app(&steam);
+
+        start_joining(&mut app);
+        for _ in 0..3 {
+            frame(&mut app, &steam);
+        }
+
+        assert_eq!(game_state(&app), GameState::Menu);
+        assert!(disconnect_reason(&app)
+            .is_some_and(|reason| reason.starts_with("Could not join lobby: version mismatch")));
+    }
+
+    #[test]
+    fn join_times_out_when_steam_does_not_answer() {
+        let steam = Arc::new(MockSteamBackend::new(me()));
+        add_friend(&steam, GAME_VERSION);
+        let mut app = lobby_app(&steam);
+
+        start_joining(&mut app);
+        // the callbacks are never run, so the join result never arrives
+        let frames_to_timeout = (LOBBY_JOIN_TIMEOUT.as_millis() / 200) as usize;
+        for _ in 0..frames_to_timeout - 5 {
+            app.update();
+        }
+        assert_eq!(game_state(&app), GameState::Lobby);
+        for _ in 0..10 {
+            app.update();
+        }
+
+        assert_eq!(game_state(&app), GameState::Menu);
+        assert_eq!(
+            disconnect_reason(&app),
+            Some("Could not join lobby: Steam did not answer the join request".to_string())
+        );
+
+        // Steam lets us in after all: the lobby is left right away
+        frame(&mut app, &steam);
+        assert_eq!(steam.lobby(friend_lobby()).unwrap().members, vec![friend()]);
+        assert_eq!(app.world().resource::<CurrentLobby>().lobby, None);
+        assert!(app.world().resource::<CurrentLobby>().abandoned.is_empty());
+    }
+}
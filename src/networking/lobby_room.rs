@@ -0,0 +1,490 @@
+//! Pre-match lobby.
+//!
+//! After connecting, players wait in [`GameState::Lobby`]. The server spawns a replicated
+//! [`MatchRoom`] entity holding the mode, map and whether the match has started, and every
+//! `Player` carries a replicated [`Ready`] status. The roster is read from the room's [`Roster`],
+//! so all clients show every player, even the ones whose ship they don't see.
+//!
+//! Clients send [`LobbyRequest`]s: everyone can toggle ready, only the host (the player connected
+//! through crossbeam) can pick the mode and map and start the match, once the others are ready.
+//! A dedicated server has no host, so it starts on its own when every player is ready.
+//!
+//! Clients follow `MatchRoom.started` to switch between the lobby and the game, which also lets
+//! players joining a running match go straight in.
+//!
+//! The host also gets a Kick button next to every other player of the roster.
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::menu::NORMAL_BUTTON;
+use crate::networking::client::ClientStartupResources;
+use crate::networking::game_mode::GameModes;
+use crate::networking::host_migration::{HostMigration, MigrationSeed};
+use crate::networking::lobby_data::LobbySettings;
+use crate::networking::moderation::{BanTarget, ModerationCommand};
+use crate::networking::protocol::{
+    ConnectionKind, ControlChannel, FriendlyFire, LobbyRequest, MatchRoom, Player, Ready, Roster,
+    Team,
+};
+use crate::networking::shared::MAPS;
+use crate::{
+    ClientCommands, ClientConfigInfo, GameCleanUp, GameState, MultiplayerState, TEXT_COLOR,
+};
+
+pub(crate) struct LobbyRoomServerPlugin {
+    /// a dedicated server starts the match by itself once everyone is ready
+    pub auto_start: bool,
+}
+
+impl Plugin for LobbyRoomServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(MultiplayerState::Server), spawn_match_room);
+        app.add_systems(
+            Update,
+            handle_lobby_requests.run_if(in_state(MultiplayerState::Server)),
+        );
+        if self.auto_start {
+            app.add_systems(
+                Update,
+                auto_start_match
+                    .run_if(in_state(MultiplayerState::Server).and(in_state(GameState::Lobby))),
+            );
+        }
+    }
+}
+
+fn spawn_match_room(
+    mut commands: Commands,
+    settings: Res<LobbySettings>,
+    seed: Option<Res<MigrationSeed>>,
+) {
+    // after a host migration the match was already running
+    let started = seed.is_some();
+    commands.spawn((
+        MatchRoom {
+            mode: settings.mode.clone(),
+            map: settings.map.clone(),
+            started,
+            teams: settings.teams,
+            friendly_fire: settings.friendly_fire,
+        },
+        Roster::default(),
+        Name::new("MatchRoom"),
+        Replicate::to_clients(NetworkTarget::All),
+        GameCleanUp,
+    ));
+}
+
+fn handle_lobby_requests(
+    mut links: Query<(Entity, &mut MessageReceiver<LobbyRequest>), With<ClientOf>>,
+    mut players: Query<(&ControlledBy, &ConnectionKind, &mut Ready), With<Player>>,
+    mut room: Single<&mut MatchRoom>,
+    mut settings: ResMut<LobbySettings>,
+    modes: Res<GameModes>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for (link, mut receiver) in links.iter_mut() {
+        for request in receiver.receive() {
+            let is_host = players.iter().any(|(controlled, kind, _)| {
+                controlled.owner == link && *kind == ConnectionKind::Local
+            });
+            match request {
+                LobbyRequest::SetReady(ready) => {
+                    if let Some((.., mut player_ready)) = players
+                        .iter_mut()
+                        .find(|(controlled, ..)| controlled.owner == link)
+                    {
+                        player_ready.0 = ready;
+                    }
+                }
+                LobbyRequest::SetMode(mode)
+                    if is_host && !room.started && modes.get(&mode).is_some() =>
+                {
+                    settings.mode = mode.clone();
+                    room.mode = mode;
+                }
+                LobbyRequest::SetMap(map)
+                    if is_host && !room.started && MAPS.contains(&map.as_str()) =>
+                {
+                    settings.map = map.clone();
+                    room.map = map;
+                }
+                LobbyRequest::SetTeams(teams) if is_host && !room.started => {
+                    settings.teams = teams;
+                    room.teams = teams;
+                }
+                LobbyRequest::SetFriendlyFire(friendly_fire) if is_host && !room.started => {
+                    settings.friendly_fire = friendly_fire;
+                    room.friendly_fire = friendly_fire;
+                }
+                LobbyRequest::StartMatch if is_host && !room.started => {
+                    let everyone_ready = players
+                        .iter()
+                        .all(|(_, kind, ready)| ready.0 || *kind == ConnectionKind::Local);
+                    if everyone_ready {
+                        info!("Host started the match: {} on {}", room.mode, room.map);
+                        room.started = true;
+                        game_state.set(GameState::Game);
+                    }
+                }
+                request => {
+                    warn!("Ignoring lobby request {request:?} from {link:?}");
+                }
+            }
+        }
+    }
+}
+
+fn auto_start_match(
+    players: Query<&Ready, With<Player>>,
+    mut room: Single<&mut MatchRoom>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if room.started || players.is_empty() || players.iter().any(|ready| !ready.0) {
+        return;
+    }
+    info!(
+        "Everyone is ready, starting the match: {} on {}",
+        room.mode, room.map
+    );
+    room.started = true;
+    game_state.set(GameState::Game);
+}
+
+pub(crate) struct LobbyRoomClientPlugin;
+
+impl Plugin for LobbyRoomClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Lobby), spawn_lobby_screen);
+        app.add_systems(
+            OnExit(GameState::Lobby),
+            crate::despawn_screen::<OnLobbyScreen>,
+        );
+        app.add_systems(
+            Update,
+            (lobby_button_action, kick_button_action, update_lobby_screen)
+                .run_if(in_state(GameState::Lobby)),
+        );
+        app.add_systems(
+            Update,
+            follow_match_room.run_if(in_state(GameState::Lobby).or(in_state(GameState::Game))),
+        );
+    }
+}
+
+#[derive(Component)]
+struct OnLobbyScreen;
+
+#[derive(Component)]
+struct RoomSettingsText;
+
+#[derive(Component)]
+struct RosterText;
+
+/// Host only, kicks the player of this roster row
+#[derive(Component)]
+struct KickButton(PeerId);
+
+#[derive(Component)]
+enum LobbyButtonAction {
+    ToggleReady,
+    CycleMode,
+    CycleMap,
+    ToggleTeams,
+    CycleFriendlyFire,
+    Start,
+    Leave,
+}
+
+fn spawn_lobby_screen(mut commands: Commands, client_config: Res<ClientConfigInfo>) {
+    let button_node = Node {
+        width: Val::Px(240.0),
+        height: Val::Px(55.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_font = TextFont {
+        font_size: 28.0,
+        ..default()
+    };
+    // the host plays on the machine running the server
+    let is_host = client_config.seperate_mode;
+    let buttons = if is_host {
+        vec![
+            ("Mode", LobbyButtonAction::CycleMode),
+            ("Map", LobbyButtonAction::CycleMap),
+            ("Teams", LobbyButtonAction::ToggleTeams),
+            ("Friendly fire", LobbyButtonAction::CycleFriendlyFire),
+            ("Start", LobbyButtonAction::Start),
+            ("Leave", LobbyButtonAction::Leave),
+        ]
+    } else if client_config.spectator {
+        vec![("Leave", LobbyButtonAction::Leave)]
+    } else {
+        vec![
+            ("Ready", LobbyButtonAction::ToggleReady),
+            ("Leave", LobbyButtonAction::Leave),
+        ]
+    };
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(Color::srgb(36.0 / 255.0, 22.0 / 255.0, 39.0 / 255.0)),
+            OnLobbyScreen,
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Lobby"),
+                TextFont {
+                    font_size: 67.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR),
+                Node {
+                    margin: UiRect::all(Val::Px(30.0)),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 28.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR),
+                RoomSettingsText,
+            ));
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    margin: UiRect::all(Val::Px(20.0)),
+                    min_height: Val::Px(200.0),
+                    ..default()
+                },
+                RosterText,
+            ));
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (label, action) in buttons {
+                        parent
+                            .spawn((
+                                Button,
+                                button_node.clone(),
+                                BackgroundColor(NORMAL_BUTTON),
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    Text::new(label),
+                                    button_text_font.clone(),
+                                    TextColor(TEXT_COLOR),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+fn lobby_button_action(
+    interaction_query: Query<(&Interaction, &LobbyButtonAction), Changed<Interaction>>,
+    mut client: Query<(Entity, &mut MessageSender<LobbyRequest>), (With<Client>, With<Connected>)>,
+    local_player: Query<&Ready, (With<Predicted>, With<Controlled>)>,
+    room: Query<&MatchRoom>,
+    modes: Res<GameModes>,
+    mut host_migration: ResMut<HostMigration>,
+    mut commands: Commands,
+) {
+    let Ok((client, mut sender)) = client.single_mut() else {
+        return;
+    };
+    for (interaction, action) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let request = match action {
+            LobbyButtonAction::ToggleReady => {
+                let ready = local_player.single().is_ok_and(|ready| ready.0);
+                LobbyRequest::SetReady(!ready)
+            }
+            LobbyButtonAction::CycleMode => {
+                let Ok(room) = room.single() else { continue };
+                LobbyRequest::SetMode(next_in(&modes.names(), &room.mode).to_string())
+            }
+            LobbyButtonAction::CycleMap => {
+                let Ok(room) = room.single() else { continue };
+                LobbyRequest::SetMap(next_in(MAPS, &room.map).to_string())
+            }
+            LobbyButtonAction::ToggleTeams => {
+                let Ok(room) = room.single() else { continue };
+                LobbyRequest::SetTeams(!room.teams)
+            }
+            LobbyButtonAction::CycleFriendlyFire => {
+                let Ok(room) = room.single() else { continue };
+                let index = FriendlyFire::ALL
+                    .iter()
+                    .position(|option| *option == room.friendly_fire)
+                    .map_or(0, |index| (index + 1) % FriendlyFire::ALL.len());
+                LobbyRequest::SetFriendlyFire(FriendlyFire::ALL[index])
+            }
+            LobbyButtonAction::Start => LobbyRequest::StartMatch,
+            LobbyButtonAction::Leave => {
+                host_migration.plan = None;
+                commands.trigger_targets(Disconnect, client);
+                continue;
+            }
+        };
+        sender.send::<ControlChannel>(request);
+    }
+}
+
+fn kick_button_action(
+    interaction_query: Query<(&Interaction, &KickButton), Changed<Interaction>>,
+    client_startup: Res<ClientStartupResources>,
+) {
+    for (interaction, kick) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(sender) = &client_startup.client_sender_commands else {
+            continue;
+        };
+        let _ = sender.send(ClientCommands::Moderation(ModerationCommand::Kick {
+            target: BanTarget::Peer(kick.0),
+            reason: "kicked by the host".to_string(),
+        }));
+    }
+}
+
+fn next_in<'a>(options: &[&'a str], current: &str) -> &'a str {
+    let index = options.iter().position(|option| *option == current);
+    options[index.map_or(0, |index| (index + 1) % options.len())]
+}
+
+fn update_lobby_screen(
+    client_config: Res<ClientConfigInfo>,
+    room: Query<&MatchRoom>,
+    rosters: Query<&Roster>,
+    mut settings_text: Single<&mut Text, With<RoomSettingsText>>,
+    roster: Single<Entity, With<RosterText>>,
+    mut shown_roster: Local<Vec<(PeerId, String, bool, bool, Option<Team>)>>,
+    mut commands: Commands,
+) {
+    let settings = match room.single() {
+        Ok(room) if room.teams => format!(
+            "Mode: {}    Map: {}    Teams (friendly fire {})",
+            room.mode,
+            room.map,
+            room.friendly_fire.label()
+        ),
+        Ok(room) => format!("Mode: {}    Map: {}", room.mode, room.map),
+        Err(_) => "Waiting for the server...".to_string(),
+    };
+    if settings_text.0 != settings {
+        settings_text.0 = settings;
+    }
+
+    let rows: Vec<_> = rosters.iter().flat_map(|roster| roster.0.iter()).collect();
+    let roster_state: Vec<_> = rows
+        .iter()
+        .map(|entry| {
+            (
+                entry.client_id,
+                entry.nickname.clone(),
+                entry.ready,
+                entry.connection_kind == Some(ConnectionKind::Local),
+                entry.team,
+            )
+        })
+        .collect();
+    if *shown_roster == roster_state {
+        return;
+    }
+    *shown_roster = roster_state;
+
+    let is_host = client_config.seperate_mode;
+    commands.entity(*roster).despawn_related::<Children>();
+    commands.entity(*roster).with_children(|parent| {
+        for entry in rows {
+            let is_local = entry.connection_kind == Some(ConnectionKind::Local);
+            let status = if is_local {
+                "host"
+            } else if entry.ready {
+                "ready"
+            } else {
+                "not ready"
+            };
+            let team = entry
+                .team
+                .map_or(String::new(), |team| format!("  -  {}", team.label()));
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(12.0),
+                    ..default()
+                })
+                .with_children(|row| {
+                    row.spawn((
+                        Text::new(format!("{}{team}  -  {status}", entry.nickname)),
+                        TextFont {
+                            font_size: 26.0,
+                            ..default()
+                        },
+                        TextColor(entry.color),
+                    ));
+                    if is_host && !is_local {
+                        row.spawn((
+                            Button,
+                            Node {
+                                padding: UiRect::axes(Val::Px(8.0), Val::Px(2.0)),
+                                ..default()
+                            },
+                            BackgroundColor(NORMAL_BUTTON),
+                            KickButton(entry.client_id),
+                        ))
+                        .with_children(|button| {
+                            button.spawn((
+                                Text::new("Kick"),
+                                TextFont {
+                                    font_size: 20.0,
+                                    ..default()
+                                },
+                                TextColor(TEXT_COLOR),
+                            ));
+                        });
+                    }
+                });
+        }
+    });
+}
+
+/// Switch between the lobby and the game when the server starts or ends the match
+fn follow_match_room(
+    room: Query<&MatchRoom, Changed<MatchRoom>>,
+    state: Res<State<GameState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Ok(room) = room.single() else {
+        return;
+    };
+    match (room.started, state.get()) {
+        (true, GameState::Lobby) => game_state.set(GameState::Game),
+        (false, GameState::Game) => game_state.set(GameState::Lobby),
+        _ => {}
+    }
+}
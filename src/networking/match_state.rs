@@ -0,0 +1,310 @@
+//! Match lifecycle: warmup, live round and results.
+//!
+//! Once the host starts the match, the server adds a replicated [`MatchState`] to the
+//! `MatchRoom` entity and runs rounds in a loop:
+//! - warmup: players can fly around and shoot, but hits don't score
+//! - live: until the time runs out or the game mode says someone reached the score limit
+//! - ended: clients show the results ranked by `Score`, then the server triggers
+//!   [`ResetRound`] to reset the players and balls, and the next warmup starts
+//!
+//! The countdown is replicated in whole seconds, so it only changes once per second.
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::networking::chat::ChatAnnouncement;
+use crate::networking::game_mode::{ActiveGameMode, ResetRound};
+use crate::networking::host_migration::MigrationSeed;
+use crate::networking::protocol::{
+    MatchPhase, MatchRoom, MatchState, Player, Roster, Score, Team, TeamGoals,
+};
+use crate::networking::teams::{standings, PlayerScore};
+use crate::{GameCleanUp, GameState, TEXT_COLOR};
+
+/// Length of each phase and score limit of the rounds
+#[derive(Resource, Clone, Debug)]
+pub struct MatchRules {
+    pub warmup: Duration,
+    pub round_length: Duration,
+    /// how long the results are shown
+    pub results: Duration,
+    pub score_limit: i32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            warmup: Duration::from_secs(10),
+            round_length: Duration::from_secs(5 * 60),
+            results: Duration::from_secs(10),
+            score_limit: 10,
+        }
+    }
+}
+
+impl MatchRules {
+    fn length(&self, phase: MatchPhase) -> Duration {
+        match phase {
+            MatchPhase::Warmup => self.warmup,
+            MatchPhase::Live => self.round_length,
+            MatchPhase::Ended => self.results,
+        }
+    }
+}
+
+/// Time left in the current phase, the server's copy of `MatchState::seconds_left`
+#[derive(Resource)]
+struct MatchClock(Timer);
+
+pub(crate) struct MatchStateServerPlugin;
+
+impl Plugin for MatchStateServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchRules>();
+        app.add_systems(
+            Update,
+            (
+                begin_match,
+                advance_match.run_if(resource_exists::<MatchClock>),
+            )
+                .chain()
+                .run_if(in_state(GameState::Game).and(resource_exists::<ActiveGameMode>)),
+        );
+    }
+}
+
+fn begin_match(
+    rules: Res<MatchRules>,
+    seed: Option<Res<MigrationSeed>>,
+    room: Query<(Entity, &MatchRoom), Without<MatchState>>,
+    mut commands: Commands,
+) {
+    let Ok((entity, room)) = room.single() else {
+        return;
+    };
+    if !room.started {
+        return;
+    }
+    // after a host migration the round was already running
+    let phase = if seed.is_some() {
+        MatchPhase::Live
+    } else {
+        MatchPhase::Warmup
+    };
+    let length = rules.length(phase);
+    commands.insert_resource(MatchClock(Timer::new(length, TimerMode::Once)));
+    commands.entity(entity).insert(MatchState {
+        phase,
+        seconds_left: length.as_secs() as u32,
+        score_limit: rules.score_limit,
+    });
+}
+
+fn advance_match(
+    time: Res<Time>,
+    rules: Res<MatchRules>,
+    mode: Res<ActiveGameMode>,
+    mut clock: ResMut<MatchClock>,
+    mut state: Single<&mut MatchState>,
+    players: Query<(&Player, &Score, Option<&Team>)>,
+    goals: Query<&TeamGoals>,
+    mut announcements: EventWriter<ChatAnnouncement>,
+    mut commands: Commands,
+) {
+    clock.0.tick(time.delta());
+    let scores: Vec<_> = players
+        .iter()
+        .map(|(player, score, team)| PlayerScore {
+            nickname: &player.nickname,
+            score: score.0,
+            team: team.copied(),
+        })
+        .collect();
+    let winner = match state.phase {
+        MatchPhase::Live => mode
+            .0
+            .round_winner(&scores, goals.single().ok(), state.score_limit),
+        _ => None,
+    };
+
+    if clock.0.finished() || winner.is_some() {
+        let next = match state.phase {
+            MatchPhase::Warmup => {
+                announcements.write(ChatAnnouncement(format!(
+                    "Round started, first to {} wins",
+                    state.score_limit
+                )));
+                MatchPhase::Live
+            }
+            MatchPhase::Live => {
+                let announcement = match winner.or_else(|| leader(&scores, goals.single().ok())) {
+                    Some(winner) => format!("{winner} won the round!"),
+                    None => "The round is a draw".to_string(),
+                };
+                announcements.write(ChatAnnouncement(announcement));
+                MatchPhase::Ended
+            }
+            MatchPhase::Ended => {
+                commands.trigger(ResetRound);
+                MatchPhase::Warmup
+            }
+        };
+        clock.0 = Timer::new(rules.length(next), TimerMode::Once);
+        state.phase = next;
+        state.score_limit = rules.score_limit;
+    }
+
+    let seconds_left = clock.0.remaining().as_secs_f32().ceil() as u32;
+    if state.seconds_left != seconds_left {
+        state.seconds_left = seconds_left;
+    }
+}
+
+/// The player or team with the best score, None if nobody played or the best score is tied
+fn leader(scores: &[PlayerScore], goals: Option<&TeamGoals>) -> Option<String> {
+    let standings = standings(scores, goals);
+    match standings.as_slice() {
+        [(name, _)] => Some(name.clone()),
+        [(name, first), (_, second), ..] if first > second => Some(name.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) struct MatchStateClientPlugin;
+
+impl Plugin for MatchStateClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), spawn_match_hud);
+        app.add_systems(
+            Update,
+            (update_match_clock, update_results).run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct MatchClockText;
+
+#[derive(Component)]
+struct ResultsScreen;
+
+#[derive(Component)]
+struct ResultsText;
+
+fn spawn_match_hud(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(12.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR),
+                MatchClockText,
+            ));
+        });
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(120.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            Visibility::Hidden,
+            ResultsScreen,
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        padding: UiRect::all(Val::Px(20.0)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(""),
+                        TextFont {
+                            font_size: 26.0,
+                            ..default()
+                        },
+                        TextColor(TEXT_COLOR),
+                        ResultsText,
+                    ));
+                });
+        });
+}
+
+fn update_match_clock(
+    state: Query<&MatchState>,
+    mut text: Single<&mut Text, With<MatchClockText>>,
+) {
+    let Ok(state) = state.single() else {
+        return;
+    };
+    let clock = format!("{}:{:02}", state.seconds_left / 60, state.seconds_left % 60);
+    let label = match state.phase {
+        MatchPhase::Warmup => format!("Warmup {clock}"),
+        MatchPhase::Live => format!("{clock} - first to {}", state.score_limit),
+        MatchPhase::Ended => format!("Next round in {clock}"),
+    };
+    if text.0 != label {
+        text.0 = label;
+    }
+}
+
+fn update_results(
+    state: Query<&MatchState>,
+    roster: Query<&Roster>,
+    goals: Query<&TeamGoals>,
+    mut screen: Single<&mut Visibility, With<ResultsScreen>>,
+    mut text: Single<&mut Text, With<ResultsText>>,
+) {
+    let ended = state
+        .single()
+        .is_ok_and(|state| state.phase == MatchPhase::Ended);
+    if !ended {
+        screen.set_if_neq(Visibility::Hidden);
+        return;
+    }
+    screen.set_if_neq(Visibility::Inherited);
+
+    let mut rows: Vec<_> = roster.iter().flat_map(|roster| roster.0.iter()).collect();
+    rows.sort_by(|a, b| b.score.cmp(&a.score));
+    let mut results = "Round over\n\n".to_string();
+    if rows.iter().any(|entry| entry.team.is_some()) {
+        let scores: Vec<_> = rows.iter().map(|entry| PlayerScore::from(*entry)).collect();
+        for (team, score) in standings(&scores, goals.single().ok()) {
+            results.push_str(&format!("{team:<24} {score:>4}\n"));
+        }
+        results.push('\n');
+    }
+    for (rank, entry) in rows.iter().enumerate() {
+        results.push_str(&format!(
+            "{:>2}. {:<20} {:>4} {}\n",
+            rank + 1,
+            entry.nickname,
+            entry.score,
+            entry.team.map_or("", |team| team.label()),
+        ));
+    }
+    if text.0 != results {
+        text.0 = results;
+    }
+}
@@ -7,8 +7,33 @@ pub mod client;
 pub mod server;
 pub mod shared;
 pub mod protocol;
+pub mod quantize;
 pub mod renderer;
 pub mod entity_label;
+pub mod chat;
+pub mod console;
+pub mod game_mode;
+pub mod handshake;
+pub mod health;
+pub mod host_migration;
+pub mod interest;
+pub mod lag_compensation;
+pub mod lobby_data;
+pub mod lobby_join;
+pub mod lobby_room;
+pub mod match_state;
+pub mod moderation;
+pub mod network_settings;
+pub mod network_stats;
+pub mod power_ups;
+pub mod priority;
+pub mod scoreboard;
+pub mod shutdown_notice;
+pub mod soccer;
+pub mod spectator;
+pub mod steam_backend;
+pub mod teams;
+pub mod weapons;
 
 use client::ExampleClientPlugin;
 use lightyear::prelude::client::ClientPlugins;
@@ -17,7 +42,10 @@ use lightyear::prelude::InterpolationRegistry;
 use parking_lot::Mutex;
 use shared::*;
 
+use crate::networking::network_stats::NetworkStatsOverlayPlugin;
+use crate::networking::quantize::PacketCompression;
 use crate::networking::renderer::ExampleRendererPlugin;
+use crate::networking::scoreboard::ScoreboardPlugin;
 use crate::ClientCommands;
 use crate::ServerCommands;
 
@@ -28,6 +56,7 @@ pub(crate) struct NetworkingPlugin {
     pub server_receive_commands: Option<crossbeam_channel::Receiver<ServerCommands>>,
     pub steam: Option<lightyear::prelude::steamworks::Client>,
     pub wrapped_single_client: Option<Arc<Mutex<lightyear::prelude::steamworks::SingleClient>>>,
+    pub compression: PacketCompression,
 }
 
 impl Plugin for NetworkingPlugin {
@@ -41,7 +70,10 @@ impl Plugin for NetworkingPlugin {
 
        
 
-        app.add_plugins(SharedPlugin { show_confirmed: true});
+        app.add_plugins(SharedPlugin {
+            show_confirmed: true,
+            compression: self.compression,
+        });
        
         
         app.add_plugins(ExampleClientPlugin { client_crossbeam: self.client_crossbeam.clone(), 
@@ -53,6 +85,10 @@ impl Plugin for NetworkingPlugin {
 
 
          app.add_plugins(ExampleRendererPlugin);
+
+         app.add_plugins(NetworkStatsOverlayPlugin);
+
+         app.add_plugins(ScoreboardPlugin);
          
     }
 }
@@ -0,0 +1,390 @@
+//! Kicking and banning players.
+//!
+//! Players can be targeted by `PeerId`, SteamId or IP. Bans are stored in [`BAN_LIST_PATH`]
+//! next to the executable, one per line, and loaded when the server starts:
+//!
+//! ```text
+//! steam:76561198000000000 1767225600 griefing
+//! ip:203.0.113.7 never spamming
+//! ```
+//!
+//! Banned peers are rejected in `handle_connections` before they get a ship, and every kicked
+//! client receives a [`KickNotice`] with the reason before being disconnected.
+//!
+//! The commands come from the lobby, the chat or the server console (see `console`).
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::crossbeam::CrossbeamIo;
+use lightyear::prelude::*;
+
+use crate::networking::client::LastDisconnectReason;
+use crate::networking::host_migration::HostMigration;
+use crate::networking::protocol::{HandshakeChannel, KickNotice};
+use crate::networking::shared::peer_label;
+
+pub const BAN_LIST_PATH: &str = "bans.txt";
+
+/// [`BAN_LIST_PATH`] in the directory of the executable, or the working directory if that
+/// can't be found
+pub fn ban_list_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(BAN_LIST_PATH)))
+        .unwrap_or_else(|| PathBuf::from(BAN_LIST_PATH))
+}
+
+/// Time given to the KickNotice to reach the client before the link is closed
+const KICK_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanTarget {
+    Peer(PeerId),
+    Steam(u64),
+    Ip(IpAddr),
+}
+
+impl BanTarget {
+    fn matches(&self, peer: PeerId, ip: Option<IpAddr>) -> bool {
+        match *self {
+            BanTarget::Peer(banned) => banned == peer,
+            BanTarget::Steam(steam_id) => peer == PeerId::Steam(steam_id),
+            BanTarget::Ip(banned) => ip == Some(banned),
+        }
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Peer(PeerId::Steam(id)) | BanTarget::Steam(id) => write!(f, "steam:{id}"),
+            BanTarget::Peer(peer) => write!(f, "{}", peer_label(*peer)),
+            BanTarget::Ip(ip) => write!(f, "ip:{ip}"),
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected netcode:<id>, steam:<id> or ip:<addr>, got {s}"))?;
+        match kind {
+            "netcode" => value
+                .parse()
+                .map(|id| BanTarget::Peer(PeerId::Netcode(id)))
+                .map_err(|e| format!("invalid netcode id {value}: {e}")),
+            "steam" => value
+                .parse()
+                .map(BanTarget::Steam)
+                .map_err(|e| format!("invalid steam id {value}: {e}")),
+            "ip" => value
+                .parse()
+                .map(BanTarget::Ip)
+                .map_err(|e| format!("invalid ip {value}: {e}")),
+            _ => Err(format!("unknown target kind {kind}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// unix timestamp in seconds, `None` for a permanent ban
+    pub expires_at: Option<u64>,
+    pub reason: String,
+}
+
+impl Ban {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Message shown to the banned player
+    pub fn describe(&self, now: u64) -> String {
+        match self.expires_at {
+            None => format!("Banned: {}", self.reason),
+            Some(expires_at) => format!(
+                "Banned for {}: {}",
+                format_duration(Duration::from_secs(expires_at.saturating_sub(now))),
+                self.reason
+            ),
+        }
+    }
+
+    pub fn describe_now(&self) -> String {
+        self.describe(unix_now())
+    }
+
+    fn to_line(&self) -> String {
+        let expires_at = self
+            .expires_at
+            .map_or("never".to_string(), |t| t.to_string());
+        format!("{} {} {}", self.target, expires_at, self.reason)
+    }
+
+    fn from_line(line: &str) -> Result<Self, String> {
+        let mut parts = line.splitn(3, ' ');
+        let target = parts.next().unwrap_or_default().parse()?;
+        let expires_at = match parts.next() {
+            None | Some("never") => None,
+            Some(t) => Some(t.parse().map_err(|e| format!("invalid expiry {t}: {e}"))?),
+        };
+        let reason = parts.next().unwrap_or("no reason given").to_string();
+        Ok(Self {
+            target,
+            expires_at,
+            reason,
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct BanList {
+    pub path: PathBuf,
+    pub bans: Vec<Ban>,
+}
+
+impl BanList {
+    /// Load the bans from disk. A missing file is an empty list.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut bans = Vec::new();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match Ban::from_line(line) {
+                    Ok(ban) => bans.push(ban),
+                    Err(e) => warn!("Skipping invalid ban in {}: {e}", path.display()),
+                }
+            }
+        }
+        info!("Loaded {} bans from {}", bans.len(), path.display());
+        Self { path, bans }
+    }
+
+    pub fn save(&self) {
+        let contents: String = self.bans.iter().map(|ban| ban.to_line() + "\n").collect();
+        if let Err(e) = fs::write(&self.path, contents) {
+            error!("Could not save bans to {}: {e}", self.path.display());
+        }
+    }
+
+    pub fn find(&self, peer: PeerId, ip: Option<IpAddr>) -> Option<&Ban> {
+        let now = unix_now();
+        self.bans
+            .iter()
+            .find(|ban| ban.is_active(now) && ban.target.matches(peer, ip))
+    }
+}
+
+#[derive(Event, Clone, Debug)]
+pub enum ModerationCommand {
+    Kick {
+        target: BanTarget,
+        reason: String,
+    },
+    Ban {
+        target: BanTarget,
+        /// `None` for a permanent ban
+        duration: Option<Duration>,
+        reason: String,
+    },
+    Unban {
+        target: BanTarget,
+    },
+}
+
+/// Added to a link that is about to be disconnected
+#[derive(Component)]
+pub struct PendingKick {
+    reason: String,
+    timer: Timer,
+    notified: bool,
+}
+
+impl PendingKick {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            timer: Timer::new(KICK_DELAY, TimerMode::Once),
+            notified: false,
+        }
+    }
+}
+
+pub(crate) struct ModerationServerPlugin;
+
+impl Plugin for ModerationServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BanList::load(ban_list_path()));
+        app.add_event::<ModerationCommand>();
+        app.add_systems(
+            Update,
+            (handle_moderation_commands, process_pending_kicks).chain(),
+        );
+    }
+}
+
+fn handle_moderation_commands(
+    mut moderation_commands: EventReader<ModerationCommand>,
+    mut ban_list: ResMut<BanList>,
+    links: Query<(Entity, &RemoteId, Option<&PeerAddr>, Has<CrossbeamIo>), With<ClientOf>>,
+    mut commands: Commands,
+) {
+    let now = unix_now();
+    for command in moderation_commands.read() {
+        match command {
+            ModerationCommand::Kick { target, reason } => {
+                for (entity, remote_id, peer_addr, _) in links.iter() {
+                    if target.matches(remote_id.0, peer_addr.map(|a| a.0.ip())) {
+                        info!("Kicking {}: {reason}", peer_label(remote_id.0));
+                        commands
+                            .entity(entity)
+                            .insert(PendingKick::new(format!("Kicked: {reason}")));
+                    }
+                }
+            }
+            ModerationCommand::Ban {
+                target,
+                duration,
+                reason,
+            } => {
+                // a netcode PeerId changes every session, so ban what identifies the peer instead
+                let mut target = *target;
+                if let BanTarget::Peer(peer) = target {
+                    let link = links.iter().find(|(_, remote_id, ..)| remote_id.0 == peer);
+                    target = match (peer, link) {
+                        (PeerId::Steam(steam_id), _) => BanTarget::Steam(steam_id),
+                        (_, Some((_, _, _, true))) => {
+                            warn!("Refusing to ban the host");
+                            continue;
+                        }
+                        (_, Some((_, _, Some(addr), _))) => BanTarget::Ip(addr.0.ip()),
+                        _ => target,
+                    };
+                }
+                let ban = Ban {
+                    target,
+                    expires_at: duration.map(|d| now + d.as_secs()),
+                    reason: reason.clone(),
+                };
+                info!("Banning {target}: {}", ban.describe(now));
+                for (entity, remote_id, peer_addr, _) in links.iter() {
+                    if target.matches(remote_id.0, peer_addr.map(|a| a.0.ip())) {
+                        commands
+                            .entity(entity)
+                            .insert(PendingKick::new(ban.describe(now)));
+                    }
+                }
+                ban_list
+                    .bans
+                    .retain(|b| b.is_active(now) && b.target != target);
+                ban_list.bans.push(ban);
+                ban_list.save();
+            }
+            ModerationCommand::Unban { target } => {
+                let before = ban_list.bans.len();
+                ban_list.bans.retain(|b| b.target != *target);
+                info!("Removed {} bans for {target}", before - ban_list.bans.len());
+                ban_list.save();
+            }
+        }
+    }
+}
+
+/// Send the reason first, then disconnect once it had time to arrive
+fn process_pending_kicks(
+    time: Res<Time>,
+    mut links: Query<(Entity, &mut PendingKick, &mut MessageSender<KickNotice>)>,
+    mut commands: Commands,
+) {
+    for (entity, mut pending_kick, mut sender) in links.iter_mut() {
+        if !pending_kick.notified {
+            sender.send::<HandshakeChannel>(KickNotice {
+                reason: pending_kick.reason.clone(),
+            });
+            pending_kick.notified = true;
+        }
+        if pending_kick.timer.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<PendingKick>();
+            commands.trigger_targets(Disconnect, entity);
+        }
+    }
+}
+
+pub(crate) fn reason_from<'a>(words: impl Iterator<Item = &'a str>) -> String {
+    let reason = words.collect::<Vec<_>>().join(" ");
+    if reason.is_empty() {
+        "no reason given".to_string()
+    } else {
+        reason
+    }
+}
+
+/// Parse `perm`, `90s`, `30m`, `2h` or `7d`.
+/// Returns `Some(None)` for a permanent ban and `None` if this isn't a duration.
+pub fn parse_ban_duration(s: &str) -> Option<Option<Duration>> {
+    if s == "perm" {
+        return Some(None);
+    }
+    let (amount, unit) = s.split_at(s.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    let secs = match unit {
+        "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        "d" => amount * 60 * 60 * 24,
+        _ => return None,
+    };
+    Some(Some(Duration::from_secs(secs)))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub(crate) struct ModerationClientPlugin;
+
+impl Plugin for ModerationClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, receive_kick_notice);
+    }
+}
+
+fn receive_kick_notice(
+    mut client: Query<&mut MessageReceiver<KickNotice>, With<Client>>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+    mut host_migration: ResMut<HostMigration>,
+) {
+    for mut receiver in client.iter_mut() {
+        for notice in receiver.receive() {
+            info!("Kicked from the server: {}", notice.reason);
+            last_disconnect_reason.0 = Some(notice.reason);
+            // we were removed on purpose, don't follow the host somewhere else
+            host_migration.plan = None;
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Client-side prediction and interpolation settings.
+//!
+//! [`NetworkSettings`] is edited from the "Network Settings" screen of the main menu and applied
+//! to the `Client` entity in `client_connect`, so changes take effect on the next connection:
+//! - the input delay is either fixed, or picked by lightyear from the measured RTT
+//! - lightyear won't roll back more than `max_rollback_ticks`, older corrections are snapped
+//! - interpolated entities are shown at least `interpolation_delay` in the past
+use std::time::Duration;
+
+use bevy::prelude::*;
+use lightyear::prelude::client::*;
+use lightyear::prelude::*;
+
+/// How many ticks inputs are delayed before being applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputDelay {
+    /// Always the same delay, anything above it is predicted
+    Fixed(u16),
+    /// Lightyear covers as much of the RTT as it can with delay, up to `max_ticks`, and
+    /// predicts the rest
+    Adaptive { max_ticks: u16 },
+}
+
+impl InputDelay {
+    pub const OPTIONS: [InputDelay; 5] = [
+        InputDelay::Adaptive { max_ticks: 6 },
+        InputDelay::Fixed(0),
+        InputDelay::Fixed(2),
+        InputDelay::Fixed(4),
+        InputDelay::Fixed(6),
+    ];
+
+    fn config(&self, max_rollback_ticks: u16) -> InputDelayConfig {
+        match *self {
+            InputDelay::Fixed(ticks) => InputDelayConfig {
+                minimum_input_delay_ticks: ticks,
+                maximum_input_delay_before_prediction: ticks,
+                maximum_predicted_ticks: max_rollback_ticks,
+            },
+            InputDelay::Adaptive { max_ticks } => InputDelayConfig {
+                minimum_input_delay_ticks: 0,
+                maximum_input_delay_before_prediction: max_ticks,
+                maximum_predicted_ticks: max_rollback_ticks,
+            },
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            InputDelay::Fixed(ticks) => format!("{ticks} ticks"),
+            InputDelay::Adaptive { max_ticks } => format!("adaptive (max {max_ticks})"),
+        }
+    }
+}
+
+/// Prediction and interpolation settings of the client, applied when connecting
+#[derive(Resource, Clone, Debug)]
+pub struct NetworkSettings {
+    pub input_delay: InputDelay,
+    /// Longest rollback allowed, in ticks
+    pub max_rollback_ticks: u16,
+    /// Minimum time interpolated entities lag behind the server
+    pub interpolation_delay: Duration,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            input_delay: InputDelay::Adaptive { max_ticks: 6 },
+            max_rollback_ticks: 50,
+            interpolation_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl NetworkSettings {
+    pub const MAX_ROLLBACK_OPTIONS: [u16; 4] = [10, 20, 50, 100];
+    pub const INTERPOLATION_DELAY_OPTIONS_MS: [u64; 4] = [50, 100, 150, 250];
+
+    pub fn cycle_input_delay(&mut self) {
+        self.input_delay = next_option(&InputDelay::OPTIONS, self.input_delay);
+    }
+
+    pub fn cycle_max_rollback(&mut self) {
+        self.max_rollback_ticks = next_option(&Self::MAX_ROLLBACK_OPTIONS, self.max_rollback_ticks);
+    }
+
+    pub fn cycle_interpolation_delay(&mut self) {
+        let current = self.interpolation_delay.as_millis() as u64;
+        self.interpolation_delay =
+            Duration::from_millis(next_option(&Self::INTERPOLATION_DELAY_OPTIONS_MS, current));
+    }
+
+    pub fn prediction_manager(&self) -> PredictionManager {
+        let mut manager = PredictionManager::default();
+        manager.rollback_policy.max_rollback_ticks = self.max_rollback_ticks;
+        manager
+    }
+
+    pub fn input_timeline(&self) -> InputTimeline {
+        InputTimeline(Timeline::from(
+            Input::default().with_input_delay(self.input_delay.config(self.max_rollback_ticks)),
+        ))
+    }
+
+    pub fn interpolation_timeline(&self) -> InterpolationTimeline {
+        InterpolationTimeline(Timeline::from(Interpolation::new(InterpolationConfig {
+            min_delay: self.interpolation_delay,
+            ..default()
+        })))
+    }
+}
+
+/// The option after `current`, or the first one if `current` isn't in the list
+fn next_option<T: Copy + PartialEq>(options: &[T], current: T) -> T {
+    let next = options
+        .iter()
+        .position(|option| *option == current)
+        .map_or(0, |index| (index + 1) % options.len());
+    options[next]
+}
@@ -0,0 +1,476 @@
+//! Per-link network statistics.
+//!
+//! A [`NetworkStatsHistory`] is added to every connected link: the `Client` entity on the client,
+//! and every `LinkOf` entity on the server. It is sampled at a fixed interval so that we can graph
+//! bandwidth, packet loss, rtt, replication traffic, input buffer depth and rollbacks.
+//!
+//! Press F3 in game to toggle the overlay on the client.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use avian2d::prelude::Position;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::input::input_buffer::InputBuffer;
+use lightyear::link::LinkSet;
+use lightyear::prediction::diagnostics::PredictionMetrics;
+use lightyear::prelude::*;
+
+use crate::networking::interest::InterestState;
+use crate::networking::protocol::{NetworkProbe, NetworkProbeChannel, Player, PlayerActions};
+use crate::{GameCleanUp, GameState};
+
+/// How often a sample is pushed into the history
+pub const STATS_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// How often a probe is sent on every link
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of samples kept per link (30 seconds of history)
+pub const STATS_HISTORY_LEN: usize = 60;
+
+const GRAPH_WIDTH: f32 = 180.0;
+const GRAPH_HEIGHT: f32 = 28.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatsMetric {
+    SendBandwidth,
+    RecvBandwidth,
+    PacketLoss,
+    Rtt,
+    ReplicationUpdates,
+    InputBuffer,
+    Rollbacks,
+}
+
+impl StatsMetric {
+    pub const ALL: [StatsMetric; 7] = [
+        StatsMetric::SendBandwidth,
+        StatsMetric::RecvBandwidth,
+        StatsMetric::PacketLoss,
+        StatsMetric::Rtt,
+        StatsMetric::ReplicationUpdates,
+        StatsMetric::InputBuffer,
+        StatsMetric::Rollbacks,
+    ];
+
+    fn format(&self, sample: &NetworkStatsSample) -> String {
+        match self {
+            StatsMetric::SendBandwidth => {
+                format!("send {:.1} kB/s", sample.send_bytes_per_sec / 1000.0)
+            }
+            StatsMetric::RecvBandwidth => {
+                format!("recv {:.1} kB/s", sample.recv_bytes_per_sec / 1000.0)
+            }
+            StatsMetric::PacketLoss => format!("loss {:.1}%", sample.packet_loss * 100.0),
+            StatsMetric::Rtt => format!(
+                "rtt {}~{}ms",
+                sample.rtt.as_millis(),
+                sample.jitter.as_millis()
+            ),
+            StatsMetric::ReplicationUpdates => {
+                format!("repl {:.0}/s", sample.replication_updates_per_sec)
+            }
+            StatsMetric::InputBuffer => format!("inputs [{}]", sample.input_buffer),
+            StatsMetric::Rollbacks => format!("rollbacks {}", sample.rollbacks),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            StatsMetric::SendBandwidth => Color::srgb(0.4, 0.8, 1.0),
+            StatsMetric::RecvBandwidth => Color::srgb(0.4, 1.0, 0.6),
+            StatsMetric::PacketLoss => Color::srgb(1.0, 0.4, 0.4),
+            StatsMetric::Rtt => Color::srgb(1.0, 0.85, 0.3),
+            StatsMetric::ReplicationUpdates => Color::srgb(0.8, 0.6, 1.0),
+            StatsMetric::InputBuffer => Color::srgb(0.9, 0.9, 0.9),
+            StatsMetric::Rollbacks => Color::srgb(1.0, 0.6, 0.2),
+        }
+    }
+
+    /// Lower bound for the top of the graph, so that a quiet link doesn't turn noise into spikes
+    fn min_scale(&self) -> f32 {
+        match self {
+            StatsMetric::SendBandwidth | StatsMetric::RecvBandwidth => 1000.0,
+            StatsMetric::PacketLoss => 0.1,
+            StatsMetric::Rtt => 100.0,
+            StatsMetric::ReplicationUpdates => 50.0,
+            StatsMetric::InputBuffer => 5.0,
+            StatsMetric::Rollbacks => 5.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkStatsSample {
+    pub send_bytes_per_sec: f32,
+    pub recv_bytes_per_sec: f32,
+    /// fraction of probes from the remote peer that never arrived, between 0 and 1
+    pub packet_loss: f32,
+    pub rtt: Duration,
+    pub jitter: Duration,
+    /// replication updates received (client only)
+    pub replication_updates_per_sec: f32,
+    /// replicated entities that moved while visible to the link, once it started replicating
+    /// (server only)
+    pub changed_entities_per_sec: f32,
+    /// number of inputs buffered ahead of the current tick for the player of this link
+    pub input_buffer: i16,
+    /// number of rollbacks since the previous sample (client only)
+    pub rollbacks: u32,
+}
+
+impl NetworkStatsSample {
+    pub fn value(&self, metric: StatsMetric) -> f32 {
+        match metric {
+            StatsMetric::SendBandwidth => self.send_bytes_per_sec,
+            StatsMetric::RecvBandwidth => self.recv_bytes_per_sec,
+            StatsMetric::PacketLoss => self.packet_loss,
+            StatsMetric::Rtt => self.rtt.as_secs_f32() * 1000.0,
+            StatsMetric::ReplicationUpdates => self.replication_updates_per_sec,
+            StatsMetric::InputBuffer => self.input_buffer.max(0) as f32,
+            StatsMetric::Rollbacks => self.rollbacks as f32,
+        }
+    }
+}
+
+/// Rolling history of [`NetworkStatsSample`]s for one link, plus the counters accumulated
+/// since the last sample was taken.
+#[derive(Component, Default, Debug)]
+pub struct NetworkStatsHistory {
+    pub samples: VecDeque<NetworkStatsSample>,
+    bytes_sent: usize,
+    bytes_received: usize,
+    replication_updates: u32,
+    changed_entities: u32,
+    rollbacks: u32,
+    next_probe_seq: u32,
+    probe_window_start: Option<u32>,
+    highest_probe_seq: Option<u32>,
+    probes_received: u32,
+}
+
+impl NetworkStatsHistory {
+    pub fn latest(&self) -> Option<&NetworkStatsSample> {
+        self.samples.back()
+    }
+
+    fn push(&mut self, sample: NetworkStatsSample) {
+        if self.samples.len() == STATS_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn record_probe(&mut self, seq: u32) {
+        if self.probe_window_start.is_none() {
+            self.probe_window_start = Some(seq);
+        }
+        if self.highest_probe_seq.is_none_or(|highest| seq > highest) {
+            self.highest_probe_seq = Some(seq);
+        }
+        self.probes_received += 1;
+    }
+
+    /// Packet loss over the probes expected since the last call, then start a new window
+    fn take_packet_loss(&mut self) -> f32 {
+        let (Some(start), Some(highest)) = (self.probe_window_start, self.highest_probe_seq) else {
+            return 0.0;
+        };
+        if highest < start {
+            // nothing new arrived in this window, we don't know how many probes to expect
+            return self.latest().map_or(0.0, |sample| sample.packet_loss);
+        }
+        let expected = highest - start + 1;
+        let loss = 1.0 - (self.probes_received as f32 / expected as f32);
+        self.probe_window_start = Some(highest + 1);
+        self.probes_received = 0;
+        loss.clamp(0.0, 1.0)
+    }
+}
+
+/// Samples the network statistics of every link. Added on both the client and the server.
+pub(crate) struct NetworkStatsPlugin;
+
+impl Plugin for NetworkStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(add_stats_history);
+        app.add_systems(PreUpdate, count_received_bytes.after(LinkSet::Receive));
+        app.add_systems(PostUpdate, count_sent_bytes.before(LinkSet::Send));
+        app.add_systems(
+            Update,
+            (
+                send_probes.run_if(on_timer(PROBE_INTERVAL)),
+                receive_probes,
+                count_client_replication_updates,
+                count_server_changed_entities,
+                count_rollbacks,
+                sample_stats.run_if(on_timer(STATS_SAMPLE_INTERVAL)),
+            )
+                .chain(),
+        );
+    }
+}
+
+fn add_stats_history(trigger: Trigger<OnAdd, Connected>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert(NetworkStatsHistory::default());
+}
+
+fn count_received_bytes(mut q: Query<(&Link, &mut NetworkStatsHistory)>) {
+    for (link, mut stats) in q.iter_mut() {
+        stats.bytes_received += link.recv.iter().map(|payload| payload.len()).sum::<usize>();
+    }
+}
+
+fn count_sent_bytes(mut q: Query<(&Link, &mut NetworkStatsHistory)>) {
+    for (link, mut stats) in q.iter_mut() {
+        stats.bytes_sent += link.send.iter().map(|payload| payload.len()).sum::<usize>();
+    }
+}
+
+fn send_probes(
+    mut q: Query<(&mut MessageSender<NetworkProbe>, &mut NetworkStatsHistory), With<Connected>>,
+) {
+    for (mut sender, mut stats) in q.iter_mut() {
+        let seq = stats.next_probe_seq;
+        stats.next_probe_seq = seq.wrapping_add(1);
+        sender.send::<NetworkProbeChannel>(NetworkProbe { seq });
+    }
+}
+
+fn receive_probes(mut q: Query<(&mut MessageReceiver<NetworkProbe>, &mut NetworkStatsHistory)>) {
+    for (mut receiver, mut stats) in q.iter_mut() {
+        for probe in receiver.receive() {
+            stats.record_probe(probe.seq);
+        }
+    }
+}
+
+/// On the client, every replication update received bumps the tick stored in `Confirmed`
+fn count_client_replication_updates(
+    confirmed: Query<Ref<Confirmed>>,
+    mut client: Single<&mut NetworkStatsHistory, With<Client>>,
+) {
+    let updates = confirmed.iter().filter(|c| c.is_changed()).count();
+    client.replication_updates += updates as u32;
+}
+
+/// On the server, count for each link the replicated entities that moved this frame and that
+/// its `ReplicationSender` sends to it, so the ones out of its interest radius don't count
+fn count_server_changed_entities(
+    changed: Query<Option<&InterestState>, (With<Replicating>, Changed<Position>)>,
+    mut links: Query<(Entity, &mut NetworkStatsHistory), (With<LinkOf>, With<ReplicationSender>)>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    for (link, mut stats) in links.iter_mut() {
+        let visible = changed
+            .iter()
+            .filter(|interest| interest.is_none_or(|interest| interest.is_visible_to(link)))
+            .count();
+        stats.changed_entities += visible as u32;
+    }
+}
+
+fn count_rollbacks(
+    metrics: Option<Res<PredictionMetrics>>,
+    mut last_rollbacks: Local<u32>,
+    mut client: Single<&mut NetworkStatsHistory, With<Client>>,
+) {
+    let Some(metrics) = metrics else {
+        return;
+    };
+    let new_rollbacks = metrics.rollbacks.wrapping_sub(*last_rollbacks);
+    *last_rollbacks = metrics.rollbacks;
+    client.rollbacks += new_rollbacks;
+}
+
+fn sample_stats(
+    mut links: Query<(Entity, &Link, &mut NetworkStatsHistory, Has<Client>)>,
+    players: Query<
+        (
+            &InputBuffer<ActionState<PlayerActions>>,
+            Option<&ControlledBy>,
+            Has<Controlled>,
+        ),
+        With<Player>,
+    >,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+) {
+    let tick = timeline.tick();
+    let secs = STATS_SAMPLE_INTERVAL.as_secs_f32();
+    for (entity, link, mut stats, is_client) in links.iter_mut() {
+        // on the client we want our own player, on the server the player owned by this link
+        let input_buffer = players
+            .iter()
+            .find(|(_, controlled_by, is_controlled)| {
+                if is_client {
+                    *is_controlled
+                } else {
+                    controlled_by.is_some_and(|c| c.owner == entity)
+                }
+            })
+            .and_then(|(buffer, _, _)| buffer.end_tick())
+            .map_or(0, |end_tick| {
+                lightyear::utils::wrapping_id::wrapping_diff(tick.0, end_tick.0)
+            });
+
+        let packet_loss = stats.take_packet_loss();
+        let sample = NetworkStatsSample {
+            send_bytes_per_sec: stats.bytes_sent as f32 / secs,
+            recv_bytes_per_sec: stats.bytes_received as f32 / secs,
+            packet_loss,
+            rtt: link.stats.rtt,
+            jitter: link.stats.jitter,
+            replication_updates_per_sec: stats.replication_updates as f32 / secs,
+            changed_entities_per_sec: stats.changed_entities as f32 / secs,
+            input_buffer,
+            rollbacks: stats.rollbacks,
+        };
+        stats.bytes_sent = 0;
+        stats.bytes_received = 0;
+        stats.replication_updates = 0;
+        stats.changed_entities = 0;
+        stats.rollbacks = 0;
+        stats.push(sample);
+    }
+}
+
+/// Client-only overlay that graphs the [`NetworkStatsHistory`] of the client link.
+pub(crate) struct NetworkStatsOverlayPlugin;
+
+impl Plugin for NetworkStatsOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (toggle_network_stats_overlay, update_network_stats_overlay)
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct NetworkStatsOverlay;
+
+#[derive(Component)]
+struct StatsLabel(StatsMetric);
+
+#[derive(Component)]
+struct StatsBar {
+    metric: StatsMetric,
+    index: usize,
+}
+
+fn toggle_network_stats_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    overlay: Query<Entity, With<NetworkStatsOverlay>>,
+    mut commands: Commands,
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+    if let Ok(overlay) = overlay.single() {
+        commands.entity(overlay).despawn();
+        return;
+    }
+
+    let bar_width = GRAPH_WIDTH / STATS_HISTORY_LEN as f32;
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                top: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(6.0)),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            NetworkStatsOverlay,
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            for metric in StatsMetric::ALL {
+                parent
+                    .spawn(Node {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(8.0),
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn((
+                            Text::new(""),
+                            TextFont {
+                                font_size: 12.0,
+                                ..default()
+                            },
+                            TextColor(metric.color()),
+                            Node {
+                                width: Val::Px(120.0),
+                                ..default()
+                            },
+                            StatsLabel(metric),
+                        ));
+                        row.spawn((
+                            Node {
+                                width: Val::Px(GRAPH_WIDTH),
+                                height: Val::Px(GRAPH_HEIGHT),
+                                flex_direction: FlexDirection::Row,
+                                align_items: AlignItems::FlexEnd,
+                                ..default()
+                            },
+                            BackgroundColor(Color::srgba(1.0, 1.0, 1.0, 0.05)),
+                        ))
+                        .with_children(|graph| {
+                            for index in 0..STATS_HISTORY_LEN {
+                                graph.spawn((
+                                    Node {
+                                        width: Val::Px(bar_width),
+                                        height: Val::Px(0.0),
+                                        ..default()
+                                    },
+                                    BackgroundColor(metric.color()),
+                                    StatsBar { metric, index },
+                                ));
+                            }
+                        });
+                    });
+            }
+        });
+}
+
+fn update_network_stats_overlay(
+    client: Single<&NetworkStatsHistory, (With<Client>, Changed<NetworkStatsHistory>)>,
+    mut labels: Query<(&mut Text, &StatsLabel)>,
+    mut bars: Query<(&mut Node, &StatsBar)>,
+) {
+    let history = client.into_inner();
+    let Some(latest) = history.latest() else {
+        return;
+    };
+    for (mut text, label) in labels.iter_mut() {
+        text.0 = label.0.format(latest);
+    }
+
+    // the newest sample is drawn on the right
+    let offset = STATS_HISTORY_LEN - history.samples.len();
+    for (mut node, bar) in bars.iter_mut() {
+        let scale = history
+            .samples
+            .iter()
+            .map(|s| s.value(bar.metric))
+            .fold(bar.metric.min_scale(), f32::max);
+        let value = bar
+            .index
+            .checked_sub(offset)
+            .and_then(|i| history.samples.get(i))
+            .map_or(0.0, |s| s.value(bar.metric));
+        node.height = Val::Px(GRAPH_HEIGHT * value / scale);
+    }
+}
@@ -0,0 +1,595 @@
+//! Power-up pickups.
+//!
+//! The server spawns a random [`PowerUp`] somewhere in the arena every [`SPAWN_INTERVAL`], as
+//! long as there are fewer than [`MAX_POWER_UPS`] of them. They are replicated and predicted, and
+//! picked up by the first ship whose collider overlaps them: the clients predict the pickups of
+//! every predicted ship, the server confirms them.
+//!
+//! Picking one up gives the ship a timed effect, which ends on a tick like `Invulnerable`:
+//! - [`Shield`]: bullets don't damage the ship (`health`)
+//! - [`SpeedBoost`]: stronger thrust (`apply_action_state_to_player_movement`)
+//! - [`FireRateBoost`]: shorter weapon cooldown (`shared_player_firing`)
+//! - [`Repairing`]: the ship heals over time
+//!
+//! Picking up the same power-up again restarts its timer.
+use core::f32::consts::TAU;
+use std::time::Duration;
+
+use avian2d::prelude::{Position, Rotation};
+use bevy::color::palettes::css;
+use bevy::ecs::query::QueryData;
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::game_mode::ArenaEntity;
+use crate::networking::health::Simulated;
+use crate::networking::protocol::{
+    Destroyed, FireRateBoost, Health, MatchRoom, PhysicsBundle, Player, PowerUp, PowerUpKind,
+    Repairing, Shield, SpeedBoost, SHIP_LENGTH,
+};
+use crate::networking::shared::{map_obstacles, FIXED_TIMESTEP_HZ, MAPS, WALL_SIZE};
+use crate::{GameCleanUp, GameState, TEXT_COLOR};
+
+pub const POWER_UP_RADIUS: f32 = 10.0;
+pub const SPAWN_INTERVAL: Duration = Duration::from_secs(10);
+pub const MAX_POWER_UPS: usize = 3;
+/// Thrust multiplier with a [`SpeedBoost`]
+pub const SPEED_BOOST: f32 = 1.6;
+/// Cooldown divider with a [`FireRateBoost`]
+pub const FIRE_RATE_BOOST: u16 = 2;
+/// A [`Repairing`] ship heals 1 point every this many ticks
+const REPAIR_INTERVAL_TICKS: u16 = 2;
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 4] = [
+        PowerUpKind::Shield,
+        PowerUpKind::SpeedBoost,
+        PowerUpKind::RapidFire,
+        PowerUpKind::Repair,
+    ];
+
+    /// How long the effect lasts, in ticks
+    pub fn duration(&self) -> u16 {
+        let seconds = match self {
+            PowerUpKind::Shield => 5,
+            PowerUpKind::SpeedBoost => 6,
+            PowerUpKind::RapidFire => 6,
+            PowerUpKind::Repair => 3,
+        };
+        seconds * FIXED_TIMESTEP_HZ as u16
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            PowerUpKind::Shield => css::DEEP_SKY_BLUE.into(),
+            PowerUpKind::SpeedBoost => css::YELLOW.into(),
+            PowerUpKind::RapidFire => css::ORANGE_RED.into(),
+            PowerUpKind::Repair => css::LIME.into(),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "Shield",
+            PowerUpKind::SpeedBoost => "Speed",
+            PowerUpKind::RapidFire => "Rapid fire",
+            PowerUpKind::Repair => "Repair",
+        }
+    }
+}
+
+/// The timed effects of a ship
+#[derive(QueryData)]
+pub struct ActiveEffects {
+    shield: Option<&'static Shield>,
+    speed_boost: Option<&'static SpeedBoost>,
+    fire_rate_boost: Option<&'static FireRateBoost>,
+    repairing: Option<&'static Repairing>,
+}
+
+impl ActiveEffectsItem<'_> {
+    /// Every active effect and the tick it ends on
+    pub fn list(&self) -> Vec<(PowerUpKind, Tick)> {
+        [
+            self.shield.map(|e| (PowerUpKind::Shield, e.until)),
+            self.speed_boost.map(|e| (PowerUpKind::SpeedBoost, e.until)),
+            self.fire_rate_boost
+                .map(|e| (PowerUpKind::RapidFire, e.until)),
+            self.repairing.map(|e| (PowerUpKind::Repair, e.until)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// An effect that is removed once its tick is reached
+trait TimedEffect: Component {
+    fn until(&self) -> Tick;
+}
+
+impl TimedEffect for Shield {
+    fn until(&self) -> Tick {
+        self.until
+    }
+}
+
+impl TimedEffect for SpeedBoost {
+    fn until(&self) -> Tick {
+        self.until
+    }
+}
+
+impl TimedEffect for FireRateBoost {
+    fn until(&self) -> Tick {
+        self.until
+    }
+}
+
+impl TimedEffect for Repairing {
+    fn until(&self) -> Tick {
+        self.until
+    }
+}
+
+/// Pickups and effects, in both apps
+pub(crate) struct PowerUpsPlugin;
+
+impl Plugin for PowerUpsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                pick_up_power_ups,
+                repair_ships,
+                expire_effect::<Shield>,
+                expire_effect::<SpeedBoost>,
+                expire_effect::<FireRateBoost>,
+                expire_effect::<Repairing>,
+            )
+                .chain(),
+        );
+    }
+}
+
+pub(crate) struct PowerUpsServerPlugin;
+
+impl Plugin for PowerUpsServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PowerUpSpawner(Timer::new(
+            SPAWN_INTERVAL,
+            TimerMode::Repeating,
+        )));
+        app.add_systems(Update, spawn_power_ups.run_if(in_state(GameState::Game)));
+    }
+}
+
+#[derive(Resource)]
+struct PowerUpSpawner(Timer);
+
+fn spawn_power_ups(
+    time: Res<Time>,
+    mut spawner: ResMut<PowerUpSpawner>,
+    power_ups: Query<(), With<PowerUp>>,
+    room: Query<&MatchRoom>,
+    mut commands: Commands,
+) {
+    if !spawner.0.tick(time.delta()).just_finished() || power_ups.iter().count() >= MAX_POWER_UPS {
+        return;
+    }
+    let kind = PowerUpKind::ALL[rand::random::<u32>() as usize % PowerUpKind::ALL.len()];
+    // keep away from the walls, and out of the map's obstacles
+    let range = WALL_SIZE - 40.0;
+    let obstacles = map_obstacles(room.iter().next().map_or(MAPS[0], |room| room.map.as_str()));
+    let position = loop {
+        let position = Vec2::new(
+            rand::random::<f32>() * 2.0 - 1.0,
+            rand::random::<f32>() * 2.0 - 1.0,
+        ) * range;
+        if !obstacles
+            .iter()
+            .any(|obstacle| obstacle.inflate(40.0).contains(position))
+        {
+            break position;
+        }
+    };
+    commands.spawn((
+        PowerUp { kind },
+        Position(position),
+        Name::new("PowerUp"),
+        Replicate::to_clients(NetworkTarget::All),
+        PredictionTarget::to_clients(NetworkTarget::All),
+        ArenaEntity,
+        GameCleanUp,
+    ));
+}
+
+fn pick_up_power_ups(
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    power_ups: Query<(Entity, &PowerUp, &Position), Simulated>,
+    ships: Query<(Entity, &Position, &Rotation), (With<Player>, Without<Destroyed>, Simulated)>,
+    mut commands: Commands,
+) {
+    if power_ups.is_empty() {
+        return;
+    }
+    let tick = timeline.tick();
+    let ship_collider = PhysicsBundle::player_ship().collider;
+    for (power_up_entity, power_up, power_up_pos) in power_ups.iter() {
+        let Some((ship, ..)) = ships.iter().find(|(_, position, rotation)| {
+            ship_collider.distance_to_point(**position, **rotation, power_up_pos.0, true)
+                <= POWER_UP_RADIUS
+        }) else {
+            continue;
+        };
+        commands.entity(power_up_entity).prediction_despawn();
+        let until = tick + power_up.kind.duration();
+        let mut ship = commands.entity(ship);
+        match power_up.kind {
+            PowerUpKind::Shield => ship.insert(Shield { until }),
+            PowerUpKind::SpeedBoost => ship.insert(SpeedBoost { until }),
+            PowerUpKind::RapidFire => ship.insert(FireRateBoost { until }),
+            PowerUpKind::Repair => ship.insert(Repairing { until }),
+        };
+    }
+}
+
+/// Thrust of a ship relative to the normal one
+pub fn thrust_multiplier(speed_boost: bool) -> f32 {
+    if speed_boost {
+        SPEED_BOOST
+    } else {
+        1.0
+    }
+}
+
+fn repair_ships(
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    mut ships: Query<&mut Health, (With<Repairing>, Without<Destroyed>, Simulated)>,
+) {
+    if timeline.tick().0 % REPAIR_INTERVAL_TICKS != 0 {
+        return;
+    }
+    for mut health in ships.iter_mut() {
+        if health.current < health.max {
+            health.current += 1;
+        }
+    }
+}
+
+fn expire_effect<T: TimedEffect>(
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    ships: Query<(Entity, &T), Simulated>,
+    mut commands: Commands,
+) {
+    let tick = timeline.tick();
+    for (entity, effect) in ships.iter() {
+        if tick - effect.until() >= 0 {
+            commands.entity(entity).remove::<T>();
+        }
+    }
+}
+
+pub(crate) struct PowerUpsClientPlugin;
+
+impl Plugin for PowerUpsClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), spawn_effects_text);
+        app.add_systems(
+            PostUpdate,
+            (draw_power_ups, draw_effect_timers).run_if(in_state(GameState::Game)),
+        );
+        app.add_systems(
+            Update,
+            update_effects_text.run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct EffectsText;
+
+/// An icon per kind: a circle with a symbol inside
+fn draw_power_ups(
+    mut gizmos: Gizmos,
+    power_ups: Query<(&PowerUp, &Position), Or<(With<Predicted>, With<Interpolated>)>>,
+) {
+    for (power_up, position) in power_ups.iter() {
+        let color = power_up.kind.color();
+        let center = position.0;
+        let r = POWER_UP_RADIUS;
+        gizmos.circle_2d(center, r, color);
+        match power_up.kind {
+            PowerUpKind::Shield => {
+                gizmos.circle_2d(center, r * 0.55, color);
+            }
+            PowerUpKind::SpeedBoost => {
+                for offset in [-0.25, 0.25] {
+                    let tip = center + Vec2::new(0.0, r * (0.35 + offset));
+                    gizmos.line_2d(tip, tip + Vec2::new(-r * 0.4, -r * 0.4), color);
+                    gizmos.line_2d(tip, tip + Vec2::new(r * 0.4, -r * 0.4), color);
+                }
+            }
+            PowerUpKind::RapidFire => {
+                for x in [-0.4, 0.0, 0.4] {
+                    let bottom = center + Vec2::new(r * x, -r * 0.45);
+                    gizmos.line_2d(bottom, bottom + Vec2::Y * r * 0.9, color);
+                }
+            }
+            PowerUpKind::Repair => {
+                gizmos.line_2d(
+                    center - Vec2::X * r * 0.5,
+                    center + Vec2::X * r * 0.5,
+                    color,
+                );
+                gizmos.line_2d(
+                    center - Vec2::Y * r * 0.5,
+                    center + Vec2::Y * r * 0.5,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// A shrinking arc around the ship per active effect, and a bubble for the shield
+fn draw_effect_timers(
+    mut gizmos: Gizmos,
+    ships: Query<
+        (&Position, ActiveEffects),
+        (
+            With<Player>,
+            Without<Destroyed>,
+            Or<(With<Predicted>, With<Interpolated>)>,
+        ),
+    >,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+) {
+    let tick = timeline.tick();
+    for (position, effects) in ships.iter() {
+        for (index, (kind, until)) in effects.list().into_iter().enumerate() {
+            let left = (until - tick).max(0) as f32 / kind.duration() as f32;
+            let radius = SHIP_LENGTH * 0.8 + index as f32 * 4.0;
+            gizmos.arc_2d(
+                Isometry2d::from_translation(position.0),
+                left * TAU,
+                radius,
+                kind.color().with_alpha(0.8),
+            );
+            if kind == PowerUpKind::Shield {
+                gizmos.circle_2d(position.0, SHIP_LENGTH * 0.7, kind.color().with_alpha(0.3));
+            }
+        }
+    }
+}
+
+fn spawn_effects_text(mut commands: Commands) {
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        TextColor(TEXT_COLOR),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        },
+        EffectsText,
+        GameCleanUp,
+    ));
+}
+
+/// Seconds left of the effects of our own ship
+fn update_effects_text(
+    ship: Query<ActiveEffects, (With<Player>, With<Controlled>, With<Predicted>)>,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+    mut text: Single<&mut Text, With<EffectsText>>,
+) {
+    let tick = timeline.tick();
+    let label = ship.single().map_or(String::new(), |effects| {
+        effects
+            .list()
+            .into_iter()
+            .map(|(kind, until)| {
+                let seconds = ((until - tick).max(0) as f64 / FIXED_TIMESTEP_HZ).ceil();
+                format!("{} {seconds}s", kind.label())
+            })
+            .collect::<Vec<_>>()
+            .join("   ")
+    });
+    if text.0 != label {
+        text.0 = label;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use avian2d::prelude::{AngularVelocity, ExternalForce, LinearVelocity};
+    use leafwing_input_manager::prelude::ActionState;
+    use lightyear::input::input_buffer::InputBuffer;
+
+    use super::*;
+    use crate::networking::health::{HealthPlugin, MAX_HEALTH};
+    use crate::networking::protocol::{
+        BulletHitEvent, ColorComponent, PlayerActions, Weapon, WeaponKind,
+    };
+    use crate::networking::shared::{
+        apply_action_state_to_player_movement, shared_player_firing, ApplyInputsQuery,
+    };
+
+    const SHIP_ID: PeerId = PeerId::Netcode(1);
+
+    /// What `player_movement` runs for every ship
+    fn thrust(
+        mut ships: Query<(&ActionState<PlayerActions>, ApplyInputsQuery)>,
+        timeline: Single<&LocalTimeline>,
+    ) {
+        for (action, mut aiq) in ships.iter_mut() {
+            // the physics step clears the forces, there is none here
+            aiq.ex_force.clear();
+            apply_action_state_to_player_movement(action, &mut aiq, timeline.tick());
+        }
+    }
+
+    /// The pickups, effects, health, movement and firing of a client, and our predicted ship at
+    /// the origin
+    fn power_up_app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_event::<BulletHitEvent>();
+        app.add_plugins((PowerUpsPlugin, HealthPlugin));
+        app.add_systems(
+            FixedUpdate,
+            (thrust, shared_player_firing).after(expire_effect::<Repairing>),
+        );
+        app.world_mut().spawn(LocalTimeline::default());
+        let ship = app
+            .world_mut()
+            .spawn((
+                Player::new(SHIP_ID, "Ripley".to_string(), 1, 0),
+                Health::default(),
+                Position(Vec2::ZERO),
+                Rotation::default(),
+                LinearVelocity::ZERO,
+                AngularVelocity::ZERO,
+                ExternalForce::ZERO.with_persistence(false),
+                ColorComponent(Color::WHITE),
+                ActionState::<PlayerActions>::default(),
+                InputBuffer::<ActionState<PlayerActions>>::default(),
+                Weapon::new(WeaponKind::Blaster),
+                Predicted {
+                    confirmed_entity: None,
+                },
+                Controlled,
+            ))
+            .id();
+        (app, ship)
+    }
+
+    fn spawn_power_up(app: &mut App, kind: PowerUpKind, position: Vec2) -> Entity {
+        app.world_mut()
+            .spawn((
+                PowerUp { kind },
+                Position(position),
+                Predicted {
+                    confirmed_entity: None,
+                },
+            ))
+            .id()
+    }
+
+    /// Drop a power-up on the ship and let it pick it up, returns the tick of the pickup
+    fn pick_up(app: &mut App, ship: Entity, kind: PowerUpKind) -> Tick {
+        let position = app.world().get::<Position>(ship).unwrap().0;
+        let power_up = spawn_power_up(app, kind, position);
+        let tick = tick(app);
+        // a predicted power-up is only disabled until the server confirms the pickup, and there is
+        // no server here
+        app.world_mut().despawn(power_up);
+        tick
+    }
+
+    /// Advance the timeline and run the fixed update of the new tick
+    fn tick(app: &mut App) -> Tick {
+        let world = app.world_mut();
+        let mut timeline = world.query::<&mut LocalTimeline>();
+        let mut timeline = timeline.single_mut(world).unwrap();
+        timeline.apply_delta(TickDelta::from_i16(1));
+        let tick = timeline.tick();
+        world.run_schedule(FixedUpdate);
+        tick
+    }
+
+    fn press(app: &mut App, ship: Entity, action: PlayerActions) {
+        app.world_mut()
+            .get_mut::<ActionState<PlayerActions>>(ship)
+            .unwrap()
+            .press(&action);
+    }
+
+    fn hit(app: &mut App) {
+        app.world_mut().send_event(BulletHitEvent {
+            bullet_owner: PeerId::Netcode(2),
+            bullet_color: Color::WHITE,
+            victim_client_id: Some(SHIP_ID),
+            position: Vec2::ZERO,
+            damage: 20,
+        });
+    }
+
+    /// Ticks between two shots while holding fire
+    fn shot_interval(app: &mut App, ship: Entity) -> i16 {
+        let mut shots = Vec::new();
+        while shots.len() < 2 {
+            let tick = tick(app);
+            if app.world().get::<Weapon>(ship).unwrap().last_fire_tick == tick {
+                shots.push(tick);
+            }
+        }
+        shots[1] - shots[0]
+    }
+
+    #[test]
+    fn shield_blocks_hits_until_it_expires() {
+        let (mut app, ship) = power_up_app();
+        let health = |app: &App| app.world().get::<Health>(ship).unwrap().current;
+        let picked_up = pick_up(&mut app, ship, PowerUpKind::Shield);
+        assert!(app.world().entity(ship).contains::<Shield>());
+
+        hit(&mut app);
+        tick(&mut app);
+        assert_eq!(health(&app), MAX_HEALTH);
+
+        while tick(&mut app) - (picked_up + PowerUpKind::Shield.duration()) < 0 {}
+        assert!(!app.world().entity(ship).contains::<Shield>());
+        hit(&mut app);
+        tick(&mut app);
+        assert_eq!(health(&app), MAX_HEALTH - 20);
+    }
+
+    #[test]
+    fn speed_boost_strengthens_the_thrust_until_it_expires() {
+        let (mut app, ship) = power_up_app();
+        let force = |app: &App| app.world().get::<ExternalForce>(ship).unwrap().force().y;
+        press(&mut app, ship, PlayerActions::Up);
+        tick(&mut app);
+        let normal = force(&app);
+        assert!(normal > 0.0);
+
+        // out of reach first
+        spawn_power_up(&mut app, PowerUpKind::SpeedBoost, Vec2::new(100.0, 0.0));
+        tick(&mut app);
+        assert_eq!(force(&app), normal);
+
+        let picked_up = pick_up(&mut app, ship, PowerUpKind::SpeedBoost);
+        assert!(app.world().entity(ship).contains::<SpeedBoost>());
+        assert_eq!(force(&app), normal * SPEED_BOOST);
+
+        while tick(&mut app) - (picked_up + PowerUpKind::SpeedBoost.duration()) < 0 {
+            assert_eq!(force(&app), normal * SPEED_BOOST);
+        }
+        assert_eq!(force(&app), normal);
+    }
+
+    #[test]
+    fn rapid_fire_shortens_the_cooldown_until_it_expires() {
+        let (mut app, ship) = power_up_app();
+        let cooldown = WeaponKind::Blaster.def().cooldown as i16;
+        press(&mut app, ship, PlayerActions::Fire);
+        assert_eq!(shot_interval(&mut app, ship), cooldown);
+
+        let picked_up = pick_up(&mut app, ship, PowerUpKind::RapidFire);
+        assert!(app.world().entity(ship).contains::<FireRateBoost>());
+        assert_eq!(
+            shot_interval(&mut app, ship),
+            cooldown / FIRE_RATE_BOOST as i16
+        );
+
+        while tick(&mut app) - (picked_up + PowerUpKind::RapidFire.duration()) < 0 {}
+        assert!(!app.world().entity(ship).contains::<FireRateBoost>());
+        assert_eq!(shot_interval(&mut app, ship), cooldown);
+    }
+}
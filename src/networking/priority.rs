@@ -0,0 +1,261 @@
+//! Replication priorities and per-client bandwidth budgets.
+//!
+//! Every replicated entity gets a [`ReplicationTier`]: the match room (mode, roster) and the
+//! ships matter most, then balls, then bullets, and anything else is cosmetic. The tier sets the
+//! priority of the entity's `ReplicationGroup`. The `Transport` of every remote link is capped to
+//! [`BandwidthBudget`] and its `ReplicationSender` is in priority mode, so on a congested link
+//! lightyear sends the most important updates first. Priorities accumulate while an update waits,
+//! so low priority entities still go out eventually. The host's crossbeam link never leaves the
+//! machine, so it isn't capped.
+//!
+//! Each link keeps a [`ReplicationBudgetStats`] with the bytes written to it, the entities it can
+//! see that changed, and the ones lightyear's priority manager held back at the end of each
+//! replication interval, with the priority they accumulated. Type `bandwidth` in the server
+//! console to print it.
+use avian2d::prelude::Position;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::crossbeam::CrossbeamIo;
+use lightyear::link::LinkSet;
+use lightyear::prelude::*;
+
+use crate::networking::interest::InterestState;
+use crate::networking::protocol::{BallMarker, BulletMarker, MatchRoom, Player, Roster};
+use crate::networking::shared::{peer_label, SERVER_REPLICATION_INTERVAL};
+
+/// How many held back entities `bandwidth` lists per link
+const MAX_LISTED_HELD_BACK: usize = 5;
+
+/// What kind of entity is replicated, from most to least important
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReplicationTier {
+    /// the `MatchRoom` and its `Roster`, everyone's lobby and scoreboard depend on it
+    Room,
+    Ship,
+    Ball,
+    Bullet,
+    Cosmetic,
+}
+
+impl ReplicationTier {
+    pub const ALL: [ReplicationTier; 5] = [
+        ReplicationTier::Room,
+        ReplicationTier::Ship,
+        ReplicationTier::Ball,
+        ReplicationTier::Bullet,
+        ReplicationTier::Cosmetic,
+    ];
+
+    /// Added to the accumulated priority of the entity every time it changes without being sent
+    pub fn priority(&self) -> f32 {
+        match self {
+            ReplicationTier::Room | ReplicationTier::Ship => 8.0,
+            ReplicationTier::Ball => 4.0,
+            ReplicationTier::Bullet => 2.0,
+            ReplicationTier::Cosmetic => 1.0,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ReplicationTier::Room => "room",
+            ReplicationTier::Ship => "ships",
+            ReplicationTier::Ball => "balls",
+            ReplicationTier::Bullet => "bullets",
+            ReplicationTier::Cosmetic => "cosmetic",
+        }
+    }
+}
+
+/// Replication bandwidth allowed for each client, passed to lightyear's priority manager
+#[derive(Resource, Clone, Debug)]
+pub struct BandwidthBudget {
+    pub bytes_per_sec: u32,
+}
+
+impl Default for BandwidthBudget {
+    fn default() -> Self {
+        Self {
+            bytes_per_sec: 16_000,
+        }
+    }
+}
+
+/// What lightyear did on a link since the stats were last printed
+#[derive(Component, Default, Debug)]
+pub struct ReplicationBudgetStats {
+    /// everything written to the link, not only replication
+    pub bytes_sent: usize,
+    pub intervals: u32,
+    /// intervals at the end of which lightyear held back at least one changed entity
+    pub held_back_intervals: u32,
+    /// entities this link can see that moved (or, for the room, whose roster changed), per tier
+    pub changed: HashMap<ReplicationTier, u32>,
+    /// entities held back at the end of an interval, with the highest priority they accumulated
+    pub held_back: HashMap<Entity, (ReplicationTier, f32)>,
+}
+
+impl ReplicationBudgetStats {
+    pub fn describe(&self) -> String {
+        let changed = ReplicationTier::ALL
+            .iter()
+            .map(|tier| {
+                format!(
+                    "{} {}",
+                    self.changed.get(tier).copied().unwrap_or_default(),
+                    tier.label(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut held_back: Vec<_> = self.held_back.iter().collect();
+        held_back.sort_by(|(_, (_, a)), (_, (_, b))| b.total_cmp(a));
+        let held_back = held_back
+            .iter()
+            .take(MAX_LISTED_HELD_BACK)
+            .map(|(entity, (tier, priority))| format!("{entity} {} {priority:.0}", tier.label()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{:.1}kB sent, held back in {}/{} intervals (most: [{held_back}]), changed: {changed}",
+            self.bytes_sent as f32 / 1000.0,
+            self.held_back_intervals,
+            self.intervals,
+        )
+    }
+}
+
+/// Written by the server console to print the [`ReplicationBudgetStats`] of every link
+#[derive(Event, Clone, Debug)]
+pub struct PrintBandwidthStats;
+
+pub(crate) struct PriorityServerPlugin;
+
+impl Plugin for PriorityServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BandwidthBudget>();
+        app.add_event::<PrintBandwidthStats>();
+        app.add_observer(set_replication_tier);
+        app.add_systems(Update, cap_link_bandwidth);
+        app.add_systems(PostUpdate, count_link_bytes.before(LinkSet::Send));
+        app.add_systems(
+            PostUpdate,
+            sample_replication_interval
+                .after(LinkSet::Send)
+                .run_if(on_timer(SERVER_REPLICATION_INTERVAL)),
+        );
+        app.add_systems(
+            Update,
+            print_bandwidth_stats.run_if(on_event::<PrintBandwidthStats>),
+        );
+    }
+}
+
+fn set_replication_tier(
+    trigger: Trigger<OnAdd, Replicate>,
+    entities: Query<(
+        Has<MatchRoom>,
+        Has<Player>,
+        Has<BallMarker>,
+        Has<BulletMarker>,
+    )>,
+    mut commands: Commands,
+) {
+    let Ok((is_room, is_ship, is_ball, is_bullet)) = entities.get(trigger.target()) else {
+        return;
+    };
+    let tier = match (is_room, is_ship, is_ball, is_bullet) {
+        (true, ..) => ReplicationTier::Room,
+        (_, true, ..) => ReplicationTier::Ship,
+        (.., true, _) => ReplicationTier::Ball,
+        (.., true) => ReplicationTier::Bullet,
+        _ => ReplicationTier::Cosmetic,
+    };
+    commands.entity(trigger.target()).insert((
+        tier,
+        ReplicationGroup::new_from_entity().set_priority(tier.priority()),
+    ));
+}
+
+/// Configure lightyear's bandwidth cap on the `Transport` of new remote links
+fn cap_link_bandwidth(
+    budget: Res<BandwidthBudget>,
+    mut links: Query<
+        (Entity, &mut Transport, Has<CrossbeamIo>),
+        (With<ClientOf>, Added<Connected>),
+    >,
+    mut commands: Commands,
+) {
+    for (link, mut transport, is_host) in links.iter_mut() {
+        if !is_host {
+            transport.priority_manager =
+                PriorityManager::new(PriorityConfig::new(budget.bytes_per_sec));
+        }
+        commands
+            .entity(link)
+            .insert(ReplicationBudgetStats::default());
+    }
+}
+
+/// Count what is written to the links, before the io sends it
+fn count_link_bytes(mut links: Query<(&Link, &mut ReplicationBudgetStats)>) {
+    for (link, mut stats) in links.iter_mut() {
+        stats.bytes_sent += link.send.iter().map(|payload| payload.len()).sum::<usize>();
+    }
+}
+
+/// Priority lightyear accumulated for the group of `entity` on a link, our groups hold a single
+/// entity. Lightyear resets it to 0 when it sends the group, so a changed entity with a priority
+/// left is one the priority manager held back.
+fn accumulated_priority(sender: &ReplicationSender, entity: Entity) -> Option<f32> {
+    sender
+        .group_channels
+        .get(&ReplicationGroupId(entity.to_bits()))
+        .map(|channel| channel.accumulated_priority)
+}
+
+/// After lightyear sent this interval's updates, record what changed on each link and what it
+/// had to leave for later
+fn sample_replication_interval(
+    changed: Query<
+        (Entity, &ReplicationTier, Option<&InterestState>),
+        Or<(Changed<Position>, Changed<Roster>)>,
+    >,
+    mut links: Query<(Entity, &ReplicationSender, &mut ReplicationBudgetStats)>,
+) {
+    for (link, sender, mut stats) in links.iter_mut() {
+        let mut held_back = false;
+        for (entity, tier, interest) in changed.iter() {
+            if !interest.is_none_or(|interest| interest.is_visible_to(link)) {
+                continue;
+            }
+            *stats.changed.entry(*tier).or_default() += 1;
+            if let Some(priority) =
+                accumulated_priority(sender, entity).filter(|priority| *priority > 0.0)
+            {
+                held_back = true;
+                let entry = stats.held_back.entry(entity).or_insert((*tier, 0.0));
+                entry.1 = entry.1.max(priority);
+            }
+        }
+        stats.intervals += 1;
+        if held_back {
+            stats.held_back_intervals += 1;
+        }
+    }
+}
+
+fn print_bandwidth_stats(
+    budget: Res<BandwidthBudget>,
+    mut links: Query<(&RemoteId, &mut ReplicationBudgetStats), With<Connected>>,
+) {
+    info!(
+        "Replication budget: {:.1}kB/s per client",
+        budget.bytes_per_sec as f32 / 1000.0
+    );
+    for (remote_id, mut stats) in links.iter_mut() {
+        info!("{:<28} {}", peer_label(remote_id.0), stats.describe());
+        *stats = ReplicationBudgetStats::default();
+    }
+}
@@ -1,5 +1,6 @@
 use avian2d::prelude::*;
 use bevy::prelude::*;
+use core::net::SocketAddr;
 use core::time::Duration;
 use leafwing_input_manager::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -8,8 +9,13 @@ use lightyear::input::config::InputConfig;
 use lightyear::prelude::input::leafwing;
 use lightyear::prelude::*;
 
+use crate::networking::handshake::ProtocolFingerprint;
+use crate::networking::host_migration::BallSnapshot;
+use crate::networking::quantize;
+use crate::networking::shared::GAME_VERSION;
 use crate::GameCleanUp;
 
+/// Radius of the default blaster bullets, see `weapons` for the others
 pub const BULLET_SIZE: f32 = 1.5;
 pub const SHIP_WIDTH: f32 = 19.0;
 pub const SHIP_LENGTH: f32 = 32.0;
@@ -24,9 +30,9 @@ pub(crate) struct PhysicsBundle {
 }
 
 impl PhysicsBundle {
-    pub(crate) fn bullet() -> Self {
+    pub(crate) fn bullet(radius: f32) -> Self {
         Self {
-            collider: Collider::circle(BULLET_SIZE),
+            collider: Collider::circle(radius),
             collider_density: ColliderDensity(5.0),
             rigid_body: RigidBody::Dynamic,
             external_force: ExternalForce::default(),
@@ -60,21 +66,49 @@ impl PhysicsBundle {
 pub struct Player {
     pub client_id: PeerId,
     pub nickname: String,
+    /// `SessionKey` of the client, recognizes the player after a host migration
+    pub session_key: u64,
+    /// Where the ship starts, see `GameMode::spawn_position`. The smallest slot that was free when
+    /// the player joined, so it stays small and never changes during the match.
+    pub spawn_slot: usize,
     pub rtt: Duration,
     pub jitter: Duration,
 }
 
 impl Player {
-    pub fn new(client_id: PeerId, nickname: String) -> Self {
+    pub fn new(client_id: PeerId, nickname: String, session_key: u64, spawn_slot: usize) -> Self {
         Self {
             client_id,
             nickname,
+            session_key,
+            spawn_slot,
             rtt: Duration::ZERO,
             jitter: Duration::ZERO,
         }
     }
 }
 
+/// How a player is connected to the server. Set by the server in `handle_connections`.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum ConnectionKind {
+    /// The host playing on the machine running the server, through the crossbeam channels
+    Local,
+    /// Netcode over UDP
+    Udp,
+    /// Steam P2P
+    Steam,
+}
+
+impl ConnectionKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionKind::Local => "host",
+            ConnectionKind::Udp => "udp",
+            ConnectionKind::Steam => "steam",
+        }
+    }
+}
+
 /// A shared system generates these events on server and client.
 /// On the server, we use them to manipulate player scores;
 /// On the clients, we just use them for visual effects.
@@ -85,12 +119,14 @@ pub struct BulletHitEvent {
     /// if it struck a player, this is their PeerId:
     pub victim_client_id: Option<PeerId>,
     pub position: Vec2,
+    /// taken from the `BulletMarker` of the bullet, set by the weapon that fired it
+    pub damage: u16,
 }
 
-#[derive(Component, Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[derive(Component, Deserialize, Serialize, Clone, Debug, PartialEq, Reflect)]
 pub struct ColorComponent(pub(crate) Color);
 
-#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
 pub struct BallMarker {
     pub radius: f32,
 }
@@ -111,47 +147,317 @@ impl BallMarker {
     }
 }
 
-#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// A projectile, with what it got from the [`WeaponDef`](crate::networking::weapons::WeaponDef)
+/// that fired it
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
 pub struct BulletMarker {
     pub owner: PeerId,
+    pub damage: u16,
+    /// radius of the collider
+    pub size: f32,
+    /// number of ships it passes through before being destroyed
+    pub pierce: u8,
 }
 
-impl BulletMarker {
-    pub fn new(owner: PeerId) -> Self {
-        Self { owner }
-    }
+/// The weapons a ship can switch between, their stats are in `weapons`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, Reflect)]
+pub enum WeaponKind {
+    #[default]
+    Blaster,
+    Spread,
+    RapidFire,
+    HeavyShell,
+    Piercing,
 }
 
-// Limiting firing rate: once you fire on `last_fire_tick` you have to wait `cooldown` ticks before firing again.
-#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+// Limiting firing rate: once you fire on `last_fire_tick` you have to wait the cooldown of the
+// weapon before firing again.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
 pub(crate) struct Weapon {
     pub(crate) last_fire_tick: Tick,
-    pub(crate) cooldown: u16,
-    pub(crate) bullet_speed: f32,
+    pub(crate) kind: WeaponKind,
 }
 
 impl Weapon {
-    pub(crate) fn new(cooldown: u16) -> Self {
+    pub(crate) fn new(kind: WeaponKind) -> Self {
         Self {
             last_fire_tick: Tick(0),
-            bullet_speed: 500.0,
-            cooldown,
+            kind,
         }
     }
 }
 
+/// Ready status of a player in the pre-match lobby
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default, Reflect)]
+pub struct Ready(pub bool);
+
+/// Replicated room state shared by everyone connected to the server
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct MatchRoom {
+    pub mode: String,
+    pub map: String,
+    /// false while players are in the lobby
+    pub started: bool,
+    /// players are split in two [`Team`]s
+    pub teams: bool,
+    pub friendly_fire: FriendlyFire,
+}
+
+/// One player in the [`Roster`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct RosterEntry {
+    pub client_id: PeerId,
+    pub nickname: String,
+    pub session_key: u64,
+    pub score: i32,
+    pub team: Option<Team>,
+    pub connection_kind: Option<ConnectionKind>,
+    pub ready: bool,
+    pub rtt: Duration,
+    pub color: Color,
+}
+
+/// Every player in the match, replicated on the `MatchRoom` entity.
+///
+/// Ships are only replicated to the clients near them (see `interest`), so the scoreboard, the
+/// lobby, the results and the chat commands read the players from here.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Default, Reflect)]
+pub struct Roster(pub Vec<RosterEntry>);
+
+/// Side of a player when `MatchRoom::teams` is on, see `teams`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+/// What happens when a bullet hits a teammate
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, Reflect)]
+pub enum FriendlyFire {
+    /// the bullet is destroyed, nothing else happens
+    #[default]
+    Off,
+    /// the shooter loses a point
+    Penalty,
+    /// counts like hitting an enemy
+    Full,
+}
+
+/// Phases of a round, see `match_state`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum MatchPhase {
+    /// hits don't score yet
+    Warmup,
+    Live,
+    /// the results are shown until the next warmup
+    Ended,
+}
+
+/// Replicated on the `MatchRoom` entity once the match started
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct MatchState {
+    pub phase: MatchPhase,
+    /// until the end of the phase
+    pub seconds_left: u32,
+    pub score_limit: i32,
+}
+
+/// Goals of each team, replicated on the `MatchRoom` entity in modes where teams score goals
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default, Reflect)]
+pub struct TeamGoals {
+    pub red: i32,
+    pub blue: i32,
+}
+
+/// A goal of the soccer mode, the ball entering it scores for the other team. See `soccer`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct GoalZone {
+    /// the team defending this goal
+    pub team: Team,
+    pub center: Vec2,
+    pub half_size: Vec2,
+}
+
 // increases if you hit another player with a bullet, decreases if you get hit.
-#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
 pub struct Score(pub i32);
 
+/// Hit points of a ship, see `health`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Health {
+    pub current: u16,
+    pub max: u16,
+}
+
+/// The ship was destroyed, it is out of play until `respawn_tick`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Destroyed {
+    pub respawn_tick: Tick,
+}
+
+/// Bullets don't damage the ship until `until`, added when it respawns
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Invulnerable {
+    pub until: Tick,
+}
+
+/// The power-ups the server spawns, see `power_ups`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum PowerUpKind {
+    Shield,
+    SpeedBoost,
+    RapidFire,
+    Repair,
+}
+
+/// A power-up waiting to be picked up
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+}
+
+/// Bullets don't damage the ship until `until`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Shield {
+    pub until: Tick,
+}
+
+/// Stronger thrust until `until`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct SpeedBoost {
+    pub until: Tick,
+}
+
+/// Shorter weapon cooldown until `until`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct FireRateBoost {
+    pub until: Tick,
+}
+
+/// The ship heals over time until `until`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Repairing {
+    pub until: Tick,
+}
+
 // despawns `lifetime` ticks after `origin_tick`
-#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
 pub(crate) struct BulletLifetime {
     pub(crate) origin_tick: Tick,
     /// number of ticks to live for
     pub(crate) lifetime: i16,
 }
 
+// Channels
+
+/// Ordered reliable channel for the [`ProtocolHello`] and the [`KickNotice`] only. It is
+/// registered first, so its id is the same in every build.
+#[derive(Reflect)]
+pub struct HandshakeChannel;
+
+/// Unreliable channel used for small periodic probes that measure packet loss on a link.
+#[derive(Reflect)]
+pub struct NetworkProbeChannel;
+
+/// Ordered reliable channel for session control messages (host migration, etc.)
+#[derive(Reflect)]
+pub struct ControlChannel;
+
+/// Ordered reliable channel for text chat
+#[derive(Reflect)]
+pub struct ChatChannel;
+
+// Messages
+
+/// Sent by a client as soon as it is connected. The server rejects it if the protocols differ.
+///
+/// Frozen: builds with another protocol must still be able to read it, so never change its fields.
+/// Everything else the server needs to know goes in the [`SessionInfo`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct ProtocolHello {
+    pub version: String,
+    pub fingerprint: u64,
+}
+
+/// Sent by the server once the [`ProtocolHello`] matched its protocol
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct HelloAccepted;
+
+/// Sent by a client once its hello was accepted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct SessionInfo {
+    /// join without a ship
+    pub spectator: bool,
+    /// how far in the past the client shows interpolated entities, for lag compensation
+    pub interpolation_delay: Duration,
+    /// stays the same when the client reconnects to a new host, unlike netcode client ids
+    pub session_key: u64,
+}
+
+/// Sent periodically in both directions. The receiver counts gaps in `seq` to estimate packet loss.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct NetworkProbe {
+    pub seq: u32,
+}
+
+/// Sent by a client once connected, so the server knows if it could take over as host.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct HostCapability {
+    pub can_host: bool,
+}
+
+/// Where the clients should reconnect if the host leaves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub enum MigrationTarget {
+    Steam(u64),
+    Udp(SocketAddr),
+}
+
+/// Periodically sent by a listen-server to every client, so they already know who takes over
+/// if the host disappears.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct HostMigrationPlan {
+    pub successor: Option<MigrationTarget>,
+    /// true for the one client that should start its own server
+    pub you_are_successor: bool,
+    /// Every ball of the arena, only sent to the successor: interest management doesn't
+    /// replicate the ones far from its ship, and its server has to start with all of them
+    pub balls: Vec<BallSnapshot>,
+}
+
+/// Warns clients that the server is about to stop
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct ShutdownNotice {
+    pub seconds_remaining: u32,
+    pub reason: String,
+}
+
+/// Sent right before the server disconnects a client on purpose (kick, ban, ...)
+///
+/// Frozen like the [`ProtocolHello`], so a client rejected for its version still reads the reason.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct KickNotice {
+    pub reason: String,
+}
+
+/// A chat line. Clients leave `sender` empty, the server fills it in before relaying.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct ChatMessage {
+    pub sender: String,
+    pub text: String,
+}
+
+/// Sent by clients from the pre-match lobby. Only the host can change the settings or start.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub enum LobbyRequest {
+    SetReady(bool),
+    SetMode(String),
+    SetMap(String),
+    SetTeams(bool),
+    SetFriendlyFire(FriendlyFire),
+    StartMatch,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash, Reflect, Actionlike)]
 pub enum PlayerActions {
     Up,
@@ -159,12 +465,47 @@ pub enum PlayerActions {
     Left,
     Right,
     Fire,
+    SwitchWeapon,
+}
+
+/// Register a channel, message or component and record it in the [`ProtocolFingerprint`], so the
+/// fingerprint follows the registrations and their order
+macro_rules! fingerprinted {
+    ($fingerprint:ident, $app:ident.$register:ident::<$T:ty>($($arg:expr),*)) => {{
+        $fingerprint.record::<$T>();
+        $app.$register::<$T>($($arg),*)
+    }};
 }
 
-pub(crate) struct ProtocolPlugin;
+pub(crate) struct ProtocolPlugin {
+    pub(crate) compression: quantize::PacketCompression,
+}
 
 impl Plugin for ProtocolPlugin {
     fn build(&self, app: &mut App) {
+        let compression = self.compression;
+        // peers with another fingerprint are rejected, see `handshake`
+        let mut fingerprint = ProtocolFingerprint::new(GAME_VERSION);
+
+        // The handshake channel, the hello and the kick notice are registered before anything
+        // else, so that they keep the same ids in builds with a different protocol and a mismatch
+        // can still be reported. Don't register anything before them.
+        fingerprinted!(
+            fingerprint,
+            app.add_channel::<HandshakeChannel>(ChannelSettings {
+                mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+                ..default()
+            })
+        )
+        .add_direction(NetworkDirection::Bidirectional);
+
+        fingerprinted!(fingerprint, app.add_message::<ProtocolHello>())
+            .add_direction(NetworkDirection::ClientToServer);
+
+        fingerprinted!(fingerprint, app.add_message::<KickNotice>())
+            .add_direction(NetworkDirection::ServerToClient);
+
+        fingerprint.record::<PlayerActions>();
         app.add_plugins(leafwing::InputPlugin::<PlayerActions> {
             config: InputConfig::<PlayerActions> {
                 rebroadcast_inputs: true,
@@ -172,36 +513,182 @@ impl Plugin for ProtocolPlugin {
             },
         });
 
+        // channels
+        fingerprinted!(
+            fingerprint,
+            app.add_channel::<NetworkProbeChannel>(ChannelSettings {
+                mode: ChannelMode::UnorderedUnreliable,
+                ..default()
+            })
+        )
+        .add_direction(NetworkDirection::Bidirectional);
+
+        fingerprinted!(
+            fingerprint,
+            app.add_channel::<ControlChannel>(ChannelSettings {
+                mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+                ..default()
+            })
+        )
+        .add_direction(NetworkDirection::Bidirectional);
+
+        fingerprinted!(
+            fingerprint,
+            app.add_channel::<ChatChannel>(ChannelSettings {
+                mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+                ..default()
+            })
+        )
+        .add_direction(NetworkDirection::Bidirectional);
+
+        // messages
+        fingerprinted!(fingerprint, app.add_message::<HelloAccepted>())
+            .add_direction(NetworkDirection::ServerToClient);
+
+        fingerprinted!(fingerprint, app.add_message::<SessionInfo>())
+            .add_direction(NetworkDirection::ClientToServer);
+
+        fingerprinted!(fingerprint, app.add_message::<NetworkProbe>())
+            .add_direction(NetworkDirection::Bidirectional);
+
+        fingerprinted!(fingerprint, app.add_message::<HostCapability>())
+            .add_direction(NetworkDirection::ClientToServer);
+
+        fingerprinted!(fingerprint, app.add_message::<HostMigrationPlan>())
+            .add_direction(NetworkDirection::ServerToClient);
+
+        fingerprinted!(fingerprint, app.add_message::<ShutdownNotice>())
+            .add_direction(NetworkDirection::ServerToClient);
+
+        fingerprinted!(fingerprint, app.add_message::<ChatMessage>())
+            .add_direction(NetworkDirection::Bidirectional);
+
+        fingerprinted!(fingerprint, app.add_message::<LobbyRequest>())
+            .add_direction(NetworkDirection::ClientToServer);
+
         // Player is synced as Simple, because we periodically update rtt ping stats
-        app.register_component::<Player>()
-            .add_prediction(PredictionMode::Simple);
+        // Components are also interpolated for spectators, who don't predict anything
+        fingerprinted!(fingerprint, app.register_component::<Player>())
+            .add_prediction(PredictionMode::Simple)
+            .add_interpolation(InterpolationMode::Simple);
 
-        app.register_component::<ColorComponent>()
-            .add_prediction(PredictionMode::Once);
+        // Simple because ships change color when they switch teams
+        fingerprinted!(fingerprint, app.register_component::<ColorComponent>())
+            .add_prediction(PredictionMode::Simple)
+            .add_interpolation(InterpolationMode::Simple);
 
-        app.register_component::<Name>()
-            .add_prediction(PredictionMode::Once);
+        fingerprinted!(fingerprint, app.register_component::<Team>())
+            .add_prediction(PredictionMode::Simple)
+            .add_interpolation(InterpolationMode::Simple);
 
-        app.register_component::<BallMarker>()
-            .add_prediction(PredictionMode::Once);
+        fingerprinted!(fingerprint, app.register_component::<ConnectionKind>())
+            .add_prediction(PredictionMode::Once)
+            .add_interpolation(InterpolationMode::Once);
 
-        app.register_component::<BulletMarker>()
-            .add_prediction(PredictionMode::Once);
+        fingerprinted!(fingerprint, app.register_component::<Name>())
+            .add_prediction(PredictionMode::Once)
+            .add_interpolation(InterpolationMode::Once);
 
-        app.register_component::<BulletLifetime>()
+        fingerprinted!(fingerprint, app.register_component::<BallMarker>())
+            .add_prediction(PredictionMode::Once)
+            .add_interpolation(InterpolationMode::Once);
+
+        fingerprinted!(fingerprint, app.register_component::<BulletMarker>())
+            .add_prediction(PredictionMode::Once)
+            .add_interpolation(InterpolationMode::Once);
+
+        fingerprinted!(fingerprint, app.register_component::<BulletLifetime>())
             .add_prediction(PredictionMode::Once);
 
-        app.register_component::<Score>()
-            .add_prediction(PredictionMode::Simple);
+        fingerprinted!(fingerprint, app.register_component::<Score>())
+            .add_prediction(PredictionMode::Simple)
+            .add_interpolation(InterpolationMode::Simple);
 
-        // Fully replicated, but not visual, so no need for lerp/corrections:
-        app.register_component::<LinearVelocity>()
-            .add_prediction(PredictionMode::Full);
+        fingerprinted!(fingerprint, app.register_component::<Ready>())
+            .add_prediction(PredictionMode::Simple)
+            .add_interpolation(InterpolationMode::Simple);
 
-        app.register_component::<AngularVelocity>()
-            .add_prediction(PredictionMode::Full);
+        // Full: clients apply damage and respawn ships themselves, and roll back on mismatches
+        fingerprinted!(fingerprint, app.register_component::<Health>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        fingerprinted!(fingerprint, app.register_component::<Destroyed>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        fingerprinted!(fingerprint, app.register_component::<Invulnerable>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        fingerprinted!(fingerprint, app.register_component::<PowerUp>())
+            .add_prediction(PredictionMode::Once)
+            .add_interpolation(InterpolationMode::Once);
+
+        // the effects are picked up and expire in the prediction too
+        fingerprinted!(fingerprint, app.register_component::<Shield>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
 
-        app.register_component::<Weapon>()
+        fingerprinted!(fingerprint, app.register_component::<SpeedBoost>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        fingerprinted!(fingerprint, app.register_component::<FireRateBoost>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        fingerprinted!(fingerprint, app.register_component::<Repairing>())
+            .add_prediction(PredictionMode::Full)
+            .add_interpolation(InterpolationMode::Simple);
+
+        // not predicted, clients read it on the replicated entity
+        fingerprinted!(fingerprint, app.register_component::<MatchRoom>());
+        fingerprinted!(fingerprint, app.register_component::<Roster>());
+        fingerprinted!(fingerprint, app.register_component::<MatchState>());
+        fingerprinted!(fingerprint, app.register_component::<TeamGoals>());
+        fingerprinted!(fingerprint, app.register_component::<GoalZone>());
+
+        // Fully replicated, but not visual, so no need for lerp/corrections.
+        // The motion components are quantized and, except `Position`, delta compressed depending
+        // on `compression`, see `quantize`
+        let linear_velocity = if compression.quantized() {
+            fingerprinted!(
+                fingerprint,
+                app.register_component_custom_serde::<LinearVelocity>(
+                    quantize::linear_velocity_serde()
+                )
+            )
+            .add_prediction(PredictionMode::Full)
+            .add_should_rollback(quantize::linear_velocity_should_rollback)
+        } else {
+            fingerprinted!(fingerprint, app.register_component::<LinearVelocity>())
+                .add_prediction(PredictionMode::Full)
+        };
+        if compression.delta() {
+            fingerprint.record::<quantize::Vec2Delta>();
+            linear_velocity.add_delta_compression::<quantize::Vec2Delta>();
+        }
+
+        let angular_velocity = if compression.quantized() {
+            fingerprinted!(
+                fingerprint,
+                app.register_component_custom_serde::<AngularVelocity>(
+                    quantize::angular_velocity_serde()
+                )
+            )
+            .add_prediction(PredictionMode::Full)
+            .add_should_rollback(quantize::angular_velocity_should_rollback)
+        } else {
+            fingerprinted!(fingerprint, app.register_component::<AngularVelocity>())
+                .add_prediction(PredictionMode::Full)
+        };
+        if compression.delta() {
+            fingerprint.record::<quantize::ScalarDelta>();
+            angular_velocity.add_delta_compression::<quantize::ScalarDelta>();
+        }
+
+        fingerprinted!(fingerprint, app.register_component::<Weapon>())
             .add_prediction(PredictionMode::Full);
 
         // Position and Rotation have a `correction_fn` set, which is used to smear rollback errors
@@ -209,15 +696,40 @@ impl Plugin for ProtocolPlugin {
         //
         // They also set `interpolation_fn` which is used by the VisualInterpolationPlugin to smooth
         // out rendering between fixedupdate ticks.
-        app.register_component::<Position>()
+        let position = if compression.quantized() {
+            fingerprinted!(
+                fingerprint,
+                app.register_component_custom_serde::<Position>(quantize::position_serde())
+            )
             .add_prediction(PredictionMode::Full)
+            .add_should_rollback(quantize::position_should_rollback)
+        } else {
+            fingerprinted!(fingerprint, app.register_component::<Position>())
+                .add_prediction(PredictionMode::Full)
+        };
+        position
+            .add_interpolation(InterpolationMode::Full)
             .add_linear_interpolation_fn()
             .add_linear_correction_fn();
 
-        app.register_component::<Rotation>()
+        let rotation = if compression.quantized() {
+            fingerprinted!(
+                fingerprint,
+                app.register_component_custom_serde::<Rotation>(quantize::rotation_serde())
+            )
             .add_prediction(PredictionMode::Full)
-            .add_linear_interpolation_fn()
-            .add_linear_correction_fn();
+            .add_should_rollback(quantize::rotation_should_rollback)
+        } else {
+            fingerprinted!(fingerprint, app.register_component::<Rotation>())
+                .add_prediction(PredictionMode::Full)
+        }
+        .add_interpolation(InterpolationMode::Full)
+        .add_linear_interpolation_fn()
+        .add_linear_correction_fn();
+        if compression.delta() {
+            fingerprint.record::<quantize::ScalarDelta>();
+            rotation.add_delta_compression::<quantize::ScalarDelta>();
+        }
 
         // do not replicate Transform but make sure to register an interpolation function
         // for it so that we can do visual interpolation
@@ -230,5 +742,9 @@ impl Plugin for ProtocolPlugin {
             .set_interpolation_mode::<Transform>(InterpolationMode::None);
 
         // app.register_component::<Transform>().add_interpolation(InterpolationMode::Full);
+
+        fingerprint.record_tag(compression.format());
+        app.insert_resource(fingerprint);
+        app.insert_resource(compression);
     }
 }
@@ -0,0 +1,452 @@
+//! Quantized serialization for the motion components.
+//!
+//! How they are sent is picked with [`PacketCompression`] (`--compression off|quantized|delta`,
+//! `delta` by default). Peers must use the same setting, it is part of the protocol fingerprint.
+//!
+//! With `quantized` and `delta`, `Position`, `Rotation`, `LinearVelocity` and `AngularVelocity`
+//! are sent as 16 bit fixed point numbers instead of full precision floats, using ranges that cover
+//! the arena (see `WALL_SIZE`):
+//!
+//! | component         | range | step    | bytes (before) |
+//! |-------------------|-------|---------|----------------|
+//! | `Position`        | ±400  | ~0.012  | 4 (8)          |
+//! | `Rotation`        | ±π    | ~0.0001 | 2 (8)          |
+//! | `LinearVelocity`  | ±1024 | ~0.03   | 4 (8)          |
+//! | `AngularVelocity` | ±32   | ~0.001  | 2 (4)          |
+//!
+//! Because the confirmed state is now slightly off from what the client predicted, rollbacks are
+//! only triggered when the difference is larger than the quantization step.
+//!
+//! Lightyear only sends the components that changed since the last acknowledged update
+//! (`SendUpdatesMode::SinceLastAck`). With `delta`, `Rotation`, `LinearVelocity`
+//! and `AngularVelocity` are also sent as the difference, in quantization steps, to the value of
+//! the update the client acknowledged last ([`Vec2Delta`], [`ScalarDelta`]). The deltas are
+//! variable length integers, so a component that barely changed (a bullet flying straight, a ball
+//! at rest) costs 1 byte per axis instead of 2, and a large change 3. `Position` moves by hundreds
+//! of steps between two updates, so it always gets the absolute encoding.
+//!
+//! Type `quantization` in the server console to compare the replication bytes of the current
+//! entities with and without quantization.
+use std::f32::consts::PI;
+use std::io::{Read, Write};
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity, Position, Rotation};
+use bevy::prelude::*;
+use lightyear::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::networking::shared::{SERVER_REPLICATION_INTERVAL, WALL_SIZE};
+
+/// How the motion components are encoded on the wire
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PacketCompression {
+    /// full precision floats, lightyear's default serialization
+    Off,
+    /// 16 bit fixed point
+    Quantized,
+    /// quantized, and the rotation and the velocities are sent as deltas to the last
+    /// acknowledged update
+    #[default]
+    Delta,
+}
+
+impl PacketCompression {
+    pub fn quantized(&self) -> bool {
+        *self != PacketCompression::Off
+    }
+
+    pub fn delta(&self) -> bool {
+        *self == PacketCompression::Delta
+    }
+
+    /// Part of the protocol fingerprint, change it with the encoding
+    pub fn format(&self) -> &'static str {
+        match self {
+            PacketCompression::Off => "motion-f32",
+            PacketCompression::Quantized => "quantized-motion-i16",
+            PacketCompression::Delta => "quantized-motion-i16-delta",
+        }
+    }
+}
+
+/// Positions are clamped to the arena plus some margin
+pub const POSITION_RANGE: f32 = WALL_SIZE + 50.0;
+/// Bullets are the fastest entities: `bullet_speed` plus the ship's velocity
+pub const LINEAR_VELOCITY_RANGE: f32 = 1024.0;
+pub const ANGULAR_VELOCITY_RANGE: f32 = 32.0;
+
+fn quantize(value: f32, range: f32) -> i16 {
+    (value.clamp(-range, range) / range * i16::MAX as f32).round() as i16
+}
+
+fn dequantize(value: i16, range: f32) -> f32 {
+    value as f32 / i16::MAX as f32 * range
+}
+
+/// Distance between two quantized values, twice the largest rounding error
+fn step(range: f32) -> f32 {
+    range / i16::MAX as f32
+}
+
+fn write_i16(writer: &mut Writer, value: i16) -> Result<(), SerializationError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_i16(reader: &mut Reader) -> Result<i16, SerializationError> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(i16::from_le_bytes(bytes))
+}
+
+fn write_vec2(writer: &mut Writer, value: Vec2, range: f32) -> Result<(), SerializationError> {
+    write_i16(writer, quantize(value.x, range))?;
+    write_i16(writer, quantize(value.y, range))
+}
+
+fn read_vec2(reader: &mut Reader, range: f32) -> Result<Vec2, SerializationError> {
+    let x = dequantize(read_i16(reader)?, range);
+    let y = dequantize(read_i16(reader)?, range);
+    Ok(Vec2::new(x, y))
+}
+
+pub fn position_serde() -> SerializeFns<Position> {
+    SerializeFns {
+        serialize: |position, writer| write_vec2(writer, position.0, POSITION_RANGE),
+        deserialize: |reader| read_vec2(reader, POSITION_RANGE).map(Position),
+    }
+}
+
+pub fn rotation_serde() -> SerializeFns<Rotation> {
+    SerializeFns {
+        serialize: |rotation, writer| write_i16(writer, quantize(rotation.as_radians(), PI)),
+        deserialize: |reader| Ok(Rotation::radians(dequantize(read_i16(reader)?, PI))),
+    }
+}
+
+pub fn linear_velocity_serde() -> SerializeFns<LinearVelocity> {
+    SerializeFns {
+        serialize: |velocity, writer| write_vec2(writer, velocity.0, LINEAR_VELOCITY_RANGE),
+        deserialize: |reader| read_vec2(reader, LINEAR_VELOCITY_RANGE).map(LinearVelocity),
+    }
+}
+
+pub fn angular_velocity_serde() -> SerializeFns<AngularVelocity> {
+    SerializeFns {
+        serialize: |velocity, writer| {
+            write_i16(writer, quantize(velocity.0, ANGULAR_VELOCITY_RANGE))
+        },
+        deserialize: |reader| {
+            Ok(AngularVelocity(dequantize(
+                read_i16(reader)?,
+                ANGULAR_VELOCITY_RANGE,
+            )))
+        },
+    }
+}
+
+/// Change of a quantized vector since the acknowledged update, in quantization steps
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Vec2Delta {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// Change of a quantized scalar since the acknowledged update, in quantization steps
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct ScalarDelta(pub i16);
+
+fn scalar_delta(old: f32, new: f32, range: f32) -> ScalarDelta {
+    ScalarDelta(quantize(new, range).wrapping_sub(quantize(old, range)))
+}
+
+/// Works on the quantized value, so the client lands on exactly what the server serialized
+fn apply_scalar_delta(base: f32, delta: ScalarDelta, range: f32) -> f32 {
+    dequantize(quantize(base, range).wrapping_add(delta.0), range)
+}
+
+fn vec2_delta(old: Vec2, new: Vec2, range: f32) -> Vec2Delta {
+    Vec2Delta {
+        x: scalar_delta(old.x, new.x, range).0,
+        y: scalar_delta(old.y, new.y, range).0,
+    }
+}
+
+fn apply_vec2_delta(base: Vec2, delta: Vec2Delta, range: f32) -> Vec2 {
+    Vec2::new(
+        apply_scalar_delta(base.x, ScalarDelta(delta.x), range),
+        apply_scalar_delta(base.y, ScalarDelta(delta.y), range),
+    )
+}
+
+impl Diffable<ScalarDelta> for Rotation {
+    fn base_value() -> Self {
+        Rotation::IDENTITY
+    }
+
+    fn diff(&self, new: &Self) -> ScalarDelta {
+        scalar_delta(self.as_radians(), new.as_radians(), PI)
+    }
+
+    fn apply_diff(&mut self, delta: &ScalarDelta) {
+        *self = Rotation::radians(apply_scalar_delta(self.as_radians(), *delta, PI));
+    }
+}
+
+impl Diffable<Vec2Delta> for LinearVelocity {
+    fn base_value() -> Self {
+        LinearVelocity::ZERO
+    }
+
+    fn diff(&self, new: &Self) -> Vec2Delta {
+        vec2_delta(self.0, new.0, LINEAR_VELOCITY_RANGE)
+    }
+
+    fn apply_diff(&mut self, delta: &Vec2Delta) {
+        self.0 = apply_vec2_delta(self.0, *delta, LINEAR_VELOCITY_RANGE);
+    }
+}
+
+impl Diffable<ScalarDelta> for AngularVelocity {
+    fn base_value() -> Self {
+        AngularVelocity::ZERO
+    }
+
+    fn diff(&self, new: &Self) -> ScalarDelta {
+        scalar_delta(self.0, new.0, ANGULAR_VELOCITY_RANGE)
+    }
+
+    fn apply_diff(&mut self, delta: &ScalarDelta) {
+        self.0 = apply_scalar_delta(self.0, *delta, ANGULAR_VELOCITY_RANGE);
+    }
+}
+
+// Only roll back when the server disagrees by more than the quantization error
+
+pub fn position_should_rollback(this: &Position, that: &Position) -> bool {
+    this.distance(that.0) > 2.0 * step(POSITION_RANGE)
+}
+
+pub fn rotation_should_rollback(this: &Rotation, that: &Rotation) -> bool {
+    this.angle_between(*that).abs() > 2.0 * step(PI)
+}
+
+pub fn linear_velocity_should_rollback(this: &LinearVelocity, that: &LinearVelocity) -> bool {
+    this.distance(that.0) > 2.0 * step(LINEAR_VELOCITY_RANGE)
+}
+
+pub fn angular_velocity_should_rollback(this: &AngularVelocity, that: &AngularVelocity) -> bool {
+    (this.0 - that.0).abs() > 2.0 * step(ANGULAR_VELOCITY_RANGE)
+}
+
+/// Written by the server console to print [`QuantizationReport`]
+#[derive(Event, Clone, Debug)]
+pub struct PrintQuantizationReport;
+
+/// Replication payload of the motion components of every replicated entity, for one update
+#[derive(Debug, Default, Clone, Copy)]
+pub struct QuantizationReport {
+    pub entities: usize,
+    pub full_precision_bytes: usize,
+    pub quantized_bytes: usize,
+}
+
+impl QuantizationReport {
+    /// Bytes per second sent to one client that sees every entity move in every update
+    pub fn per_client_per_sec(bytes: usize) -> f32 {
+        bytes as f32 / SERVER_REPLICATION_INTERVAL.as_secs_f32()
+    }
+}
+
+pub(crate) struct QuantizationServerPlugin;
+
+impl Plugin for QuantizationServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PrintQuantizationReport>();
+        app.add_systems(
+            Update,
+            print_quantization_report.run_if(on_event::<PrintQuantizationReport>),
+        );
+    }
+}
+
+fn print_quantization_report(
+    entities: Query<
+        (
+            Option<&Position>,
+            Option<&Rotation>,
+            Option<&LinearVelocity>,
+            Option<&AngularVelocity>,
+        ),
+        With<Replicating>,
+    >,
+    compression: Res<PacketCompression>,
+) {
+    let mut report = QuantizationReport::default();
+    let mut writer = Writer::default();
+    for (position, rotation, linear_velocity, angular_velocity) in entities.iter() {
+        report.entities += 1;
+        let before = writer.len();
+        let mut result = Ok(());
+        if let Some(position) = position {
+            report.full_precision_bytes += size_of::<Vec2>();
+            result = result.and((position_serde().serialize)(position, &mut writer));
+        }
+        if let Some(rotation) = rotation {
+            // avian stores the cosine and sine
+            report.full_precision_bytes += 2 * size_of::<f32>();
+            result = result.and((rotation_serde().serialize)(rotation, &mut writer));
+        }
+        if let Some(velocity) = linear_velocity {
+            report.full_precision_bytes += size_of::<Vec2>();
+            result = result.and((linear_velocity_serde().serialize)(velocity, &mut writer));
+        }
+        if let Some(velocity) = angular_velocity {
+            report.full_precision_bytes += size_of::<f32>();
+            result = result.and((angular_velocity_serde().serialize)(velocity, &mut writer));
+        }
+        if let Err(e) = result {
+            warn!("Could not serialize the motion components: {e:?}");
+        }
+        report.quantized_bytes += writer.len() - before;
+    }
+    info!(
+        "Motion components of {} replicated entities: {} bytes per update at full precision, {} \
+         quantized ({:.1}kB/s -> {:.1}kB/s per client), sent with --compression {:?}",
+        report.entities,
+        report.full_precision_bytes,
+        report.quantized_bytes,
+        QuantizationReport::per_client_per_sec(report.full_precision_bytes) / 1000.0,
+        QuantizationReport::per_client_per_sec(report.quantized_bytes) / 1000.0,
+        *compression,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Motion of an entity in two consecutive replication updates
+    struct Motion {
+        position: [Vec2; 2],
+        rotation: [f32; 2],
+        linear_velocity: [Vec2; 2],
+        angular_velocity: [f32; 2],
+    }
+
+    /// 4 ships turning and speeding up, 24 bullets flying straight and 2 balls slowing down
+    fn world() -> Vec<Motion> {
+        let ship = || Motion {
+            position: [Vec2::new(-100.0, 50.0), Vec2::new(-98.0, 50.0)],
+            rotation: [0.0, 0.1],
+            linear_velocity: [Vec2::ZERO, Vec2::new(100.0, 0.0)],
+            angular_velocity: [0.0, 1.0],
+        };
+        let bullet = || Motion {
+            position: [Vec2::new(0.0, 0.0), Vec2::new(30.0, 40.0)],
+            rotation: [0.5, 0.5],
+            linear_velocity: [Vec2::new(300.0, 400.0); 2],
+            angular_velocity: [0.0, 0.0],
+        };
+        let ball = || Motion {
+            position: [Vec2::new(200.0, -10.0), Vec2::new(201.0, -10.0)],
+            rotation: [0.5, 0.5],
+            linear_velocity: [Vec2::new(10.0, 0.0), Vec2::new(9.9, 0.0)],
+            angular_velocity: [0.0, 0.0],
+        };
+        let mut world: Vec<_> = (0..4).map(|_| ship()).collect();
+        world.extend((0..24).map(|_| bullet()));
+        world.extend((0..2).map(|_| ball()));
+        world
+    }
+
+    /// Bytes of the second update with `PacketCompression::Off`: lightyear's default serde
+    fn full_precision_bytes(motion: &Motion) -> usize {
+        let mut writer = Writer::default();
+        (SerializeFns::<Position>::default().serialize)(&Position(motion.position[1]), &mut writer)
+            .unwrap();
+        (SerializeFns::<Rotation>::default().serialize)(
+            &Rotation::radians(motion.rotation[1]),
+            &mut writer,
+        )
+        .unwrap();
+        (SerializeFns::<LinearVelocity>::default().serialize)(
+            &LinearVelocity(motion.linear_velocity[1]),
+            &mut writer,
+        )
+        .unwrap();
+        (SerializeFns::<AngularVelocity>::default().serialize)(
+            &AngularVelocity(motion.angular_velocity[1]),
+            &mut writer,
+        )
+        .unwrap();
+        writer.len()
+    }
+
+    /// Bytes of the second update with `PacketCompression::Quantized`
+    fn quantized_bytes(motion: &Motion) -> usize {
+        let mut writer = Writer::default();
+        (position_serde().serialize)(&Position(motion.position[1]), &mut writer).unwrap();
+        (rotation_serde().serialize)(&Rotation::radians(motion.rotation[1]), &mut writer).unwrap();
+        (linear_velocity_serde().serialize)(
+            &LinearVelocity(motion.linear_velocity[1]),
+            &mut writer,
+        )
+        .unwrap();
+        (angular_velocity_serde().serialize)(
+            &AngularVelocity(motion.angular_velocity[1]),
+            &mut writer,
+        )
+        .unwrap();
+        writer.len()
+    }
+
+    /// Bytes of the second update with `PacketCompression::Delta`: the position is quantized, the
+    /// deltas use the default serde of their registered types
+    fn delta_bytes(motion: &Motion) -> usize {
+        let mut writer = Writer::default();
+        (position_serde().serialize)(&Position(motion.position[1]), &mut writer).unwrap();
+        let rotation =
+            Rotation::radians(motion.rotation[0]).diff(&Rotation::radians(motion.rotation[1]));
+        (SerializeFns::<ScalarDelta>::default().serialize)(&rotation, &mut writer).unwrap();
+        let linear_velocity = LinearVelocity(motion.linear_velocity[0])
+            .diff(&LinearVelocity(motion.linear_velocity[1]));
+        (SerializeFns::<Vec2Delta>::default().serialize)(&linear_velocity, &mut writer).unwrap();
+        let angular_velocity = AngularVelocity(motion.angular_velocity[0])
+            .diff(&AngularVelocity(motion.angular_velocity[1]));
+        (SerializeFns::<ScalarDelta>::default().serialize)(&angular_velocity, &mut writer).unwrap();
+        writer.len()
+    }
+
+    #[test]
+    fn update_bytes_per_compression() {
+        let world = world();
+        let full: usize = world.iter().map(full_precision_bytes).sum();
+        let quantized: usize = world.iter().map(quantized_bytes).sum();
+        let delta: usize = world.iter().map(delta_bytes).sum();
+        // position 8, rotation 8 (cosine and sine), linear velocity 8, angular velocity 4
+        assert_eq!(full, 30 * 28);
+        assert_eq!(quantized, 30 * 12);
+        // position 4, then ships 10 bytes (every value moved by more than 125 steps), bullets
+        // and balls 4
+        assert_eq!(delta, 4 * 14 + 26 * 8);
+    }
+
+    #[test]
+    fn deltas_land_on_the_serialized_value() {
+        for motion in world() {
+            let mut velocity = LinearVelocity(motion.linear_velocity[0]);
+            let target = LinearVelocity(motion.linear_velocity[1]);
+            velocity.apply_diff(&velocity.diff(&target));
+
+            // what `linear_velocity_serde` reads back from the full update
+            let serialized = target.0.map(|value| {
+                dequantize(
+                    quantize(value, LINEAR_VELOCITY_RANGE),
+                    LINEAR_VELOCITY_RANGE,
+                )
+            });
+            assert_eq!(velocity.0, serialized);
+        }
+    }
+}
@@ -1,9 +1,13 @@
 use crate::networking::entity_label;
 use crate::networking::entity_label::EntityLabel;
 use crate::networking::entity_label::EntityLabelPlugin;
+use crate::networking::health::RESPAWN_DELAY_TICKS;
 use crate::networking::protocol::BallMarker;
 use crate::networking::protocol::BulletMarker;
 use crate::networking::protocol::ColorComponent;
+use crate::networking::protocol::Destroyed;
+use crate::networking::protocol::Health;
+use crate::networking::protocol::Invulnerable;
 use crate::networking::protocol::Player;
 use crate::networking::protocol::PlayerActions;
 use crate::networking::protocol::Score;
@@ -50,12 +54,14 @@ impl Plugin for ExampleRendererPlugin {
                 draw_confirmed_shadows.run_if(move || draw_shadows),
                 draw_predicted_entities,
                 draw_confirmed_entities.run_if(is_server),
+                draw_health_bars,
                 draw_explosions,
             )
                 .chain()
                 .after(bevy::transform::TransformSystem::TransformPropagate),
         );
         app.add_observer(add_player_label);
+        app.add_observer(explode_ship);
 
         app.add_systems(FixedPreUpdate, insert_bullet_mesh);
 
@@ -116,8 +122,11 @@ fn add_visual_interpolation_components(
 fn add_player_label(
     trigger: Trigger<OnAdd, Player>,
     mut commands: Commands,
-    // add the label on both client and server
-    q: Query<(Entity, &Player, &Score), Or<(With<Predicted>, With<Replicating>)>>,
+    // add the label on both client and server, spectators only have interpolated players
+    q: Query<
+        (Entity, &Player, &Score),
+        Or<(With<Predicted>, With<Interpolated>, With<Replicating>)>,
+    >,
 ) {
     if let Ok((e, player, score)) = q.get(trigger.target()) {
         info!("Adding visual bits to {e:?}");
@@ -141,7 +150,7 @@ fn update_player_label(
             Entity,
             &Player,
             &mut EntityLabel,
-            &InputBuffer<ActionState<PlayerActions>>,
+            Option<&InputBuffer<ActionState<PlayerActions>>>,
             &Score,
         ),
         Or<(
@@ -157,11 +166,12 @@ fn update_player_label(
         // hopefully this is positive, ie we have received remote player inputs before they are needed.
         // this can happen because of input_delay. The server receives inputs in advance of
         // needing them, and rebroadcasts to other players.
-        let num_buffered_inputs = if let Some(end_tick) = input_buffer.end_tick() {
-            lightyear::utils::wrapping_id::wrapping_diff(tick.0, end_tick.0)
-        } else {
-            0
-        };
+        let num_buffered_inputs =
+            if let Some(end_tick) = input_buffer.and_then(|input_buffer| input_buffer.end_tick()) {
+                lightyear::utils::wrapping_id::wrapping_diff(tick.0, end_tick.0)
+            } else {
+                0
+            };
         label.text = format!("{} <{}>\n", player.nickname, score.0);
         label.sub_text = format!(
             "{}~{}ms [{num_buffered_inputs}]",
@@ -213,6 +223,8 @@ fn draw_predicted_entities(
             Has<PreSpawned>,
             Option<&ActionState<PlayerActions>>,
             Option<&InputBuffer<ActionState<PlayerActions>>>,
+            Has<Destroyed>,
+            Has<Invulnerable>,
         ),
         (
             // skip drawing bullet outlines, since we add a mesh + material to them
@@ -223,7 +235,19 @@ fn draw_predicted_entities(
     timeline: Single<&LocalTimeline, Without<ClientOf>>,
 ) {
     let tick = timeline.tick();
-    for (e, position, rotation, color, collider, prespawned, opt_action, opt_ib) in &predicted {
+    for (
+        e,
+        position,
+        rotation,
+        color,
+        collider,
+        prespawned,
+        opt_action,
+        opt_ib,
+        destroyed,
+        invulnerable,
+    ) in &predicted
+    {
         // render prespawned translucent until acknowledged by the server
         // (at which point the PreSpawned component is removed)
         let col = if prespawned {
@@ -231,6 +255,23 @@ fn draw_predicted_entities(
         } else {
             color.0
         };
+        // a faint wreck while waiting to respawn, no engine
+        if destroyed {
+            render_shape(
+                collider.shape(),
+                position,
+                rotation,
+                &mut gizmos,
+                col.with_alpha(0.15),
+            );
+            continue;
+        }
+        // blink while invulnerable
+        let col = if invulnerable && (tick.0 / 8) % 2 == 0 {
+            col.with_alpha(0.3)
+        } else {
+            col
+        };
 
         render_shape(collider.shape(), position, rotation, &mut gizmos, col);
         // render engine exhaust for players holding down thrust.
@@ -316,6 +357,57 @@ fn draw_confirmed_entities(
     }
 }
 
+/// Health bar under each ship, and a ring filling up until a destroyed ship respawns
+fn draw_health_bars(
+    mut gizmos: Gizmos,
+    ships: Query<
+        (&Position, &Health, Option<&Destroyed>),
+        (
+            With<Player>,
+            Or<(With<Predicted>, With<Interpolated>, With<Replicating>)>,
+        ),
+    >,
+    timeline: Single<&LocalTimeline, Without<ClientOf>>,
+) {
+    const BAR_WIDTH: f32 = 30.0;
+    let tick = timeline.tick();
+    for (position, health, destroyed) in &ships {
+        if let Some(destroyed) = destroyed {
+            let ticks_left = (destroyed.respawn_tick - tick).max(0) as f32;
+            let progress = 1.0 - ticks_left / RESPAWN_DELAY_TICKS as f32;
+            gizmos.arc_2d(
+                Isometry2d::from_translation(position.0),
+                progress * TAU,
+                SHIP_LENGTH * 0.75,
+                css::ANTIQUE_WHITE.with_alpha(0.6),
+            );
+            continue;
+        }
+        let start = position.0 + Vec2::new(-BAR_WIDTH / 2.0, -SHIP_LENGTH);
+        let end = start + Vec2::X * BAR_WIDTH;
+        let filled = start + Vec2::X * BAR_WIDTH * health.fraction();
+        let color = css::RED.mix(&css::LIMEGREEN, health.fraction());
+        gizmos.line_2d(start, end, css::DIM_GRAY.with_alpha(0.6));
+        gizmos.line_2d(start, filled, color);
+    }
+}
+
+// a big explosion where a ship is destroyed
+fn explode_ship(
+    trigger: Trigger<OnAdd, Destroyed>,
+    ships: Query<(&Position, &ColorComponent), Or<(With<Predicted>, With<Interpolated>)>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    if let Ok((position, color)) = ships.get(trigger.target()) {
+        commands.spawn((
+            Transform::from_xyz(position.x, position.y, 0.0),
+            Visibility::default(),
+            Explosion::ship(time.elapsed(), color.0),
+        ));
+    }
+}
+
 // draws explosion effects, and despawns them once they expire
 fn draw_explosions(
     mut gizmos: Gizmos,
@@ -414,6 +506,16 @@ impl Explosion {
         }
     }
 
+    /// Bigger and longer than a bullet hit
+    pub fn ship(now: Duration, color: Color) -> Self {
+        Self {
+            spawn_time: now,
+            max_age: Duration::from_millis(600),
+            initial_radius: SHIP_LENGTH / 2.0,
+            color: (color.to_linear() * 3.0).into(), // bloom
+        }
+    }
+
     // Gives a color and radius based on elapsed time, for a simple visual explosion effect.
     //
     // None = despawn due to expiry.
@@ -0,0 +1,115 @@
+//! Scoreboard shown while holding Tab in game.
+//!
+//! Lists every player with their `PeerId` and how they are connected (host, udp or steam),
+//! since all three transports can be mixed in a single match. The rows come from the replicated
+//! `Roster`, so players whose ship is out of sight are listed too. With teams on, the team totals (or
+//! goals, in soccer) are shown first.
+use bevy::prelude::*;
+
+use crate::networking::protocol::{Roster, TeamGoals};
+use crate::networking::shared::peer_label;
+use crate::networking::teams::{standings, PlayerScore};
+use crate::{GameCleanUp, GameState, TEXT_COLOR};
+
+pub(crate) struct ScoreboardPlugin;
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), spawn_scoreboard);
+        app.add_systems(
+            Update,
+            (toggle_scoreboard, update_scoreboard).run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct Scoreboard;
+
+#[derive(Component)]
+struct ScoreboardText;
+
+fn spawn_scoreboard(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(80.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            Visibility::Hidden,
+            Scoreboard,
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        padding: UiRect::all(Val::Px(12.0)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.75)),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(""),
+                        TextFont {
+                            font_size: 18.0,
+                            ..default()
+                        },
+                        TextColor(TEXT_COLOR),
+                        ScoreboardText,
+                    ));
+                });
+        });
+}
+
+fn toggle_scoreboard(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut scoreboard: Single<&mut Visibility, With<Scoreboard>>,
+) {
+    let visibility = if keys.pressed(KeyCode::Tab) {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    scoreboard.set_if_neq(visibility);
+}
+
+fn update_scoreboard(
+    roster: Query<&Roster>,
+    goals: Query<&TeamGoals>,
+    mut text: Single<&mut Text, With<ScoreboardText>>,
+) {
+    let mut rows: Vec<_> = roster.iter().flat_map(|roster| roster.0.iter()).collect();
+    rows.sort_by(|a, b| b.score.cmp(&a.score));
+
+    let mut board = String::new();
+    if rows.iter().any(|entry| entry.team.is_some()) {
+        let scores: Vec<_> = rows.iter().map(|entry| PlayerScore::from(*entry)).collect();
+        for (team, score) in standings(&scores, goals.single().ok()) {
+            board.push_str(&format!("{team:<20} {score:>6}\n"));
+        }
+        board.push('\n');
+    }
+    board.push_str(&format!(
+        "{:<20} {:>6} {:>5} {:>8} {:<28} {:>6}\n",
+        "Player", "Score", "Team", "Ping", "PeerId", "Via"
+    ));
+    for entry in rows {
+        board.push_str(&format!(
+            "{:<20} {:>6} {:>5} {:>6}ms {:<28} {:>6}\n",
+            entry.nickname,
+            entry.score,
+            entry.team.map_or("-", |team| team.label()),
+            entry.rtt.as_millis(),
+            peer_label(entry.client_id),
+            entry.connection_kind.map_or("?", |kind| kind.label()),
+        ));
+    }
+    if text.0 != board {
+        text.0 = board;
+    }
+}
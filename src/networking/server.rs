@@ -9,16 +9,47 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::networking::chat::ChatServerPlugin;
+use crate::networking::console::ConsoleServerPlugin;
+use crate::networking::game_mode::{ArenaEntity, GameModeServerPlugin, GameModes};
+use crate::networking::handshake::{HandshakeAccepted, HandshakeServerPlugin};
+use crate::networking::health::HealthServerPlugin;
+use crate::networking::host_migration::HostMigrationServerPlugin;
+use crate::networking::host_migration::MigrationSeed;
+use crate::networking::interest::InterestServerPlugin;
+use crate::networking::lag_compensation::LagCompensationServerPlugin;
+use crate::networking::lobby_data::{LobbyDataServerPlugin, LobbySettings};
+use crate::networking::lobby_join::RICH_PRESENCE_CONNECT;
+use crate::networking::lobby_room::LobbyRoomServerPlugin;
+use crate::networking::match_state::MatchStateServerPlugin;
+use crate::networking::moderation::{
+    BanList, ModerationCommand, ModerationServerPlugin, PendingKick,
+};
+use crate::networking::network_stats::NetworkStatsHistory;
+use crate::networking::power_ups::PowerUpsServerPlugin;
+use crate::networking::priority::PriorityServerPlugin;
 use crate::networking::protocol::BallMarker;
-use crate::networking::protocol::BulletHitEvent;
 use crate::networking::protocol::ColorComponent;
+use crate::networking::protocol::ConnectionKind;
+use crate::networking::protocol::MatchRoom;
 use crate::networking::protocol::PhysicsBundle;
 use crate::networking::protocol::Player;
 use crate::networking::protocol::PlayerActions;
+use crate::networking::protocol::Ready;
 use crate::networking::protocol::Score;
+use crate::networking::protocol::Team;
 use crate::networking::protocol::Weapon;
+use crate::networking::protocol::WeaponKind;
+use crate::networking::quantize::QuantizationServerPlugin;
 use crate::networking::shared;
 use crate::networking::shared::*;
+use crate::networking::shutdown_notice::{
+    PendingShutdown, ShutdownNoticeServerPlugin, ShutdownNoticeSet, SHUTDOWN_NOTICE_DELAY,
+};
+use crate::networking::soccer::SoccerServerPlugin;
+use crate::networking::spectator::{Spectator, SpectatorServerPlugin};
+use crate::networking::steam_backend::{SteamPlatform, SteamworksBackend};
+use crate::networking::teams::{smaller_team, PaletteColor, TeamsServerPlugin};
 use crate::ClientCommands;
 use crate::GameState;
 use crate::MultiplayerState;
@@ -30,13 +61,11 @@ use bevy::time::common_conditions::on_timer;
 use crossbeam_channel::Receiver;
 use crossbeam_channel::Sender;
 use leafwing_input_manager::prelude::ActionState;
-use lightyear::connection::client::PeerMetadata;
 use lightyear::crossbeam::CrossbeamIo;
 use lightyear::link::Unlink;
 use lightyear::prelude::server::*;
 use lightyear::prelude::*;
 use parking_lot::Mutex;
-use std::f32::consts::TAU;
 use steamworks::LobbyId;
 
 #[derive(Resource)]
@@ -52,11 +81,6 @@ pub struct ServerStartupResources {
         Option<Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<LobbyId>>>>,
 }
 
-#[derive(Resource)]
-pub struct SteamSingleClient {
-    pub steam: Arc<Mutex<lightyear::prelude::steamworks::SingleClient>>,
-}
-
 pub struct ExampleServerPlugin {
     pub just_server: bool,
     pub server_crossbeam: Option<CrossbeamIo>,
@@ -73,7 +97,14 @@ pub struct Global {
 
 impl Plugin for ExampleServerPlugin {
     fn build(&self, app: &mut App) {
-        // Create the server immediately
+        // Create the server immediately.
+        // A single server entity serves every transport, so that all players share one world and
+        // `Replicate::to_clients` reaches all of them:
+        // - `ServerUdpIo` accepts netcode clients over udp
+        // - the host's crossbeam link is attached to this entity in `start_server` as an already
+        //   linked `LinkOf`, it skips the netcode handshake and gets `HOST_CLIENT_ID` as its `RemoteId`
+        // - `SteamServerIo` (added below when steam is available) accepts steam P2P peers,
+        //   which are authenticated by steam instead of netcode
         let server_entity = app
             .world_mut()
             .spawn((
@@ -100,10 +131,10 @@ impl Plugin for ExampleServerPlugin {
 
             app.insert_resource(SteamworksClient(steam.clone()));
 
-            let resource = SteamSingleClient {
-                steam: wrapped_single_client.clone(),
-            };
-            app.insert_resource(resource);
+            app.insert_resource(SteamPlatform::new(SteamworksBackend::new(
+                steam.clone(),
+                wrapped_single_client.clone(),
+            )));
             app.add_systems(
                 PreUpdate,
                 steam_callbacks.run_if(in_state(MultiplayerState::Server)),
@@ -113,7 +144,9 @@ impl Plugin for ExampleServerPlugin {
             app.world_mut()
                 .entity_mut(server_entity)
                 .insert(SteamServerIo {
-                    target: ListenTarget::Peer { virtual_port: 4001 },
+                    target: ListenTarget::Peer {
+                        virtual_port: STEAM_VIRTUAL_PORT,
+                    },
                     config: SessionConfig::default(),
                 });
         }
@@ -127,30 +160,60 @@ impl Plugin for ExampleServerPlugin {
                 server_commands: self.server_send_commands.clone().unwrap().clone(),
             });
             app.add_systems(FixedUpdate, handle_client_commands);
+            app.add_systems(
+                Update,
+                finish_pending_shutdown
+                    .run_if(resource_exists::<PendingShutdown>)
+                    .after(ShutdownNoticeSet),
+            );
         }
 
         // app.add_systems(OnEnter(GameState::Game), init.run_if(in_state(MultiplayerState::Server).or(in_state(MultiplayerState::HostServer))));
         app.add_systems(OnEnter(MultiplayerState::Server), start_server);
 
         app.insert_resource(Global { predict_all: true });
-        app.add_systems(OnEnter(MultiplayerState::Server), init);
         // the physics/FixedUpdates systems that consume inputs should be run in this set
         app.add_systems(
             FixedUpdate,
-            (player_movement, shared::shared_player_firing).chain(),
+            (player_movement, shared::shared_player_firing)
+                .chain()
+                .run_if(in_state(GameState::Game)),
         );
-        app.add_observer(handle_new_client);
+        app.add_plugins(ShutdownNoticeServerPlugin);
+        app.add_plugins(HandshakeServerPlugin);
+        app.add_plugins(SpectatorServerPlugin);
+        app.add_plugins(InterestServerPlugin);
+        app.add_plugins(PriorityServerPlugin);
+        app.add_plugins(QuantizationServerPlugin);
+        app.add_plugins(LagCompensationServerPlugin);
+        app.add_plugins(GameModeServerPlugin);
+        app.add_plugins(MatchStateServerPlugin);
+        app.add_plugins(TeamsServerPlugin);
+        app.add_plugins(HealthServerPlugin);
+        app.add_plugins(SoccerServerPlugin);
+        app.add_plugins(PowerUpsServerPlugin);
+        app.add_plugins(ChatServerPlugin);
+        app.add_plugins(LobbyDataServerPlugin);
+        app.add_plugins(LobbyRoomServerPlugin {
+            auto_start: self.just_server,
+        });
+        app.add_plugins(ModerationServerPlugin);
+        if self.just_server {
+            app.add_plugins(ConsoleServerPlugin);
+        }
+
+        if !self.just_server {
+            // a listen-server can hand the match over to another client when the host leaves
+            app.add_plugins(HostMigrationServerPlugin);
+        }
+
         app.add_observer(handle_connections);
         app.add_systems(
             Update,
-            (update_player_metrics.run_if(on_timer(Duration::from_secs(1))),),
-        );
-
-        app.add_systems(
-            FixedUpdate,
-            handle_hit_event
-                .run_if(on_event::<BulletHitEvent>)
-                .after(shared::process_collisions),
+            (
+                update_player_metrics.run_if(on_timer(Duration::from_secs(1))),
+                log_link_stats.run_if(on_timer(Duration::from_secs(10))),
+            ),
         );
 
         app.add_systems(Update, talk_to_me);
@@ -164,28 +227,29 @@ fn talk_to_me(server_q: Query<(Entity, Has<RemoteId>, Has<ReplicationSender>), W
     // }
 }
 
-fn steam_callbacks(steam: ResMut<SteamSingleClient>, server_q: Query<Entity, With<Started>>) {
+fn steam_callbacks(steam: Res<SteamPlatform>, server_q: Query<Entity, With<Started>>) {
     if server_q.is_empty() {
         // If the server is not started, we don't need to run the callbacks
         return;
     }
     // This system is responsible for running the Steamworks callbacks
     // It should be run every frame to ensure that the Steamworks API works correctly
-    steam.steam.lock().run_callbacks();
+    steam.run_callbacks();
 }
 
 pub fn start_server(
     mut commands: Commands,
     server_q: Query<Entity, With<Server>>,
     mut server_startup: ResMut<ServerStartupResources>,
-    steam_works: Option<Res<SteamworksClient>>,
+    steam: Option<Res<SteamPlatform>>,
+    lobby_settings: Res<LobbySettings>,
 ) {
     if let Some(server) = server_q.iter().next() {
         commands.trigger_targets(Start, server);
 
         if !server_startup.just_server {
             if let Some(server_crossbeam) = &server_startup.server_crossbeam {
-                // You need to provide a valid client_id here. For demonstration, we'll use 12345.
+                // The host link is trusted: it is linked right away with the reserved HOST_CLIENT_ID
                 info!("Add a Linked connection for host client to server");
 
                 let mut entity = commands.spawn(LinkOf { server: server });
@@ -195,19 +259,18 @@ pub fn start_server(
                 entity.insert(Link::new(None));
                 entity.insert(Linked);
                 entity.insert(server_crossbeam.clone());
-                entity.insert(RemoteId(PeerId::Netcode(1)));
+                entity.insert(RemoteId(PeerId::Netcode(HOST_CLIENT_ID)));
             }
         }
 
-        if let Some(steam_work) = steam_works {
+        if let Some(steam) = steam {
             let shared_data: Arc<
                 parking_lot::lock_api::Mutex<parking_lot::RawMutex, Option<LobbyId>>,
             > = Arc::new(Mutex::new(None));
             let cloned_data = shared_data.clone();
-            steam_work.matchmaking().create_lobby(
-                steamworks::LobbyType::FriendsOnly,
-                10,
-                move |result: Result<LobbyId, steamworks::SteamError>| {
+            steam.create_lobby(
+                lobby_settings.max_players,
+                Box::new(move |result| {
                     match result {
                         Ok(lobby_id) => {
                             shared_data.clone().lock().replace(lobby_id);
@@ -218,7 +281,7 @@ pub fn start_server(
                             eprintln!("Error creating lobby: {:?}", e);
                         }
                     }
-                },
+                }),
             );
 
             server_startup.steam_lobby_id = Some(cloned_data);
@@ -248,44 +311,111 @@ pub(crate) fn handle_client_commands(
     mut commands: Commands,
     mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
     mut game_state: ResMut<NextState<GameState>>,
-    mut server_q: Query<Entity, With<Server>>,
+    server_q: Query<Entity, With<Server>>,
     mut server_startup: ResMut<ServerStartupResources>,
-    steam_works: Option<Res<SteamworksClient>>,
+    steam: Option<Res<SteamPlatform>>,
+    remote_clients: Query<(), (With<ClientOf>, With<Connected>, Without<CrossbeamIo>)>,
+    pending_shutdown: Option<Res<PendingShutdown>>,
+    mut moderation_commands: EventWriter<ModerationCommand>,
 ) {
     for c in client_commands.read() {
         match c {
             ClientCommands::StartServer => {
                 info!("Server received StartServer command");
+                // a shutdown still counting down would stop the new server
+                commands.remove_resource::<PendingShutdown>();
+                multiplayer_state.set(MultiplayerState::Server);
+                game_state.set(GameState::Lobby);
+            }
+            ClientCommands::StartMigratedServer(snapshot) => {
+                info!("Server received StartMigratedServer command, taking over as host");
+                commands.remove_resource::<PendingShutdown>();
+                commands.insert_resource(MigrationSeed(snapshot.clone()));
                 multiplayer_state.set(MultiplayerState::Server);
                 game_state.set(GameState::Game);
             }
+            ClientCommands::Moderation(command) => {
+                moderation_commands.write(command.clone());
+            }
             ClientCommands::StopServer => {
                 info!("Server received StopServer command");
-                if let Some(server) = server_q.iter().next() {
-                    if let Some(ref steam_work) = steam_works {
-                        if let Some(lobby_arc) = server_startup.steam_lobby_id.clone() {
-                            if let Some(lobby_id) = *lobby_arc.lock() {
-                                steam_work.matchmaking().leave_lobby(lobby_id);
-                            }
-                        }
-                        server_startup.steam_lobby_id = None;
-                    }
-
-                    commands.trigger_targets(
-                        Unlink {
-                            reason: "Stopping Server".to_string(),
-                        },
-                        server,
+                if remote_clients.is_empty() || pending_shutdown.is_some() {
+                    stop_server(
+                        &mut commands,
+                        server_q.iter().next(),
+                        steam.as_deref(),
+                        &mut server_startup,
                     );
-                    commands.trigger_targets(Stop, server);
+                    multiplayer_state.set(MultiplayerState::None);
+                    game_state.set(GameState::Menu);
+                } else {
+                    // give the remote players a moment to see why they are about to be disconnected
+                    info!(
+                        "Stopping server in {}s, notifying remote clients",
+                        SHUTDOWN_NOTICE_DELAY.as_secs()
+                    );
+                    commands.insert_resource(PendingShutdown::new(
+                        "Host left the game",
+                        SHUTDOWN_NOTICE_DELAY,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Stop the server once the shutdown notice countdown is over
+fn finish_pending_shutdown(
+    pending_shutdown: Res<PendingShutdown>,
+    mut commands: Commands,
+    mut multiplayer_state: ResMut<NextState<MultiplayerState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    server_q: Query<Entity, With<Server>>,
+    mut server_startup: ResMut<ServerStartupResources>,
+    steam: Option<Res<SteamPlatform>>,
+) {
+    if !pending_shutdown.is_finished() {
+        return;
+    }
+    stop_server(
+        &mut commands,
+        server_q.iter().next(),
+        steam.as_deref(),
+        &mut server_startup,
+    );
+    multiplayer_state.set(MultiplayerState::None);
+    game_state.set(GameState::Menu);
+}
 
-                    info!("Server Stopped");
+fn stop_server(
+    commands: &mut Commands,
+    server: Option<Entity>,
+    steam: Option<&SteamPlatform>,
+    server_startup: &mut ServerStartupResources,
+) {
+    if let Some(server) = server {
+        if let Some(steam) = steam {
+            if let Some(lobby_arc) = server_startup.steam_lobby_id.clone() {
+                if let Some(lobby_id) = *lobby_arc.lock() {
+                    steam.leave_lobby(lobby_id);
+                    steam.set_rich_presence(RICH_PRESENCE_CONNECT, None);
                 }
-                multiplayer_state.set(MultiplayerState::None);
-                game_state.set(GameState::Menu);
             }
+            server_startup.steam_lobby_id = None;
         }
+
+        commands.trigger_targets(
+            Unlink {
+                reason: "Stopping Server".to_string(),
+            },
+            server,
+        );
+        commands.trigger_targets(Stop, server);
+
+        info!("Server Stopped");
     }
+    commands.remove_resource::<MigrationSeed>();
+    commands.remove_resource::<PendingShutdown>();
 }
 
 /// Since Player is replicated, this allows the clients to display remote players' latency stats.
@@ -301,53 +431,94 @@ fn update_player_metrics(
     }
 }
 
-fn init(mut commands: Commands) {
-    // the balls are server-authoritative
-    const NUM_BALLS: usize = 6;
-    for i in 0..NUM_BALLS {
-        let radius = 10.0 + i as f32 * 4.0;
-        let angle: f32 = i as f32 * (TAU / NUM_BALLS as f32);
-        let pos = Vec2::new(125.0 * angle.cos(), 125.0 * angle.sin());
-        let ball = BallMarker::new(radius);
-        commands.spawn((
-            Position(pos),
-            ColorComponent(css::GOLD.into()),
-            ball.physics_bundle(),
-            ball,
-            Name::new("Ball"),
-            Replicate::to_clients(NetworkTarget::All),
-            PredictionTarget::to_clients(NetworkTarget::All),
-        ));
+/// Print a one line summary of every link, so that a headless server also shows network health.
+fn log_link_stats(links: Query<(&RemoteId, &NetworkStatsHistory), With<LinkOf>>) {
+    for (remote_id, stats) in links.iter() {
+        if let Some(sample) = stats.latest() {
+            info!(
+                "{:?}: send {:.1}kB/s recv {:.1}kB/s loss {:.1}% rtt {}~{}ms inputs [{}]",
+                remote_id.0,
+                sample.send_bytes_per_sec / 1000.0,
+                sample.recv_bytes_per_sec / 1000.0,
+                sample.packet_loss * 100.0,
+                sample.rtt.as_millis(),
+                sample.jitter.as_millis(),
+                sample.input_buffer,
+            );
+        }
     }
 }
 
-/// Add the ReplicationSender component to new clients
-pub(crate) fn handle_new_client(trigger: Trigger<OnAdd, ClientOf>, mut commands: Commands) {
-    info!(
-        "remote id added, adding replication sender {}",
-        trigger.target()
-    );
-    commands
-        .entity(trigger.target())
-        .insert(ReplicationSender::new(
-            SERVER_REPLICATION_INTERVAL,
-            SendUpdatesMode::SinceLastAck,
-            false,
-        ));
+pub(crate) fn spawn_ball(commands: &mut Commands, pos: Vec2, radius: f32) {
+    let ball = BallMarker::new(radius);
+    commands.spawn((
+        Position(pos),
+        ColorComponent(css::GOLD.into()),
+        ball.physics_bundle(),
+        ball,
+        Name::new("Ball"),
+        Replicate::to_clients(NetworkTarget::All),
+        PredictionTarget::to_clients(NetworkTarget::All),
+        ArenaEntity,
+    ));
+}
+
+/// Start replicating to a link. Only done once it passed the protocol handshake: a client running
+/// another build couldn't decode the updates.
+fn start_replication(commands: &mut Commands, link: Entity) {
+    commands.entity(link).insert(ReplicationSender::new(
+        SERVER_REPLICATION_INTERVAL,
+        SendUpdatesMode::SinceLastAck,
+        // send the highest priority updates first within the link's `BandwidthBudget`
+        true,
+    ));
 }
 
-/// Whenever a new client connects, spawn their spaceship
+/// Once a new client passed the protocol handshake, start replicating to it and spawn its spaceship
 pub(crate) fn handle_connections(
-    trigger: Trigger<OnAdd, Connected>,
-    query: Query<&RemoteId, With<ClientOf>>,
+    trigger: Trigger<HandshakeAccepted>,
+    query: Query<(&RemoteId, Option<&PeerAddr>, Has<CrossbeamIo>), With<ClientOf>>,
     mut commands: Commands,
-    all_players: Query<Entity, With<Player>>,
+    all_players: Query<(&Player, Option<&Team>)>,
+    seed: Option<Res<MigrationSeed>>,
+    ban_list: Res<BanList>,
+    modes: Res<GameModes>,
+    lobby_settings: Res<LobbySettings>,
+    room: Query<&MatchRoom>,
 ) {
-    // track the number of connected players in order to pick colors and starting positions
+    // track the number of connected players in order to pick colors
     let player_n = all_players.iter().count();
-    if let Ok(remote_id) = query.get(trigger.target()) {
+    if let Ok((remote_id, peer_addr, is_crossbeam)) = query.get(trigger.target()) {
         let client_id = remote_id.0;
-        info!("New connected client, client_id: {client_id:?}. Spawning player entity..");
+        if let Some(ban) = ban_list.find(client_id, peer_addr.map(|addr| addr.0.ip())) {
+            info!(
+                "Rejecting banned client {}: {}",
+                peer_label(client_id),
+                ban.reason
+            );
+            commands
+                .entity(trigger.target())
+                .insert(PendingKick::new(ban.describe_now()));
+            return;
+        }
+        if trigger.event().spectator {
+            info!("{} joined as a spectator", peer_label(client_id));
+            // marked first, so replication starts with the spectator targets
+            commands.entity(trigger.target()).insert(Spectator);
+            start_replication(&mut commands, trigger.target());
+            return;
+        }
+        start_replication(&mut commands, trigger.target());
+        let connection_kind = match client_id {
+            PeerId::Steam(_) => ConnectionKind::Steam,
+            _ if is_crossbeam => ConnectionKind::Local,
+            _ => ConnectionKind::Udp,
+        };
+        info!(
+            "New connected client, client_id: {} ({}). Spawning player entity..",
+            peer_label(client_id),
+            connection_kind.label()
+        );
         // pick color and x,y pos for player
         let available_colors = [
             css::LIMEGREEN,
@@ -363,19 +534,39 @@ pub(crate) fn handle_connections(
             css::WHITE,
             css::RED,
         ];
-        let col = available_colors[player_n % available_colors.len()];
-        let angle: f32 = player_n as f32 * 5.0;
-        let x = 200.0 * angle.cos();
-        let y = 200.0 * angle.sin();
+        let col: Color = available_colors[player_n % available_colors.len()].into();
+        // with teams on, join the smaller team and wear its color
+        let team = room
+            .single()
+            .is_ok_and(|room| room.teams)
+            .then(|| smaller_team(all_players.iter().filter_map(|(_, team)| team)));
+        let spawn_slot = free_spawn_slot(all_players.iter().map(|(player, _)| player.spawn_slot));
+        let mut position = modes
+            .get_or_default(&lobby_settings.mode)
+            .spawn_position(spawn_slot, team);
+        let mut nickname = pick_player_name(client_id.to_bits());
+        let mut score = 0;
+
+        // players coming back after a host migration keep their name, score and position, when
+        // the successor could see their ship
+        if let Some(previous) = seed
+            .as_ref()
+            .and_then(|seed| seed.0.find_player(trigger.event().session_key))
+        {
+            info!("Restoring {} from the previous host", previous.nickname);
+            position = previous.position.unwrap_or(position);
+            nickname = previous.nickname.clone();
+            score = previous.score;
+        }
 
         // spawn the player with ActionState - the client will add their own InputMap
         let player_ent = commands
             .spawn((
-                Player::new(client_id, pick_player_name(client_id.to_bits())),
-                Score(0),
+                Player::new(client_id, nickname, trigger.event().session_key, spawn_slot),
+                Score(score),
                 Name::new("Player"),
                 ActionState::<PlayerActions>::default(),
-                Position(Vec2::new(x, y)),
+                Position(position),
                 Replicate::to_clients(NetworkTarget::All),
                 PredictionTarget::to_clients(NetworkTarget::All),
                 ControlledBy {
@@ -385,14 +576,26 @@ pub(crate) fn handle_connections(
                 // prevent rendering children to be replicated
                 DisableReplicateHierarchy,
                 PhysicsBundle::player_ship(),
-                Weapon::new((FIXED_TIMESTEP_HZ / 5.0) as u16),
-                ColorComponent(col.into()),
+                Weapon::new(WeaponKind::default()),
+                ColorComponent(team.map_or(col, |team| team.color())),
+                PaletteColor(col),
+                connection_kind,
+                Ready::default(),
             ))
             .id();
+        if let Some(team) = team {
+            commands.entity(player_ent).insert(team);
+        }
         info!("Created entity {player_ent:?} for client {client_id:?}");
     }
 }
 
+/// The smallest spawn slot no player has
+fn free_spawn_slot(taken: impl Iterator<Item = usize>) -> usize {
+    let taken: Vec<_> = taken.collect();
+    (0..).find(|slot| !taken.contains(slot)).unwrap()
+}
+
 fn pick_player_name(client_id: u64) -> String {
     let index = (client_id % NAMES.len() as u64) as usize;
     NAMES[index].to_string()
@@ -436,31 +639,6 @@ const NAMES: [&str; 35] = [
     "Mr. T",
 ];
 
-/// Server will manipulate scores when a bullet collides with a player.
-/// the `Score` component is a simple replication. Score is fully server-authoritative.
-pub(crate) fn handle_hit_event(
-    peer_metadata: Res<PeerMetadata>,
-    mut events: EventReader<BulletHitEvent>,
-    mut player_q: Query<(&Player, &mut Score)>,
-) {
-    let client_id_to_player_entity =
-        |client_id: PeerId| -> Option<Entity> { peer_metadata.mapping.get(&client_id).copied() };
-
-    for ev in events.read() {
-        // did they hit a player?
-        if let Some(victim_entity) = ev.victim_client_id.and_then(client_id_to_player_entity) {
-            if let Ok((player, mut score)) = player_q.get_mut(victim_entity) {
-                score.0 -= 1;
-            }
-            if let Some(shooter_entity) = client_id_to_player_entity(ev.bullet_owner) {
-                if let Ok((player, mut score)) = player_q.get_mut(shooter_entity) {
-                    score.0 += 1;
-                }
-            }
-        }
-    }
-}
-
 /// Read inputs and move players
 ///
 /// If we didn't receive the input for a given player, we do nothing (which is the default behaviour from lightyear),
@@ -13,26 +13,52 @@ use lightyear::input::input_buffer::InputBuffer;
 use lightyear::prelude::*;
 use tracing::Level;
 
+use crate::networking::power_ups::{thrust_multiplier, FIRE_RATE_BOOST};
 use crate::networking::protocol::{
-    BulletHitEvent, BulletLifetime, BulletMarker, ColorComponent, PhysicsBundle, Player,
-    PlayerActions, Weapon, BULLET_SIZE, SHIP_LENGTH,
+    BulletHitEvent, BulletLifetime, BulletMarker, ColorComponent, Destroyed, FireRateBoost,
+    FriendlyFire, MatchRoom, PhysicsBundle, Player, PlayerActions, SpeedBoost, Team, Weapon,
+    SHIP_LENGTH,
 };
+use crate::networking::quantize::PacketCompression;
+use crate::networking::teams::is_friendly;
+use crate::networking::weapons::{projectile_salt, PiercedShips};
 use crate::{GameCleanUp, GameState};
 
 pub(crate) const MAX_VELOCITY: f32 = 200.0;
 pub(crate) const WALL_SIZE: f32 = 350.0;
+/// Maps the host can pick in the lobby. Every map is inside the same square of walls.
+pub const MAPS: &[&str] = &["Arena", "Pillars"];
+/// Distance of the `Pillars` map's pillars to both axes, clear of the spawn positions and balls
+const PILLAR_OFFSET: f32 = 200.0;
+const PILLAR_HALF_SIZE: f32 = 25.0;
 pub const SERVER_REPLICATION_INTERVAL: Duration = Duration::from_millis(100);
 pub const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 5000);
 pub const FIXED_TIMESTEP_HZ: f64 = 64.0;
+/// Netcode client id reserved for the host's crossbeam link, so it can't clash with a udp client
+pub const HOST_CLIENT_ID: u64 = 1;
+/// Steam P2P virtual port the server listens on
+pub const STEAM_VIRTUAL_PORT: i32 = 4001;
+/// Published in the steam lobby, clients can only join a server running the same version
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Size of the steam lobby created by the host
+pub const MAX_PLAYERS: u32 = 10;
 
 #[derive(Clone)]
 pub struct SharedPlugin {
     pub(crate) show_confirmed: bool,
+    /// Must be the same on every peer, see `quantize`
+    pub(crate) compression: PacketCompression,
 }
 
 impl Plugin for SharedPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(super::protocol::ProtocolPlugin);
+        app.add_plugins(super::protocol::ProtocolPlugin {
+            compression: self.compression,
+        });
+        app.add_plugins(super::network_stats::NetworkStatsPlugin);
+        app.add_plugins(super::game_mode::GameModePlugin);
+        app.add_plugins(super::health::HealthPlugin);
+        app.add_plugins(super::power_ups::PowerUpsPlugin);
 
         // bundles
         app.add_systems(OnEnter(GameState::Game), init);
@@ -60,6 +86,15 @@ impl Plugin for SharedPlugin {
     }
 }
 
+/// Short human readable form of a `PeerId`, used in the scoreboard and server logs
+pub fn peer_label(peer: PeerId) -> String {
+    match peer {
+        PeerId::Netcode(id) => format!("netcode:{id}"),
+        PeerId::Steam(id) => format!("steam:{id}"),
+        other => format!("{other:?}"),
+    }
+}
+
 pub(crate) fn color_from_id(client_id: PeerId) -> Color {
     let h = (((client_id.to_bits().wrapping_mul(30)) % 360) as f32) / 360.0;
     let s = 1.0;
@@ -67,7 +102,23 @@ pub(crate) fn color_from_id(client_id: PeerId) -> Color {
     Color::hsl(h, s, l)
 }
 
-pub(crate) fn init(mut commands: Commands) {
+/// Obstacles inside the arena walls of `map`
+pub(crate) fn map_obstacles(map: &str) -> Vec<Rect> {
+    match map {
+        "Pillars" => [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| {
+                Rect::from_center_half_size(
+                    Vec2::new(x, y) * PILLAR_OFFSET,
+                    Vec2::splat(PILLAR_HALF_SIZE),
+                )
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub(crate) fn init(mut commands: Commands, room: Query<&MatchRoom>) {
     commands.spawn(WallBundle::new(
         Vec2::new(-WALL_SIZE, -WALL_SIZE),
         Vec2::new(-WALL_SIZE, WALL_SIZE),
@@ -88,6 +139,21 @@ pub(crate) fn init(mut commands: Commands) {
         Vec2::new(-WALL_SIZE, -WALL_SIZE),
         Color::WHITE,
     ));
+
+    // the clients only enter the game once the replicated room has started, so they know the map
+    let map = room.iter().next().map_or(MAPS[0], |room| room.map.as_str());
+    for obstacle in map_obstacles(map) {
+        let corners = [
+            obstacle.min,
+            Vec2::new(obstacle.min.x, obstacle.max.y),
+            obstacle.max,
+            Vec2::new(obstacle.max.x, obstacle.min.y),
+        ];
+        for (i, start) in corners.iter().enumerate() {
+            let end = corners[(i + 1) % corners.len()];
+            commands.spawn(WallBundle::new(*start, end, Color::WHITE));
+        }
+    }
 }
 
 #[derive(QueryData)]
@@ -97,6 +163,8 @@ pub struct ApplyInputsQuery {
     pub ang_vel: &'static mut AngularVelocity,
     pub rot: &'static Rotation,
     pub player: &'static Player,
+    pub destroyed: Has<Destroyed>,
+    pub speed_boost: Has<SpeedBoost>,
 }
 
 /// applies forces based on action state inputs
@@ -105,6 +173,10 @@ pub fn apply_action_state_to_player_movement(
     aiq: &mut ApplyInputsQueryItem,
     tick: Tick,
 ) {
+    // destroyed ships wait for their respawn
+    if aiq.destroyed {
+        return;
+    }
     let ex_force = &mut aiq.ex_force;
     let rot = &aiq.rot;
     let ang_vel = &mut aiq.ang_vel;
@@ -112,9 +184,10 @@ pub fn apply_action_state_to_player_movement(
     const THRUSTER_POWER: f32 = 32000.;
     const ROTATIONAL_SPEED: f32 = 4.0;
 
+    let thruster_power = THRUSTER_POWER * thrust_multiplier(aiq.speed_boost);
     if action.pressed(&PlayerActions::Up) {
         ex_force
-            .apply_force(*rot * (Vec2::Y * THRUSTER_POWER))
+            .apply_force(*rot * (Vec2::Y * thruster_power))
             .with_persistence(false);
     }
     let desired_ang_vel = if action.pressed(&PlayerActions::Left) {
@@ -151,6 +224,8 @@ pub fn shared_player_firing(
             &mut Weapon,
             Has<Controlled>,
             &Player,
+            Has<Destroyed>,
+            Has<FireRateBoost>,
         ),
         Or<(With<Predicted>, With<Replicate>)>,
     >,
@@ -173,6 +248,8 @@ pub fn shared_player_firing(
         mut weapon,
         is_local,
         player,
+        destroyed,
+        fire_rate_boost,
     ) in q.iter_mut()
     {
         if !is_server && !is_local {
@@ -182,14 +259,25 @@ pub fn shared_player_firing(
             // visually distracting to temporarily see a fake bullet that then disappears.
             continue;
         }
-        if !action.pressed(&PlayerActions::Fire) {
+        // Q switches to the next weapon
+        if action.just_pressed(&PlayerActions::SwitchWeapon) {
+            weapon.kind = weapon.kind.next();
+        }
+        if destroyed || !action.pressed(&PlayerActions::Fire) {
             continue;
         }
 
         // info!(?current_tick, player = ?player.client_id, "Buffer: {buffer}");
 
+        let def = weapon.def();
+        let cooldown = if fire_rate_boost {
+            (def.cooldown / FIRE_RATE_BOOST).max(1)
+        } else {
+            def.cooldown
+        };
         let wrapped_diff = weapon.last_fire_tick - current_tick;
-        if wrapped_diff.abs() <= weapon.cooldown as i16 {
+        // fires exactly every `cooldown` ticks
+        if wrapped_diff.abs() < cooldown as i16 {
             // cooldown period - can't fire.
             if weapon.last_fire_tick == current_tick {
                 // logging because debugging latency edge conditions where
@@ -203,42 +291,55 @@ pub fn shared_player_firing(
         let prev_last_fire_tick = weapon.last_fire_tick;
         weapon.last_fire_tick = current_tick;
 
-        // bullet spawns just in front of the nose of the ship, in the direction the ship is facing,
-        // and inherits the speed of the ship.
-        let bullet_spawn_offset = Vec2::Y * (2.0 + (SHIP_LENGTH + BULLET_SIZE) / 2.0);
-
-        let bullet_origin = player_position.0 + player_rotation * bullet_spawn_offset;
-        let bullet_linvel = player_rotation * (Vec2::Y * weapon.bullet_speed) + player_velocity.0;
-
-        // the default hashing algorithm uses the tick and component list. in order to disambiguate
-        // between two players spawning a bullet on the same tick, we add client_id to the mix.
-        let prespawned = PreSpawned::default_with_salt(player.client_id.to_bits());
-
-        let bullet_entity = commands
-            .spawn((
-                Position(bullet_origin),
-                LinearVelocity(bullet_linvel),
-                ColorComponent((color.0.to_linear() * 5.0).into()), // bloom !
-                BulletLifetime {
-                    origin_tick: current_tick,
-                    lifetime: FIXED_TIMESTEP_HZ as i16 * 2,
-                },
-                BulletMarker::new(player.client_id),
-                PhysicsBundle::bullet(),
-                prespawned,
-            ))
-            .id();
-        debug!(
-            pressed=?action.get_pressed(),
-            "spawned bullet for ActionState, bullet={bullet_entity:?} ({}, {}). prev last_fire tick: {prev_last_fire_tick:?}",
-            weapon.last_fire_tick.0, player.client_id
-        );
-
-        if is_server {
-            commands.entity(bullet_entity).insert((
-                Replicate::to_clients(NetworkTarget::All),
-                PredictionTarget::to_clients(NetworkTarget::All),
-            ));
+        // bullets spawn just in front of the nose of the ship, fanned out around the direction
+        // the ship is facing, and inherit the speed of the ship.
+        let bullet_spawn_offset = Vec2::Y * (2.0 + (SHIP_LENGTH + def.size) / 2.0);
+
+        for index in 0..def.projectiles {
+            let direction = Vec2::from_angle(def.projectile_angle(index));
+            let bullet_origin =
+                player_position.0 + player_rotation * direction.rotate(bullet_spawn_offset);
+            let bullet_linvel =
+                player_rotation * direction.rotate(Vec2::Y * def.bullet_speed) + player_velocity.0;
+
+            // the default hashing algorithm uses the tick and component list. in order to disambiguate
+            // between two players spawning a bullet on the same tick, or the bullets of the same
+            // shot, we add client_id and the index of the bullet to the mix.
+            let prespawned =
+                PreSpawned::default_with_salt(projectile_salt(player.client_id, index));
+
+            let bullet = def.bullet(player.client_id);
+            let bullet_entity = commands
+                .spawn((
+                    Position(bullet_origin),
+                    LinearVelocity(bullet_linvel),
+                    ColorComponent((color.0.to_linear() * 5.0).into()), // bloom !
+                    BulletLifetime {
+                        origin_tick: current_tick,
+                        lifetime: def.lifetime,
+                    },
+                    PhysicsBundle::bullet(bullet.size),
+                    bullet,
+                    prespawned,
+                ))
+                .id();
+            if def.pierce > 0 {
+                commands
+                    .entity(bullet_entity)
+                    .insert((Sensor, PiercedShips::default()));
+            }
+            debug!(
+                pressed=?action.get_pressed(),
+                "spawned bullet for ActionState, bullet={bullet_entity:?} ({}, {}). prev last_fire tick: {prev_last_fire_tick:?}",
+                weapon.last_fire_tick.0, player.client_id
+            );
+
+            if is_server {
+                commands.entity(bullet_entity).insert((
+                    Replicate::to_clients(NetworkTarget::All),
+                    PredictionTarget::to_clients(NetworkTarget::All),
+                ));
+            }
         }
     }
 }
@@ -297,58 +398,77 @@ impl WallBundle {
 // Players can't collide with their own bullets.
 // this is especially helpful if you are accelerating forwards while shooting, as otherwise you
 // might overtake / collide on spawn with your own bullets that spawn in front of you.
+//
+// Piercing bullets go through ships until they hit one more than their `pierce`, see `weapons`.
+//
+// On the server, bullets don't collide with the ships: `lag_compensation` checks those hits.
 pub(crate) fn process_collisions(
     collisions: Collisions,
-    bullet_q: Query<(&BulletMarker, &ColorComponent, &Position)>,
-    player_q: Query<&Player>,
+    mut bullet_q: Query<(
+        &BulletMarker,
+        &ColorComponent,
+        &Position,
+        Option<&mut PiercedShips>,
+    )>,
+    player_q: Query<(&Player, Option<&Team>)>,
+    room: Query<&MatchRoom>,
     mut commands: Commands,
     timeline: Single<(&LocalTimeline, Has<Server>), Without<ClientOf>>,
     mut hit_ev_writer: EventWriter<BulletHitEvent>,
 ) {
     let (timeline, is_server) = timeline.into_inner();
+    // with friendly fire off, hitting a teammate only destroys the bullet
+    let friendly_fire_off = room
+        .single()
+        .is_ok_and(|room| room.friendly_fire == FriendlyFire::Off);
+    let hits_teammate = |bullet: &BulletMarker, victim: Entity| {
+        let shooter_team = player_q
+            .iter()
+            .find(|(player, _)| player.client_id == bullet.owner)
+            .and_then(|(_, team)| team);
+        let victim_team = player_q.get(victim).ok().and_then(|(_, team)| team);
+        is_friendly(shooter_team, victim_team)
+    };
     // when A and B collide, it can be reported as one of:
     // * A collides with B
     // * B collides with A
-    // which is why logic is duplicated twice here
+    // which is why both orders are checked here
     for contacts in collisions.iter() {
-        if let Ok((bullet, col, bullet_pos)) = bullet_q.get(contacts.collider1) {
-            if let Ok(owner) = player_q.get(contacts.collider2) {
-                if bullet.owner == owner.client_id {
-                    // this is our own bullet, don't do anything
-                    continue;
-                }
-            }
-            // despawn the bullet
-            commands.entity(contacts.collider1).prediction_despawn();
-            let victim_client_id = player_q
-                .get(contacts.collider2)
-                .map_or(None, |victim_player| Some(victim_player.client_id));
-
-            let ev = BulletHitEvent {
-                bullet_owner: bullet.owner,
-                victim_client_id,
-                position: bullet_pos.0,
-                bullet_color: col.0,
+        for (bullet_entity, other) in [
+            (contacts.collider1, contacts.collider2),
+            (contacts.collider2, contacts.collider1),
+        ] {
+            let Ok((bullet, col, bullet_pos, pierced)) = bullet_q.get_mut(bullet_entity) else {
+                continue;
             };
-            hit_ev_writer.write(ev);
-        }
-        if let Ok((bullet, col, bullet_pos)) = bullet_q.get(contacts.collider2) {
-            if let Ok(owner) = player_q.get(contacts.collider1) {
-                if bullet.owner == owner.client_id {
-                    // this is our own bullet, don't do anything
-                    continue;
+            let victim = player_q.get(other).ok().map(|(player, _)| player.client_id);
+            if victim == Some(bullet.owner) {
+                // this is our own bullet, don't do anything
+                continue;
+            }
+            match (pierced, victim) {
+                (Some(mut pierced), Some(victim)) => {
+                    if pierced.0.contains(&victim) {
+                        continue;
+                    }
+                    pierced.0.push(victim);
+                    if pierced.0.len() > bullet.pierce as usize {
+                        commands.entity(bullet_entity).prediction_despawn();
+                    }
                 }
+                // despawn the bullet
+                _ => commands.entity(bullet_entity).prediction_despawn(),
+            }
+            if friendly_fire_off && hits_teammate(bullet, other) {
+                continue;
             }
-            commands.entity(contacts.collider2).prediction_despawn();
-            let victim_client_id = player_q
-                .get(contacts.collider1)
-                .map_or(None, |victim_player| Some(victim_player.client_id));
 
             let ev = BulletHitEvent {
                 bullet_owner: bullet.owner,
-                victim_client_id,
+                victim_client_id: victim,
                 position: bullet_pos.0,
                 bullet_color: col.0,
+                damage: bullet.damage,
             };
             hit_ev_writer.write(ev);
         }
@@ -0,0 +1,154 @@
+//! Warn clients before the server stops.
+//!
+//! Instead of stopping right away, the server counts down for a few seconds and sends a
+//! [`ShutdownNotice`] every second. Clients show it as a banner, and keep the reason so the
+//! menu can display it once they have been disconnected.
+use std::time::Duration;
+
+use bevy::prelude::*;
+use lightyear::connection::client_of::ClientOf;
+use lightyear::prelude::*;
+
+use crate::networking::client::LastDisconnectReason;
+use crate::networking::protocol::{ControlChannel, ShutdownNotice};
+use crate::{GameCleanUp, GameState};
+
+/// How long remote clients are warned before the server stops
+pub const SHUTDOWN_NOTICE_DELAY: Duration = Duration::from_secs(5);
+
+/// Inserted on the server when a shutdown has been requested
+#[derive(Resource)]
+pub struct PendingShutdown {
+    pub reason: String,
+    timer: Timer,
+    announced_seconds: Option<u32>,
+}
+
+impl PendingShutdown {
+    pub fn new(reason: impl Into<String>, delay: Duration) -> Self {
+        Self {
+            reason: reason.into(),
+            timer: Timer::new(delay, TimerMode::Once),
+            announced_seconds: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.timer.finished()
+    }
+
+    fn seconds_remaining(&self) -> u32 {
+        self.timer.remaining().as_secs_f32().ceil() as u32
+    }
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShutdownNoticeSet;
+
+pub(crate) struct ShutdownNoticeServerPlugin;
+
+impl Plugin for ShutdownNoticeServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            announce_shutdown
+                .run_if(resource_exists::<PendingShutdown>)
+                .in_set(ShutdownNoticeSet),
+        );
+    }
+}
+
+/// Tick the countdown and send a notice every time the number of seconds left changes
+fn announce_shutdown(
+    time: Res<Time>,
+    mut pending_shutdown: ResMut<PendingShutdown>,
+    mut links: Query<&mut MessageSender<ShutdownNotice>, (With<ClientOf>, With<Connected>)>,
+) {
+    pending_shutdown.timer.tick(time.delta());
+    let seconds_remaining = pending_shutdown.seconds_remaining();
+    if pending_shutdown.announced_seconds == Some(seconds_remaining) {
+        return;
+    }
+    pending_shutdown.announced_seconds = Some(seconds_remaining);
+    info!(
+        "Server shutting down in {seconds_remaining}s: {}",
+        pending_shutdown.reason
+    );
+    for mut sender in links.iter_mut() {
+        sender.send::<ControlChannel>(ShutdownNotice {
+            seconds_remaining,
+            reason: pending_shutdown.reason.clone(),
+        });
+    }
+}
+
+pub(crate) struct ShutdownNoticeClientPlugin;
+
+impl Plugin for ShutdownNoticeClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (receive_shutdown_notice, update_shutdown_banner)
+                .chain()
+                .run_if(in_state(GameState::Lobby).or(in_state(GameState::Game))),
+        );
+    }
+}
+
+/// Banner showing the countdown, counted down locally between two notices
+#[derive(Component)]
+struct ShutdownBanner {
+    reason: String,
+    timer: Timer,
+}
+
+fn receive_shutdown_notice(
+    mut client: Query<&mut MessageReceiver<ShutdownNotice>, With<Client>>,
+    mut banner: Query<&mut ShutdownBanner>,
+    mut last_disconnect_reason: ResMut<LastDisconnectReason>,
+    mut commands: Commands,
+) {
+    for mut receiver in client.iter_mut() {
+        for notice in receiver.receive() {
+            last_disconnect_reason.0 = Some(notice.reason.clone());
+            let timer = Timer::new(
+                Duration::from_secs(notice.seconds_remaining as u64),
+                TimerMode::Once,
+            );
+            if let Ok(mut banner) = banner.single_mut() {
+                banner.reason = notice.reason;
+                banner.timer = timer;
+                continue;
+            }
+            commands.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(20.0),
+                    width: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                Text::new(""),
+                TextFont {
+                    font_size: 26.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(1.0, 0.75, 0.3)),
+                TextLayout::new_with_justify(JustifyText::Center),
+                ShutdownBanner {
+                    reason: notice.reason,
+                    timer,
+                },
+                GameCleanUp,
+            ));
+        }
+    }
+}
+
+fn update_shutdown_banner(time: Res<Time>, mut banner: Query<(&mut Text, &mut ShutdownBanner)>) {
+    for (mut text, mut banner) in banner.iter_mut() {
+        banner.timer.tick(time.delta());
+        let seconds = banner.timer.remaining().as_secs_f32().ceil() as u32;
+        text.0 = format!("Server shutting down in {seconds}s: {}", banner.reason);
+    }
+}
@@ -0,0 +1,319 @@
+//! Soccer game mode.
+//!
+//! Played in teams with a single big ball. Each team defends a [`GoalZone`] on its side of the
+//! arena: when the ball enters it, the other team scores a goal and the ball goes back to the
+//! kick-off spot. The first team to reach the score limit wins the round, bullet hits don't score.
+//! Ships spawn and respawn in the half their team defends.
+//!
+//! Goals are detected by the server only, the ball is predicted like in the other modes, so
+//! clients roll back to the kick-off spot when the server resets it. The goals are counted in a
+//! replicated [`TeamGoals`] on the `MatchRoom` entity, used by the scoreboard and the results.
+//! The player who last touched the ball (with their ship or a bullet) gets a point in `Score` when
+//! it goes in the other team's goal.
+use std::time::Duration;
+
+use avian2d::prelude::{AngularVelocity, Collisions, LinearVelocity, Position};
+use bevy::prelude::*;
+use lightyear::prelude::*;
+
+use crate::networking::chat::ChatAnnouncement;
+use crate::networking::game_mode::{
+    ActiveGameMode, ArenaEntity, GameMode, ResetRound, ScoreChange,
+};
+use crate::networking::host_migration::MigrationSeed;
+use crate::networking::protocol::{
+    BallMarker, BulletHitEvent, BulletMarker, GoalZone, MatchPhase, MatchRoom, MatchState, Player,
+    Score, Team, TeamGoals,
+};
+use crate::networking::server::spawn_ball;
+use crate::networking::shared::{process_collisions, WALL_SIZE};
+use crate::networking::teams::{standings, PlayerScore};
+use crate::{GameCleanUp, GameState};
+
+const BALL_RADIUS: f32 = 18.0;
+const GOAL_HALF_SIZE: Vec2 = Vec2::new(25.0, 70.0);
+const GOAL_BANNER_DURATION: Duration = Duration::from_secs(2);
+
+/// Two teams, one ball, two goals
+pub struct Soccer;
+
+impl Soccer {
+    pub const NAME: &'static str = "Soccer";
+}
+
+impl GameMode for Soccer {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn spawn_arena(&self, commands: &mut Commands) {
+        // red defends the left side, blue the right side
+        for (team, side) in [(Team::Red, -1.0), (Team::Blue, 1.0)] {
+            commands.spawn((
+                GoalZone {
+                    team,
+                    center: Vec2::new(side * (WALL_SIZE - GOAL_HALF_SIZE.x), 0.0),
+                    half_size: GOAL_HALF_SIZE,
+                },
+                Name::new("Goal"),
+                Replicate::to_clients(NetworkTarget::All),
+                ArenaEntity,
+                GameCleanUp,
+            ));
+        }
+    }
+
+    fn spawn_balls(&self, commands: &mut Commands) {
+        spawn_ball(commands, Vec2::ZERO, BALL_RADIUS);
+    }
+
+    fn spawn_position(&self, index: usize, team: Option<Team>) -> Vec2 {
+        // in the half the team defends, away from the ball
+        let side = match team {
+            Some(Team::Red) => -1.0,
+            Some(Team::Blue) => 1.0,
+            None if index % 2 == 0 => -1.0,
+            None => 1.0,
+        };
+        let row = (index % 5) as f32 - 2.0;
+        Vec2::new(side * 150.0, row * 60.0)
+    }
+
+    fn score_hit(&self, _hit: &BulletHitEvent) -> ScoreChange {
+        ScoreChange::default()
+    }
+
+    fn round_winner(
+        &self,
+        players: &[PlayerScore],
+        goals: Option<&TeamGoals>,
+        score_limit: i32,
+    ) -> Option<String> {
+        standings(players, goals)
+            .into_iter()
+            .find(|(_, score)| *score >= score_limit)
+            .map(|(name, _)| name)
+    }
+
+    fn teams(&self) -> bool {
+        true
+    }
+}
+
+impl GoalZone {
+    fn contains(&self, point: Vec2) -> bool {
+        let offset = (point - self.center).abs();
+        offset.x <= self.half_size.x && offset.y <= self.half_size.y
+    }
+}
+
+/// Last player who touched a ball, server only
+#[derive(Component, Debug)]
+struct LastTouch(PeerId);
+
+fn soccer_active(mode: Option<Res<ActiveGameMode>>) -> bool {
+    mode.is_some_and(|mode| mode.0.name() == Soccer::NAME)
+}
+
+pub(crate) struct SoccerServerPlugin;
+
+impl Plugin for SoccerServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(reset_goals);
+        app.add_systems(
+            Update,
+            add_team_goals.run_if(in_state(GameState::Game).and(soccer_active)),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (track_last_touch, score_goals)
+                .chain()
+                .after(process_collisions)
+                .run_if(in_state(GameState::Game).and(soccer_active)),
+        );
+    }
+}
+
+fn add_team_goals(
+    room: Query<Entity, (With<MatchRoom>, Without<TeamGoals>)>,
+    seed: Option<Res<MigrationSeed>>,
+    mut commands: Commands,
+) {
+    if let Ok(room) = room.single() {
+        // after a host migration the match goes on with the goals it had
+        let goals = seed.and_then(|seed| seed.0.team_goals).unwrap_or_default();
+        commands.entity(room).insert(goals);
+    }
+}
+
+fn reset_goals(_trigger: Trigger<ResetRound>, mut goals: Query<&mut TeamGoals>) {
+    for mut goals in goals.iter_mut() {
+        *goals = TeamGoals::default();
+    }
+}
+
+fn track_last_touch(
+    collisions: Collisions,
+    balls: Query<(), With<BallMarker>>,
+    players: Query<&Player>,
+    bullets: Query<&BulletMarker>,
+    mut commands: Commands,
+) {
+    for contacts in collisions.iter() {
+        for (ball, other) in [
+            (contacts.collider1, contacts.collider2),
+            (contacts.collider2, contacts.collider1),
+        ] {
+            if !balls.contains(ball) {
+                continue;
+            }
+            let toucher = players
+                .get(other)
+                .map(|player| player.client_id)
+                .or_else(|_| bullets.get(other).map(|bullet| bullet.owner));
+            if let Ok(toucher) = toucher {
+                commands.entity(ball).insert(LastTouch(toucher));
+            }
+        }
+    }
+}
+
+fn score_goals(
+    match_state: Query<&MatchState>,
+    goal_zones: Query<&GoalZone>,
+    mut team_goals: Query<&mut TeamGoals>,
+    mut balls: Query<
+        (
+            &mut Position,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+            Option<&LastTouch>,
+        ),
+        With<BallMarker>,
+    >,
+    mut players: Query<(&Player, &mut Score, Option<&Team>)>,
+    mut announcements: EventWriter<ChatAnnouncement>,
+) {
+    let live = match_state
+        .single()
+        .is_ok_and(|state| state.phase == MatchPhase::Live);
+    for (mut position, mut velocity, mut angular, last_touch) in balls.iter_mut() {
+        let Some(goal) = goal_zones.iter().find(|goal| goal.contains(position.0)) else {
+            continue;
+        };
+        // back to the kick-off spot, also during warmup
+        position.0 = Vec2::ZERO;
+        velocity.0 = Vec2::ZERO;
+        angular.0 = 0.0;
+
+        let Ok(mut team_goals) = team_goals.single_mut() else {
+            continue;
+        };
+        if !live {
+            continue;
+        }
+        let team = goal.team.opponent();
+        team_goals.add(team);
+
+        // own goals aren't credited to anyone
+        let scorer = last_touch.and_then(|touch| {
+            players.iter_mut().find(|(player, _, player_team)| {
+                player.client_id == touch.0 && *player_team == Some(&team)
+            })
+        });
+        let by = match scorer {
+            Some((player, mut score, _)) => {
+                score.0 += 1;
+                format!(" by {}", player.nickname)
+            }
+            None => String::new(),
+        };
+        announcements.write(ChatAnnouncement(format!(
+            "Goal for the {} team{by}! Red {} - {} Blue",
+            team.label(),
+            team_goals.red,
+            team_goals.blue
+        )));
+    }
+}
+
+pub(crate) struct SoccerClientPlugin;
+
+impl Plugin for SoccerClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), spawn_goal_banner);
+        app.add_systems(
+            Update,
+            (draw_goals, update_goal_banner).run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct GoalBanner;
+
+fn spawn_goal_banner(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Percent(30.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            GameCleanUp,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 48.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                Visibility::Hidden,
+                GoalBanner,
+            ));
+        });
+}
+
+fn draw_goals(mut gizmos: Gizmos, goals: Query<&GoalZone>) {
+    for goal in goals.iter() {
+        gizmos.rect_2d(
+            Isometry2d::from_translation(goal.center),
+            goal.half_size * 2.0,
+            goal.team.color(),
+        );
+    }
+}
+
+/// Flash the name of the team that scored
+fn update_goal_banner(
+    time: Res<Time>,
+    goals: Query<&TeamGoals>,
+    mut previous: Local<Option<TeamGoals>>,
+    mut hide_at: Local<Duration>,
+    banner: Single<(&mut Text, &mut TextColor, &mut Visibility), With<GoalBanner>>,
+) {
+    let (mut text, mut color, mut visibility) = banner.into_inner();
+    if let Ok(goals) = goals.single() {
+        // no banner for the goals scored before we joined
+        if let Some(previous) = previous.as_ref() {
+            if let Some(team) = Team::ALL
+                .into_iter()
+                .find(|team| goals.of(*team) > previous.of(*team))
+            {
+                text.0 = format!("GOAL! {} team", team.label());
+                color.0 = team.color();
+                *hide_at = time.elapsed() + GOAL_BANNER_DURATION;
+            }
+        }
+        *previous = Some(*goals);
+    }
+    visibility.set_if_neq(if time.elapsed() < *hide_at {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    });
+}
@@ -0,0 +1,272 @@
+//! Spectator mode.
+//!
+//! A client joins as a spectator with the "Spectate" toggle of the join screen or `--spectate`.
+//! It says so in its [`SessionInfo`](crate::networking::protocol::SessionInfo), and the server
+//! marks its link with [`Spectator`] instead of spawning a ship.
+//!
+//! Spectators don't control anything, so they get interpolated copies of every entity rather than
+//! predicted ones: the server restricts the `PredictionTarget` of replicated entities to the
+//! players and adds an `InterpolationTarget` for the spectators. The link is marked before the
+//! server starts replicating to it (see `handshake`), so no entity reaches a spectator as predicted.
+//!
+//! In game, spectators move a free camera with WASD or the arrow keys and zoom with the mouse
+//! wheel. Space cycles through the players to follow them, panning goes back to the free camera.
+use avian2d::prelude::{Collider, Position, Rotation};
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use lightyear::prelude::*;
+
+use crate::camera::OuterCamera;
+use crate::networking::protocol::{
+    BallMarker, BulletMarker, ColorComponent, PhysicsBundle, Player,
+};
+use crate::networking::renderer::render_shape;
+use crate::{ClientConfigInfo, GameCleanUp, GameState, TEXT_COLOR};
+
+/// Free camera speed, in world units per second at zoom 1
+const PAN_SPEED: f32 = 500.0;
+const ZOOM_STEP: f32 = 0.1;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+
+/// Added by the server on the link of a client that joined as a spectator
+#[derive(Component, Debug)]
+pub struct Spectator;
+
+/// Spectators currently connected to the server
+#[derive(Resource, Default)]
+struct Spectators(Vec<PeerId>);
+
+impl Spectators {
+    /// Players predict the entity, spectators interpolate it
+    fn targets(&self) -> (PredictionTarget, InterpolationTarget) {
+        (
+            PredictionTarget::to_clients(NetworkTarget::AllExcept(self.0.clone())),
+            InterpolationTarget::to_clients(NetworkTarget::Only(self.0.clone())),
+        )
+    }
+}
+
+pub(crate) struct SpectatorServerPlugin;
+
+impl Plugin for SpectatorServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Spectators>();
+        app.add_observer(add_spectator);
+        app.add_observer(remove_spectator);
+        app.add_observer(target_spectators);
+    }
+}
+
+fn add_spectator(
+    trigger: Trigger<OnAdd, Spectator>,
+    links: Query<&RemoteId>,
+    replicated: Query<Entity, With<PredictionTarget>>,
+    mut spectators: ResMut<Spectators>,
+    mut commands: Commands,
+) {
+    let Ok(remote_id) = links.get(trigger.target()) else {
+        return;
+    };
+    spectators.0.push(remote_id.0);
+    // entities that already exist switch to interpolation for the new spectator
+    for entity in replicated.iter() {
+        commands.entity(entity).insert(spectators.targets());
+    }
+}
+
+fn remove_spectator(
+    trigger: Trigger<OnRemove, Spectator>,
+    links: Query<&RemoteId>,
+    replicated: Query<Entity, With<PredictionTarget>>,
+    mut spectators: ResMut<Spectators>,
+    mut commands: Commands,
+) {
+    let Ok(remote_id) = links.get(trigger.target()) else {
+        return;
+    };
+    spectators.0.retain(|peer| *peer != remote_id.0);
+    // the server may be despawning everything, when it stops
+    for entity in replicated.iter() {
+        commands.entity(entity).try_insert(spectators.targets());
+    }
+}
+
+fn target_spectators(
+    trigger: Trigger<OnAdd, PredictionTarget>,
+    spectators: Res<Spectators>,
+    mut commands: Commands,
+) {
+    if !spectators.0.is_empty() {
+        commands
+            .entity(trigger.target())
+            .insert(spectators.targets());
+    }
+}
+
+/// Where the spectator camera is looking
+#[derive(Resource, Default)]
+struct SpectatorCamera {
+    /// the player being followed, free camera if None
+    following: Option<Entity>,
+}
+
+pub(crate) struct SpectatorClientPlugin;
+
+impl Plugin for SpectatorClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpectatorCamera>();
+        app.add_systems(
+            OnEnter(GameState::Game),
+            spawn_spectator_hint.run_if(spectating),
+        );
+        app.add_systems(OnExit(GameState::Game), reset_camera);
+        app.add_systems(
+            Update,
+            (move_spectator_camera, follow_player)
+                .chain()
+                .run_if(in_state(GameState::Game).and(spectating)),
+        );
+        app.add_systems(
+            PostUpdate,
+            draw_interpolated_entities.after(bevy::transform::TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+fn spectating(client_config: Res<ClientConfigInfo>) -> bool {
+    client_config.spectator
+}
+
+fn spawn_spectator_hint(mut commands: Commands) {
+    commands.spawn((
+        Text::new("Spectating - WASD: move, wheel: zoom, Space: follow next player"),
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        TextColor(TEXT_COLOR.with_alpha(0.7)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            right: Val::Px(12.0),
+            ..default()
+        },
+        GameCleanUp,
+    ));
+}
+
+fn move_spectator_camera(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    players: Query<(Entity, &Player), With<Interpolated>>,
+    mut spectator_camera: ResMut<SpectatorCamera>,
+    camera: Single<(&mut Transform, &mut Projection), With<OuterCamera>>,
+) {
+    let (mut transform, mut projection) = camera.into_inner();
+    let Projection::Orthographic(ortho) = &mut *projection else {
+        return;
+    };
+
+    for event in wheel.read() {
+        ortho.scale = (ortho.scale * (1.0 - event.y * ZOOM_STEP)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    let mut direction = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::KeyW, KeyCode::ArrowUp]) {
+        direction.y += 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyS, KeyCode::ArrowDown]) {
+        direction.y -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyA, KeyCode::ArrowLeft]) {
+        direction.x -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyD, KeyCode::ArrowRight]) {
+        direction.x += 1.0;
+    }
+    if direction != Vec2::ZERO {
+        spectator_camera.following = None;
+        let offset = direction.normalize() * PAN_SPEED * ortho.scale * time.delta_secs();
+        transform.translation += offset.extend(0.0);
+    }
+
+    if keys.just_pressed(KeyCode::Space) {
+        let mut players: Vec<_> = players.iter().collect();
+        players.sort_by(|a, b| a.1.nickname.cmp(&b.1.nickname));
+        let next = match spectator_camera.following {
+            Some(current) => players
+                .iter()
+                .position(|(entity, _)| *entity == current)
+                .map_or(0, |index| index + 1),
+            None => 0,
+        };
+        // after the last player, go back to the free camera
+        spectator_camera.following = players.get(next).map(|(entity, player)| {
+            info!("Following {}", player.nickname);
+            *entity
+        });
+    }
+}
+
+fn follow_player(
+    mut spectator_camera: ResMut<SpectatorCamera>,
+    players: Query<&Position, (With<Player>, With<Interpolated>)>,
+    mut camera: Single<&mut Transform, With<OuterCamera>>,
+) {
+    let Some(following) = spectator_camera.following else {
+        return;
+    };
+    match players.get(following) {
+        Ok(position) => {
+            camera.translation.x = position.x;
+            camera.translation.y = position.y;
+        }
+        // the player left
+        Err(_) => spectator_camera.following = None,
+    }
+}
+
+fn reset_camera(
+    mut spectator_camera: ResMut<SpectatorCamera>,
+    camera: Single<(&mut Transform, &mut Projection), With<OuterCamera>>,
+) {
+    spectator_camera.following = None;
+    let (mut transform, mut projection) = camera.into_inner();
+    *transform = Transform::default();
+    if let Projection::Orthographic(ortho) = &mut *projection {
+        ortho.scale = 1.0;
+    }
+}
+
+/// Interpolated entities have no collider, so draw them from their markers
+fn draw_interpolated_entities(
+    mut gizmos: Gizmos,
+    entities: Query<
+        (
+            &Position,
+            &Rotation,
+            &ColorComponent,
+            Option<&BallMarker>,
+            Option<&BulletMarker>,
+        ),
+        (
+            With<Interpolated>,
+            Or<(With<Player>, With<BallMarker>, With<BulletMarker>)>,
+        ),
+    >,
+) {
+    if entities.is_empty() {
+        return;
+    }
+    let ship = PhysicsBundle::player_ship().collider;
+    for (position, rotation, color, ball, bullet) in entities.iter() {
+        let shape = match (ball, bullet) {
+            (Some(ball), _) => ball.physics_bundle().collider,
+            (None, Some(bullet)) => Collider::circle(bullet.size),
+            (None, None) => ship.clone(),
+        };
+        render_shape(shape.shape(), position, rotation, &mut gizmos, color.0);
+    }
+}
@@ -0,0 +1,544 @@
+//! The Steam calls made by the game (lobbies, friends and invites), behind a trait.
+//!
+//! [`SteamworksBackend`] forwards to the real steamworks client. [`MockSteamBackend`] keeps
+//! everything in memory, so the lobby and invite flows can run without a Steam client:
+//! create a mock, add friends, then call [`MockSteamBackend::request_join`] to fake an accepted invite.
+//!
+//! Like steamworks, results are delivered through callbacks that only run in
+//! [`SteamBackend::run_callbacks`].
+//!
+//! The steam *transport* (`SteamServerIo`, `SteamClientIo`) still needs lightyear's
+//! `SteamworksClient` resource, so it isn't covered by the mock.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use lightyear::prelude::steamworks::{
+    CallbackHandle, Client, FriendFlags, GameLobbyJoinRequested, GameRichPresenceJoinRequested,
+    LobbyId, LobbyType, SingleClient, SteamId,
+};
+use parking_lot::Mutex;
+
+use crate::networking::lobby_join::parse_connect_lobby;
+
+/// Spacewar, the Steam test app
+pub const STEAM_APP_ID: u32 = 480;
+
+/// A friend currently in a lobby of our game
+#[derive(Clone, Debug, PartialEq)]
+pub struct SteamFriend {
+    pub name: String,
+    pub id: SteamId,
+    pub lobby: LobbyId,
+}
+
+pub type LobbyCallback = Box<dyn FnOnce(Result<LobbyId, String>) + Send>;
+/// Receives the lobby to join and the friend who is in it
+pub type JoinRequestedCallback = Box<dyn Fn(LobbyId, SteamId) + Send + Sync>;
+
+pub trait SteamBackend: Send + Sync + 'static {
+    /// Deliver pending callbacks. Must be called every frame.
+    fn run_callbacks(&self);
+
+    fn local_user(&self) -> SteamId;
+
+    /// Friends playing our game in a lobby they can be joined through
+    fn friends_in_game(&self) -> Vec<SteamFriend>;
+
+    fn create_lobby(&self, max_members: u32, callback: LobbyCallback);
+
+    fn join_lobby(&self, lobby: LobbyId, callback: LobbyCallback);
+
+    fn leave_lobby(&self, lobby: LobbyId);
+
+    fn lobby_owner(&self, lobby: LobbyId) -> Option<SteamId>;
+
+    /// Publish a key/value pair on a lobby we own
+    fn set_lobby_data(&self, lobby: LobbyId, key: &str, value: &str);
+
+    /// Ask Steam for the data of a lobby we are not in. It shows up in `lobby_data` a moment later.
+    fn request_lobby_data(&self, lobby: LobbyId);
+
+    fn lobby_data(&self, lobby: LobbyId, key: &str) -> Option<String>;
+
+    /// Set (or clear with `None`) a rich presence key of the local user
+    fn set_rich_presence(&self, key: &str, value: Option<&str>);
+
+    /// Called when the local user accepts an invite, or clicks "Join game" on a friend
+    fn on_join_requested(&self, callback: JoinRequestedCallback);
+}
+
+/// The Steam backend used by the app, inserted when Steam is available
+#[derive(Resource, Clone, Deref)]
+pub struct SteamPlatform(pub Arc<dyn SteamBackend>);
+
+impl SteamPlatform {
+    pub fn new(backend: impl SteamBackend) -> Self {
+        Self(Arc::new(backend))
+    }
+}
+
+pub struct SteamworksBackend {
+    client: Client,
+    single_client: Arc<Mutex<SingleClient>>,
+    /// steamworks unregisters a callback when its handle is dropped
+    callback_handles: Mutex<Vec<CallbackHandle>>,
+}
+
+impl SteamworksBackend {
+    pub fn new(client: Client, single_client: Arc<Mutex<SingleClient>>) -> Self {
+        Self {
+            client,
+            single_client,
+            callback_handles: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl SteamBackend for SteamworksBackend {
+    fn run_callbacks(&self) {
+        self.single_client.lock().run_callbacks();
+    }
+
+    fn local_user(&self) -> SteamId {
+        self.client.user().steam_id()
+    }
+
+    fn friends_in_game(&self) -> Vec<SteamFriend> {
+        self.client
+            .friends()
+            .get_friends(FriendFlags::all())
+            .iter()
+            .filter_map(|friend| {
+                let game_info = friend.game_played()?;
+                (game_info.game.app_id().0 == STEAM_APP_ID && game_info.lobby.raw() != 0).then(
+                    || SteamFriend {
+                        name: friend.name(),
+                        id: friend.id(),
+                        lobby: game_info.lobby,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn create_lobby(&self, max_members: u32, callback: LobbyCallback) {
+        self.client.matchmaking().create_lobby(
+            LobbyType::FriendsOnly,
+            max_members,
+            move |result| callback(result.map_err(|e| e.to_string())),
+        );
+    }
+
+    fn join_lobby(&self, lobby: LobbyId, callback: LobbyCallback) {
+        // steam doesn't say why, it is usually a full lobby or one that was closed
+        self.client.matchmaking().join_lobby(lobby, move |result| {
+            callback(result.map_err(|()| "lobby is full or no longer exists".to_string()))
+        });
+    }
+
+    fn leave_lobby(&self, lobby: LobbyId) {
+        self.client.matchmaking().leave_lobby(lobby);
+    }
+
+    fn lobby_owner(&self, lobby: LobbyId) -> Option<SteamId> {
+        Some(self.client.matchmaking().lobby_owner(lobby)).filter(|owner| owner.raw() != 0)
+    }
+
+    fn set_lobby_data(&self, lobby: LobbyId, key: &str, value: &str) {
+        if !self.client.matchmaking().set_lobby_data(lobby, key, value) {
+            warn!("Could not set lobby data {key}={value} on {lobby:?}");
+        }
+    }
+
+    fn request_lobby_data(&self, lobby: LobbyId) {
+        self.client.matchmaking().request_lobby_data(lobby);
+    }
+
+    fn lobby_data(&self, lobby: LobbyId, key: &str) -> Option<String> {
+        self.client
+            .matchmaking()
+            .lobby_data(lobby, key)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    }
+
+    fn set_rich_presence(&self, key: &str, value: Option<&str>) {
+        if !self.client.friends().set_rich_presence(key, value) {
+            warn!("Could not set rich presence {key}={value:?}");
+        }
+    }
+
+    fn on_join_requested(&self, callback: JoinRequestedCallback) {
+        let callback: Arc<JoinRequestedCallback> = Arc::new(callback);
+        let lobby_callback = callback.clone();
+        let lobby_handle = self
+            .client
+            .register_callback(move |p: GameLobbyJoinRequested| {
+                lobby_callback(p.lobby_steam_id, p.friend_steam_id)
+            });
+        // "Join game" through rich presence sends our connect string instead of a lobby
+        let presence_handle =
+            self.client
+                .register_callback(
+                    move |p: GameRichPresenceJoinRequested| match parse_connect_lobby(&p.connect) {
+                        Some(lobby) => callback(lobby, p.friend_steam_id),
+                        None => warn!("Ignoring join request with connect string {:?}", p.connect),
+                    },
+                );
+        self.callback_handles
+            .lock()
+            .extend([lobby_handle, presence_handle]);
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockLobby {
+    pub owner: SteamId,
+    pub max_members: u32,
+    pub members: Vec<SteamId>,
+    pub data: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct MockSteamState {
+    friends: Vec<SteamFriend>,
+    lobbies: HashMap<LobbyId, MockLobby>,
+    next_lobby: u64,
+    fail_lobby_creation: bool,
+    join_requested: Vec<Arc<JoinRequestedCallback>>,
+    rich_presence: HashMap<String, String>,
+    /// callbacks waiting for the next `run_callbacks`
+    pending: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+/// In-memory Steam, for running the lobby and invite flows without a Steam client
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct MockSteamBackend {
+    local_user: SteamId,
+    state: Mutex<MockSteamState>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MockSteamBackend {
+    pub fn new(local_user: SteamId) -> Self {
+        Self {
+            local_user,
+            state: Mutex::new(MockSteamState {
+                next_lobby: 1,
+                ..default()
+            }),
+        }
+    }
+
+    /// Add a friend hosting `lobby`, with the given lobby data
+    pub fn add_friend(
+        &self,
+        name: impl Into<String>,
+        id: SteamId,
+        lobby: LobbyId,
+        data: &[(&str, &str)],
+    ) {
+        let mut state = self.state.lock();
+        state.friends.push(SteamFriend {
+            name: name.into(),
+            id,
+            lobby,
+        });
+        state.lobbies.insert(
+            lobby,
+            MockLobby {
+                owner: id,
+                max_members: 10,
+                members: vec![id],
+                data: data
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            },
+        );
+    }
+
+    /// Make the next `create_lobby` calls fail
+    pub fn set_lobby_creation_fails(&self, fails: bool) {
+        self.state.lock().fail_lobby_creation = fails;
+    }
+
+    /// Resize a lobby, e.g. to fill it
+    pub fn set_max_members(&self, lobby: LobbyId, max_members: u32) {
+        if let Some(mock_lobby) = self.state.lock().lobbies.get_mut(&lobby) {
+            mock_lobby.max_members = max_members;
+        }
+    }
+
+    pub fn lobby(&self, lobby: LobbyId) -> Option<MockLobby> {
+        self.state.lock().lobbies.get(&lobby).cloned()
+    }
+
+    pub fn lobbies(&self) -> Vec<LobbyId> {
+        self.state.lock().lobbies.keys().copied().collect()
+    }
+
+    pub fn rich_presence(&self, key: &str) -> Option<String> {
+        self.state.lock().rich_presence.get(key).cloned()
+    }
+
+    /// Fake the local user accepting an invite from `friend`, to the lobby they are in
+    pub fn request_join(&self, friend: SteamId) {
+        let mut state = self.state.lock();
+        let Some(lobby) = state
+            .friends
+            .iter()
+            .find(|f| f.id == friend)
+            .map(|f| f.lobby)
+        else {
+            return;
+        };
+        for callback in state.join_requested.clone() {
+            state
+                .pending
+                .push(Box::new(move || callback(lobby, friend)));
+        }
+    }
+}
+
+impl SteamBackend for MockSteamBackend {
+    fn run_callbacks(&self) {
+        // take the callbacks first: they may call back into the backend
+        let pending = std::mem::take(&mut self.state.lock().pending);
+        for callback in pending {
+            callback();
+        }
+    }
+
+    fn local_user(&self) -> SteamId {
+        self.local_user
+    }
+
+    fn friends_in_game(&self) -> Vec<SteamFriend> {
+        self.state.lock().friends.clone()
+    }
+
+    fn create_lobby(&self, max_members: u32, callback: LobbyCallback) {
+        let mut state = self.state.lock();
+        let result = if state.fail_lobby_creation {
+            Err("mock lobby creation failure".to_string())
+        } else {
+            let lobby = LobbyId::from_raw(state.next_lobby);
+            state.next_lobby += 1;
+            state.lobbies.insert(
+                lobby,
+                MockLobby {
+                    owner: self.local_user,
+                    max_members,
+                    members: vec![self.local_user],
+                    data: HashMap::new(),
+                },
+            );
+            Ok(lobby)
+        };
+        state.pending.push(Box::new(move || callback(result)));
+    }
+
+    fn join_lobby(&self, lobby: LobbyId, callback: LobbyCallback) {
+        let mut state = self.state.lock();
+        let result = match state.lobbies.get_mut(&lobby) {
+            None => Err("lobby no longer exists".to_string()),
+            Some(mock_lobby) if mock_lobby.members.len() as u32 >= mock_lobby.max_members => {
+                Err("lobby is full".to_string())
+            }
+            Some(mock_lobby) => {
+                if !mock_lobby.members.contains(&self.local_user) {
+                    mock_lobby.members.push(self.local_user);
+                }
+                Ok(lobby)
+            }
+        };
+        state.pending.push(Box::new(move || callback(result)));
+    }
+
+    fn leave_lobby(&self, lobby: LobbyId) {
+        let mut state = self.state.lock();
+        let Some(mock_lobby) = state.lobbies.get_mut(&lobby) else {
+            return;
+        };
+        mock_lobby
+            .members
+            .retain(|member| *member != self.local_user);
+        // like steam, a lobby is destroyed once everyone has left
+        if mock_lobby.members.is_empty() {
+            state.lobbies.remove(&lobby);
+        }
+    }
+
+    fn lobby_owner(&self, lobby: LobbyId) -> Option<SteamId> {
+        self.state
+            .lock()
+            .lobbies
+            .get(&lobby)
+            .map(|lobby| lobby.owner)
+    }
+
+    fn set_lobby_data(&self, lobby: LobbyId, key: &str, value: &str) {
+        let mut state = self.state.lock();
+        if let Some(mock_lobby) = state
+            .lobbies
+            .get_mut(&lobby)
+            .filter(|mock_lobby| mock_lobby.owner == self.local_user)
+        {
+            mock_lobby.data.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    fn request_lobby_data(&self, _lobby: LobbyId) {
+        // everything is already local
+    }
+
+    fn lobby_data(&self, lobby: LobbyId, key: &str) -> Option<String> {
+        self.state
+            .lock()
+            .lobbies
+            .get(&lobby)?
+            .data
+            .get(key)
+            .cloned()
+    }
+
+    fn set_rich_presence(&self, key: &str, value: Option<&str>) {
+        let mut state = self.state.lock();
+        match value {
+            Some(value) => state
+                .rich_presence
+                .insert(key.to_string(), value.to_string()),
+            None => state.rich_presence.remove(key),
+        };
+    }
+
+    fn on_join_requested(&self, callback: JoinRequestedCallback) {
+        self.state.lock().join_requested.push(Arc::new(callback));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn me() -> SteamId {
+        SteamId::from_raw(100)
+    }
+
+    fn friend() -> SteamId {
+        SteamId::from_raw(200)
+    }
+
+    fn friend_lobby() -> LobbyId {
+        LobbyId::from_raw(7)
+    }
+
+    type LobbyResult = Arc<Mutex<Option<Result<LobbyId, String>>>>;
+
+    /// A lobby callback that stores its result
+    fn capture() -> (LobbyResult, LobbyCallback) {
+        let result: LobbyResult = Arc::default();
+        let stored = result.clone();
+        (
+            result,
+            Box::new(move |lobby| {
+                stored.lock().replace(lobby);
+            }),
+        )
+    }
+
+    #[test]
+    fn create_lobby() {
+        let steam = MockSteamBackend::new(me());
+        let (result, callback) = capture();
+        steam.create_lobby(4, callback);
+        assert_eq!(*result.lock(), None, "callbacks wait for run_callbacks");
+
+        steam.run_callbacks();
+        let lobby = result.lock().clone().unwrap().unwrap();
+        assert_eq!(
+            steam.lobby(lobby),
+            Some(MockLobby {
+                owner: me(),
+                max_members: 4,
+                members: vec![me()],
+                data: HashMap::new(),
+            })
+        );
+        assert_eq!(steam.lobby_owner(lobby), Some(me()));
+
+        steam.leave_lobby(lobby);
+        assert!(steam.lobbies().is_empty());
+    }
+
+    #[test]
+    fn create_lobby_failure() {
+        let steam = MockSteamBackend::new(me());
+        steam.set_lobby_creation_fails(true);
+        let (result, callback) = capture();
+        steam.create_lobby(4, callback);
+        steam.run_callbacks();
+        assert!(result.lock().clone().unwrap().is_err());
+        assert!(steam.lobbies().is_empty());
+    }
+
+    #[test]
+    fn join_lobby() {
+        let steam = MockSteamBackend::new(me());
+        steam.add_friend("Friend", friend(), friend_lobby(), &[]);
+        let (result, callback) = capture();
+        steam.join_lobby(friend_lobby(), callback);
+        steam.run_callbacks();
+        assert_eq!(*result.lock(), Some(Ok(friend_lobby())));
+        assert_eq!(
+            steam.lobby(friend_lobby()).unwrap().members,
+            vec![friend(), me()]
+        );
+    }
+
+    #[test]
+    fn join_full_lobby() {
+        let steam = MockSteamBackend::new(me());
+        steam.add_friend("Friend", friend(), friend_lobby(), &[]);
+        steam.set_max_members(friend_lobby(), 1);
+        let (result, callback) = capture();
+        steam.join_lobby(friend_lobby(), callback);
+        steam.run_callbacks();
+        assert_eq!(*result.lock(), Some(Err("lobby is full".to_string())));
+        assert_eq!(steam.lobby(friend_lobby()).unwrap().members, vec![friend()]);
+    }
+
+    #[test]
+    fn join_missing_lobby() {
+        let steam = MockSteamBackend::new(me());
+        let (result, callback) = capture();
+        steam.join_lobby(friend_lobby(), callback);
+        steam.run_callbacks();
+        assert_eq!(
+            *result.lock(),
+            Some(Err("lobby no longer exists".to_string()))
+        );
+    }
+
+    #[test]
+    fn request_join_calls_the_join_callback() {
+        let steam = MockSteamBackend::new(me());
+        steam.add_friend("Friend", friend(), friend_lobby(), &[]);
+        let requested = Arc::new(Mutex::new(None));
+        let stored = requested.clone();
+        steam.on_join_requested(Box::new(move |lobby, friend| {
+            stored.lock().replace((lobby, friend));
+        }));
+
+        // not a friend: nothing to join
+        steam.request_join(SteamId::from_raw(300));
+        steam.request_join(friend());
+        assert_eq!(*requested.lock(), None, "callbacks wait for run_callbacks");
+
+        steam.run_callbacks();
+        assert_eq!(*requested.lock(), Some((friend_lobby(), friend())));
+    }
+}
@@ -0,0 +1,227 @@
+//! Teams.
+//!
+//! The host turns teams on in the lobby (`MatchRoom::teams`). Every ship then gets a replicated
+//! [`Team`] and its color instead of the per-player palette color. New players join the smaller
+//! team in `handle_connections`, and the teams are balanced again when the setting changes and
+//! between rounds, so they never differ by more than one player.
+//!
+//! `MatchRoom::friendly_fire` decides what a bullet hitting a teammate does: `process_collisions`
+//! destroys the bullet without a hit when it is off, `handle_hit_event` costs the shooter a point
+//! with `Penalty`.
+//!
+//! With teams on, the scoreboard, the results and the win conditions use the sum of the scores
+//! of each team.
+use bevy::color::palettes::css;
+use bevy::prelude::*;
+
+use crate::networking::game_mode::ResetRound;
+use crate::networking::protocol::{
+    ColorComponent, FriendlyFire, MatchRoom, Player, RosterEntry, Team, TeamGoals,
+};
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Red, Team::Blue];
+
+    pub fn color(&self) -> Color {
+        match self {
+            Team::Red => css::TOMATO.into(),
+            Team::Blue => css::DODGER_BLUE.into(),
+        }
+    }
+
+    pub fn opponent(&self) -> Team {
+        match self {
+            Team::Red => Team::Blue,
+            Team::Blue => Team::Red,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Team::Red => "Red",
+            Team::Blue => "Blue",
+        }
+    }
+}
+
+impl FriendlyFire {
+    pub const ALL: [FriendlyFire; 3] =
+        [FriendlyFire::Off, FriendlyFire::Penalty, FriendlyFire::Full];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FriendlyFire::Off => "off",
+            FriendlyFire::Penalty => "penalty",
+            FriendlyFire::Full => "full",
+        }
+    }
+}
+
+/// Whether the shooter and the victim are on the same team
+pub fn is_friendly(shooter: Option<&Team>, victim: Option<&Team>) -> bool {
+    matches!((shooter, victim), (Some(shooter), Some(victim)) if shooter == victim)
+}
+
+/// The team with the fewest players, Red on ties
+pub fn smaller_team<'a>(teams: impl Iterator<Item = &'a Team>) -> Team {
+    let blue_minus_red = teams.fold(0, |diff, team| match team {
+        Team::Red => diff - 1,
+        Team::Blue => diff + 1,
+    });
+    if blue_minus_red < 0 {
+        Team::Blue
+    } else {
+        Team::Red
+    }
+}
+
+/// Score of a player, as seen by the game mode and the results
+pub struct PlayerScore<'a> {
+    pub nickname: &'a str,
+    pub score: i32,
+    pub team: Option<Team>,
+}
+
+impl<'a> From<&'a RosterEntry> for PlayerScore<'a> {
+    fn from(entry: &'a RosterEntry) -> Self {
+        Self {
+            nickname: &entry.nickname,
+            score: entry.score,
+            team: entry.team,
+        }
+    }
+}
+
+impl TeamGoals {
+    pub fn of(&self, team: Team) -> i32 {
+        match team {
+            Team::Red => self.red,
+            Team::Blue => self.blue,
+        }
+    }
+
+    pub fn add(&mut self, team: Team) {
+        match team {
+            Team::Red => self.red += 1,
+            Team::Blue => self.blue += 1,
+        }
+    }
+}
+
+/// Names and scores, best first: one row per team when teams are on, per player otherwise.
+/// When the mode counts [`TeamGoals`], the team rows show the goals instead of the sum of the
+/// player scores.
+pub fn standings(players: &[PlayerScore], goals: Option<&TeamGoals>) -> Vec<(String, i32)> {
+    let mut rows: Vec<_> = if players.iter().any(|player| player.team.is_some()) {
+        Team::ALL
+            .iter()
+            .map(|team| {
+                let score = match goals {
+                    Some(goals) => goals.of(*team),
+                    None => players
+                        .iter()
+                        .filter(|player| player.team == Some(*team))
+                        .map(|player| player.score)
+                        .sum(),
+                };
+                (format!("{} team", team.label()), score)
+            })
+            .collect()
+    } else {
+        players
+            .iter()
+            .map(|player| (player.nickname.to_string(), player.score))
+            .collect()
+    };
+    rows.sort_by(|a, b| b.1.cmp(&a.1));
+    rows
+}
+
+/// Color of the ship when teams are off, kept by the server to restore it
+#[derive(Component, Debug)]
+pub struct PaletteColor(pub Color);
+
+pub(crate) struct TeamsServerPlugin;
+
+impl Plugin for TeamsServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, apply_team_setting);
+        app.add_observer(balance_between_rounds);
+    }
+}
+
+fn apply_team_setting(
+    room: Query<&MatchRoom, Changed<MatchRoom>>,
+    players: Query<(Entity, Option<&Team>, &PaletteColor), With<Player>>,
+    mut commands: Commands,
+) {
+    let Ok(room) = room.single() else {
+        return;
+    };
+    if room.teams {
+        balance_teams(&players, &mut commands);
+    } else {
+        for (entity, team, palette) in players.iter() {
+            if team.is_some() {
+                commands
+                    .entity(entity)
+                    .remove::<Team>()
+                    .insert(ColorComponent(palette.0));
+            }
+        }
+    }
+}
+
+fn balance_between_rounds(
+    _trigger: Trigger<ResetRound>,
+    room: Single<&MatchRoom>,
+    players: Query<(Entity, Option<&Team>, &PaletteColor), With<Player>>,
+    mut commands: Commands,
+) {
+    if room.teams {
+        balance_teams(&players, &mut commands);
+    }
+}
+
+/// Put players without a team in the smaller one, then move players until the teams differ by
+/// at most one
+fn balance_teams(
+    players: &Query<(Entity, Option<&Team>, &PaletteColor), With<Player>>,
+    commands: &mut Commands,
+) {
+    let mut teams: Vec<(Entity, Option<Team>)> = players
+        .iter()
+        .map(|(entity, team, _)| (entity, team.copied()))
+        .collect();
+    let original = teams.clone();
+
+    for index in 0..teams.len() {
+        if teams[index].1.is_none() {
+            let team = smaller_team(teams.iter().filter_map(|(_, team)| team.as_ref()));
+            teams[index].1 = Some(team);
+        }
+    }
+    loop {
+        let count = |team| teams.iter().filter(|(_, t)| *t == Some(team)).count();
+        let (bigger, smaller) = if count(Team::Red) > count(Team::Blue) {
+            (Team::Red, Team::Blue)
+        } else {
+            (Team::Blue, Team::Red)
+        };
+        if count(bigger) - count(smaller) <= 1 {
+            break;
+        }
+        // move the last player who joined the bigger team
+        if let Some(moved) = teams.iter_mut().rev().find(|(_, t)| *t == Some(bigger)) {
+            moved.1 = Some(smaller);
+        }
+    }
+
+    for ((entity, team), (_, previous)) in teams.into_iter().zip(original) {
+        if let Some(team) = team.filter(|team| previous != Some(*team)) {
+            commands
+                .entity(entity)
+                .insert((team, ColorComponent(team.color())));
+        }
+    }
+}
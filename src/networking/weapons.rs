@@ -0,0 +1,198 @@
+//! Weapon definitions.
+//!
+//! Each [`WeaponKind`] has a [`WeaponDef`] in [`WEAPONS`]: how often it fires, how many
+//! projectiles it fires at once and how they spread, and the speed, lifetime, size and damage of
+//! each projectile. `shared_player_firing` reads the definition of the ship's `Weapon`, and every
+//! projectile it spawns carries its damage, size and pierce in its `BulletMarker`.
+//!
+//! Piercing projectiles are sensors: they fly through ships (up to `pierce` of them) instead of
+//! bouncing off, and remember which ships they already hit in [`PiercedShips`].
+//!
+//! Press Q in game to switch to the next weapon.
+use bevy::prelude::*;
+use lightyear::prelude::*;
+
+use crate::networking::protocol::{BulletMarker, Player, Weapon, WeaponKind, BULLET_SIZE};
+use crate::networking::shared::FIXED_TIMESTEP_HZ;
+use crate::{GameCleanUp, GameState, TEXT_COLOR};
+
+/// Stats of a weapon and of its projectiles
+#[derive(Clone, Debug)]
+pub struct WeaponDef {
+    pub kind: WeaponKind,
+    pub name: &'static str,
+    /// ticks between two shots
+    pub cooldown: u16,
+    pub bullet_speed: f32,
+    pub damage: u16,
+    /// angle between the first and the last projectile of a shot, in radians
+    pub spread: f32,
+    pub projectiles: u8,
+    /// in ticks
+    pub lifetime: i16,
+    pub size: f32,
+    /// ships a projectile passes through before being destroyed
+    pub pierce: u8,
+}
+
+const TICKS_PER_SECOND: i16 = FIXED_TIMESTEP_HZ as i16;
+
+pub static WEAPONS: [WeaponDef; 5] = [
+    WeaponDef {
+        kind: WeaponKind::Blaster,
+        name: "Blaster",
+        cooldown: (FIXED_TIMESTEP_HZ / 5.0) as u16,
+        bullet_speed: 500.0,
+        damage: 20,
+        spread: 0.0,
+        projectiles: 1,
+        lifetime: TICKS_PER_SECOND * 2,
+        size: BULLET_SIZE,
+        pierce: 0,
+    },
+    WeaponDef {
+        kind: WeaponKind::Spread,
+        name: "Spread shot",
+        cooldown: (FIXED_TIMESTEP_HZ / 2.5) as u16,
+        bullet_speed: 450.0,
+        damage: 10,
+        spread: 0.6,
+        projectiles: 5,
+        lifetime: TICKS_PER_SECOND * 3 / 4,
+        size: BULLET_SIZE,
+        pierce: 0,
+    },
+    WeaponDef {
+        kind: WeaponKind::RapidFire,
+        name: "Rapid fire",
+        cooldown: (FIXED_TIMESTEP_HZ / 16.0) as u16,
+        bullet_speed: 600.0,
+        damage: 6,
+        spread: 0.0,
+        projectiles: 1,
+        lifetime: TICKS_PER_SECOND * 3 / 2,
+        size: 1.0,
+        pierce: 0,
+    },
+    WeaponDef {
+        kind: WeaponKind::HeavyShell,
+        name: "Heavy shell",
+        cooldown: FIXED_TIMESTEP_HZ as u16,
+        bullet_speed: 250.0,
+        damage: 60,
+        spread: 0.0,
+        projectiles: 1,
+        lifetime: TICKS_PER_SECOND * 3,
+        size: 4.0,
+        pierce: 0,
+    },
+    WeaponDef {
+        kind: WeaponKind::Piercing,
+        name: "Piercing rounds",
+        cooldown: (FIXED_TIMESTEP_HZ / 3.0) as u16,
+        bullet_speed: 700.0,
+        damage: 15,
+        spread: 0.0,
+        projectiles: 1,
+        lifetime: TICKS_PER_SECOND * 3 / 2,
+        size: 1.2,
+        pierce: 2,
+    },
+];
+
+impl WeaponKind {
+    pub fn def(&self) -> &'static WeaponDef {
+        WEAPONS
+            .iter()
+            .find(|def| def.kind == *self)
+            .expect("every weapon kind has a definition")
+    }
+
+    pub fn next(&self) -> WeaponKind {
+        let index = WEAPONS
+            .iter()
+            .position(|def| def.kind == *self)
+            .map_or(0, |index| (index + 1) % WEAPONS.len());
+        WEAPONS[index].kind
+    }
+}
+
+impl Weapon {
+    pub(crate) fn def(&self) -> &'static WeaponDef {
+        self.kind.def()
+    }
+}
+
+impl WeaponDef {
+    /// Angle of the `index`th projectile of a shot, relative to the nose of the ship
+    pub fn projectile_angle(&self, index: u8) -> f32 {
+        if self.projectiles <= 1 {
+            return 0.0;
+        }
+        -self.spread / 2.0 + self.spread * index as f32 / (self.projectiles - 1) as f32
+    }
+
+    pub fn bullet(&self, owner: PeerId) -> BulletMarker {
+        BulletMarker {
+            owner,
+            damage: self.damage,
+            size: self.size,
+            pierce: self.pierce,
+        }
+    }
+}
+
+/// Salt of the `PreSpawned` hash of a projectile. The default hash uses the tick and the
+/// components, so the owner and the index tell apart the projectiles of different players and
+/// of the same shot. The index is xored into the top byte, so the salts of one owner never
+/// collide.
+pub fn projectile_salt(owner: PeerId, index: u8) -> u64 {
+    owner.to_bits() ^ ((index as u64) << 56)
+}
+
+/// Ships a piercing projectile already went through
+#[derive(Component, Default, Debug)]
+pub struct PiercedShips(pub Vec<PeerId>);
+
+pub(crate) struct WeaponsClientPlugin;
+
+impl Plugin for WeaponsClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), spawn_weapon_text);
+        app.add_systems(Update, update_weapon_text.run_if(in_state(GameState::Game)));
+    }
+}
+
+#[derive(Component)]
+struct WeaponText;
+
+fn spawn_weapon_text(mut commands: Commands) {
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        TextColor(TEXT_COLOR),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            right: Val::Px(12.0),
+            ..default()
+        },
+        WeaponText,
+        GameCleanUp,
+    ));
+}
+
+fn update_weapon_text(
+    weapon: Query<&Weapon, (With<Player>, With<Controlled>, With<Predicted>)>,
+    mut text: Single<&mut Text, With<WeaponText>>,
+) {
+    let label = weapon.single().map_or(String::new(), |weapon| {
+        format!("{} (Q to switch)", weapon.def().name)
+    });
+    if text.0 != label {
+        text.0 = label;
+    }
+}